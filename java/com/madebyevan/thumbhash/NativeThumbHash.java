@@ -0,0 +1,49 @@
+package com.madebyevan.thumbhash;
+
+import java.nio.ByteBuffer;
+
+/**
+ * A wrapper around the Rust implementation, which must be built with the "jni"
+ * feature and be on the library path as "thumbhash".
+ */
+public final class NativeThumbHash {
+    static {
+        System.loadLibrary("thumbhash");
+    }
+
+    /**
+     * Encodes an RGBA image to a ThumbHash. RGB should not be premultiplied by A.
+     *
+     * @param w    The width of the input image. Must be ≤100px.
+     * @param h    The height of the input image. Must be ≤100px.
+     * @param rgba The pixels in the input image, row-by-row. Must be a direct buffer with w*h*4 bytes.
+     * @return The ThumbHash as a byte array.
+     */
+    public static native byte[] rgbaToThumbHash(int w, int h, ByteBuffer rgba);
+
+    /**
+     * Decodes a ThumbHash to an ARGB image. RGB is not premultiplied by A.
+     *
+     * @param hash The bytes of the ThumbHash.
+     * @return The width, height, and pixels of the rendered placeholder image.
+     */
+    public static Image thumbHashToARGB(byte[] hash) {
+        int[] size = new int[2];
+        int[] argb = thumbHashToARGB(hash, size);
+        return new Image(size[0], size[1], argb);
+    }
+
+    private static native int[] thumbHashToARGB(byte[] hash, int[] size);
+
+    public static final class Image {
+        public int width;
+        public int height;
+        public int[] argb;
+
+        public Image(int width, int height, int[] argb) {
+            this.width = width;
+            this.height = height;
+            this.argb = argb;
+        }
+    }
+}
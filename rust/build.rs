@@ -0,0 +1,5 @@
+fn main() {
+    // The Node.js addon needs extra linker flags on some platforms
+    #[cfg(feature = "node")]
+    napi_build::setup();
+}
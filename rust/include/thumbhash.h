@@ -0,0 +1,194 @@
+#ifndef THUMBHASH_H
+#define THUMBHASH_H
+
+/* This file is generated by cbindgen. Do not edit it by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * The maximum number of bytes in a hash from `thumbhash_encode`.
+ */
+#define THUMBHASH_MAX_HASH_LEN 25
+
+/**
+ * The maximum number of bytes in an image from `thumbhash_decode`.
+ */
+#define THUMBHASH_MAX_RGBA_LEN ((32 * 32) * 4)
+
+/**
+ * The result of a call to this API.
+ */
+typedef enum ThumbHashStatus {
+  /**
+   * The call succeeded.
+   */
+  THUMB_HASH_STATUS_OK = 0,
+  /**
+   * A pointer was null or a size was out of range.
+   */
+  THUMB_HASH_STATUS_INVALID_ARGUMENT = 1,
+  /**
+   * The hash couldn't be decoded.
+   */
+  THUMB_HASH_STATUS_INVALID_HASH = 2,
+  /**
+   * The output buffer was too small. The sizes that were written say how
+   * big it needs to be.
+   */
+  THUMB_HASH_STATUS_BUFFER_TOO_SMALL = 3,
+} ThumbHashStatus;
+
+/**
+ * Bytes that were allocated by this API, such as an encoded hash or decoded
+ * image. This is returned by pointer so it can be released with a single call
+ * to `thumbhash_buffer_free`, which makes it easy to attach to a finalizer in
+ * languages with garbage collection (e.g. a `NativeFinalizer` in `dart:ffi`).
+ */
+typedef struct ThumbHashBuffer {
+  /**
+   * The bytes in the buffer.
+   */
+  uint8_t *data;
+  /**
+   * The number of bytes in the buffer.
+   */
+  size_t len;
+  /**
+   * The width of the image, or 0 for a hash.
+   */
+  size_t w;
+  /**
+   * The height of the image, or 0 for a hash.
+   */
+  size_t h;
+} ThumbHashBuffer;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Encodes an RGBA image to a ThumbHash. RGB should not be premultiplied by A.
+ *
+ * The image must be at most 100x100 and `rgba_len` must be `w*h*4`. The hash
+ * is written to `hash` and its length to `hash_len`. A buffer of
+ * `THUMBHASH_MAX_HASH_LEN` bytes is always big enough.
+ *
+ * # Safety
+ *
+ * `rgba` must point to `rgba_len` readable bytes, `hash` must point to
+ * `hash_capacity` writable bytes, and `hash_len` must be writable.
+ */
+enum ThumbHashStatus thumbhash_encode(size_t w,
+                                      size_t h,
+                                      const uint8_t *rgba,
+                                      size_t rgba_len,
+                                      uint8_t *hash,
+                                      size_t hash_capacity,
+                                      size_t *hash_len);
+
+/**
+ * Returns the width and height of the image that `thumbhash_decode` produces,
+ * without decoding any pixels.
+ *
+ * # Safety
+ *
+ * `hash` must point to `hash_len` readable bytes, and `w` and `h` must be
+ * writable.
+ */
+enum ThumbHashStatus thumbhash_decoded_size(const uint8_t *hash,
+                                            size_t hash_len,
+                                            size_t *w,
+                                            size_t *h);
+
+/**
+ * Decodes a ThumbHash to an RGBA image. RGB is not premultiplied by A.
+ *
+ * The pixels are written to `rgba` row-by-row, and the width and height to
+ * `w` and `h`. A buffer of `THUMBHASH_MAX_RGBA_LEN` bytes is always big
+ * enough.
+ *
+ * # Safety
+ *
+ * `hash` must point to `hash_len` readable bytes, `rgba` must point to
+ * `rgba_capacity` writable bytes, and `w` and `h` must be writable.
+ */
+enum ThumbHashStatus thumbhash_decode(const uint8_t *hash,
+                                      size_t hash_len,
+                                      uint8_t *rgba,
+                                      size_t rgba_capacity,
+                                      size_t *w,
+                                      size_t *h);
+
+/**
+ * Extracts the average color from a ThumbHash. RGB is not premultiplied by
+ * A. Each value ranges from 0 to 1.
+ *
+ * # Safety
+ *
+ * `hash` must point to `hash_len` readable bytes, and `r`, `g`, `b`, and `a`
+ * must be writable.
+ */
+enum ThumbHashStatus thumbhash_average_rgba(const uint8_t *hash,
+                                            size_t hash_len,
+                                            float *r,
+                                            float *g,
+                                            float *b,
+                                            float *a);
+
+/**
+ * Extracts the approximate aspect ratio (width / height) of the original
+ * image.
+ *
+ * # Safety
+ *
+ * `hash` must point to `hash_len` readable bytes, and `ratio` must be
+ * writable.
+ */
+enum ThumbHashStatus thumbhash_approximate_aspect_ratio(const uint8_t *hash,
+                                                        size_t hash_len,
+                                                        float *ratio);
+
+/**
+ * Like `thumbhash_encode` but returns the hash in a new buffer, or null if
+ * the arguments are invalid. The buffer must be freed with
+ * `thumbhash_buffer_free`.
+ *
+ * # Safety
+ *
+ * `rgba` must point to `rgba_len` readable bytes.
+ */
+struct ThumbHashBuffer *thumbhash_encode_to_buffer(size_t w,
+                                                   size_t h,
+                                                   const uint8_t *rgba,
+                                                   size_t rgba_len);
+
+/**
+ * Like `thumbhash_decode` but returns the image in a new buffer, or null if
+ * the hash couldn't be decoded. The buffer must be freed with
+ * `thumbhash_buffer_free`.
+ *
+ * # Safety
+ *
+ * `hash` must point to `hash_len` readable bytes.
+ */
+struct ThumbHashBuffer *thumbhash_decode_to_buffer(const uint8_t *hash, size_t hash_len);
+
+/**
+ * Frees a buffer returned by this API. Passing null does nothing.
+ *
+ * # Safety
+ *
+ * `buffer` must be null or a buffer from this API that hasn't been freed.
+ */
+void thumbhash_buffer_free(struct ThumbHashBuffer *buffer);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* THUMBHASH_H */
@@ -0,0 +1,40 @@
+// The standard base64 alphabet, which is what ThumbHashes are usually stored
+// as when they appear in text.
+const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes bytes as base64 with padding.
+#[cfg(any(feature = "async-graphql", feature = "metadata"))]
+pub(crate) fn encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Decodes base64 with or without padding.
+pub(crate) fn decode(text: &str) -> Result<Vec<u8>, ()> {
+    let text = text.trim_end_matches('=').as_bytes();
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    for chunk in text.chunks(4) {
+        if chunk.len() == 1 {
+            return Err(());
+        }
+        let mut n = 0;
+        for (i, c) in chunk.iter().enumerate() {
+            let digit = ALPHABET.iter().position(|a| a == c).ok_or(())?;
+            n |= (digit as u32) << (18 - 6 * i);
+        }
+        bytes.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    Ok(bytes)
+}
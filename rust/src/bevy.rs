@@ -0,0 +1,34 @@
+use crate::{base64, thumb_hash_to_rgba};
+use bevy_asset::{Assets, Handle, RenderAssetUsages};
+use bevy_image::Image;
+use wgpu_types::{Extent3d, TextureDimension, TextureFormat};
+
+/// Decodes a ThumbHash to a Bevy image. RGB is not premultiplied by A. Only
+/// available with the `bevy` feature.
+///
+/// An error will be returned if the input is too short.
+pub fn thumb_hash_to_bevy_image(hash: &[u8]) -> Result<Image, ()> {
+    let (w, h, rgba) = thumb_hash_to_rgba(hash)?;
+    let size = Extent3d {
+        width: w as u32,
+        height: h as u32,
+        depth_or_array_layers: 1,
+    };
+    Ok(Image::new(
+        size,
+        TextureDimension::D2,
+        rgba,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    ))
+}
+
+/// Decodes a ThumbHash stored as base64 and adds it to `images`, returning a
+/// handle that can be shown until the real texture has loaded. Only available
+/// with the `bevy` feature.
+///
+/// An error will be returned if the input isn't valid base64 or is too short.
+pub fn add_thumb_hash_image(images: &mut Assets<Image>, hash: &str) -> Result<Handle<Image>, ()> {
+    let hash = base64::decode(hash.trim())?;
+    Ok(images.add(thumb_hash_to_bevy_image(&hash)?))
+}
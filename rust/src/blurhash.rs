@@ -0,0 +1,220 @@
+use crate::{rgba_to_thumb_hash, Channels};
+use std::f32::consts::PI;
+
+// The base83 alphabet that BlurHash uses for its digits.
+const ALPHABET: &[u8] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// Converts a BlurHash to a ThumbHash, for migrating stored placeholders from
+/// BlurHash without the original images.
+///
+/// BlurHashes don't store the size of the image, so `w` and `h` must be the
+/// size of the original image (or any size with the same aspect ratio). The
+/// BlurHash is rendered at a low resolution and the result is encoded like
+/// [`rgba_to_thumb_hash`]. An error will be returned if the input isn't a
+/// valid BlurHash.
+pub fn blurhash_to_thumb_hash(blurhash: &str, w: usize, h: usize) -> Result<Vec<u8>, ()> {
+    assert!(w > 0 && h > 0);
+    let digits = blurhash
+        .bytes()
+        .map(|c| ALPHABET.iter().position(|&a| a == c).ok_or(()))
+        .collect::<Result<Vec<usize>, ()>>()?;
+    let number =
+        |range: std::ops::Range<usize>| digits[range].iter().fold(0, |n, &digit| n * 83 + digit);
+    if digits.len() < 6 {
+        return Err(());
+    }
+    let (nx, ny) = (number(0..1) % 9 + 1, number(0..1) / 9 + 1);
+    if digits.len() != 4 + 2 * nx * ny {
+        return Err(());
+    }
+
+    // Read the terms, which are linear RGB
+    let max_ac = (number(1..2) + 1) as f32 / 166.0;
+    let dc = number(2..6);
+    let mut terms = vec![[dc >> 16, (dc >> 8) & 255, dc & 255].map(|c| srgb_to_linear(c as u8))];
+    for i in 1..nx * ny {
+        let value = number(4 + 2 * i..6 + 2 * i);
+        terms.push([value / (19 * 19), value / 19 % 19, value % 19].map(|q| {
+            let f = (q as f32 - 9.0) / 9.0;
+            f * f.abs() * max_ac
+        }));
+    }
+
+    // Render the terms at the size of a decoded ThumbHash
+    let (dw, dh) = Channels::size_for_ratio(w as f32 / h as f32, 32);
+    let mut rgba = Vec::with_capacity(dw * dh * 4);
+    for y in 0..dh {
+        for x in 0..dw {
+            let mut rgb = [0.0; 3];
+            for j in 0..ny {
+                for i in 0..nx {
+                    let basis = (PI * x as f32 * i as f32 / dw as f32).cos()
+                        * (PI * y as f32 * j as f32 / dh as f32).cos();
+                    for (c, term) in rgb.iter_mut().zip(terms[i + j * nx]) {
+                        *c += term * basis;
+                    }
+                }
+            }
+            rgba.extend(rgb.map(linear_to_srgb));
+            rgba.push(255);
+        }
+    }
+    Ok(rgba_to_thumb_hash(dw, dh, &rgba))
+}
+
+/// Converts a ThumbHash to a BlurHash with `nx` by `ny` components (each from
+/// 1 to 9), for systems that can only display BlurHashes. A common choice is 4
+/// by 3.
+///
+/// The ThumbHash is decoded like [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba)
+/// and the result is encoded to a BlurHash. BlurHashes can't be transparent, so
+/// the alpha of the placeholder is ignored. An error will be returned if the
+/// input is too short.
+pub fn thumb_hash_to_blurhash(hash: &[u8], nx: usize, ny: usize) -> Result<String, ()> {
+    assert!((1..=9).contains(&nx) && (1..=9).contains(&ny));
+    let channels = Channels::read(hash)?;
+    let (w, h) = channels.size(32);
+    let linear: Vec<[f32; 3]> = channels
+        .render(w, h)
+        .chunks_exact(4)
+        .map(|p| [p[0], p[1], p[2]].map(srgb_to_linear))
+        .collect();
+
+    // Find the terms, which are linear RGB
+    let mut terms = Vec::with_capacity(nx * ny);
+    for j in 0..ny {
+        for i in 0..nx {
+            let normalization = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut term = [0.0; 3];
+            for y in 0..h {
+                for x in 0..w {
+                    let basis = (PI * x as f32 * i as f32 / w as f32).cos()
+                        * (PI * y as f32 * j as f32 / h as f32).cos();
+                    for (t, c) in term.iter_mut().zip(linear[x + y * w]) {
+                        *t += basis * c;
+                    }
+                }
+            }
+            terms.push(term.map(|t| t * normalization / (w * h) as f32));
+        }
+    }
+
+    // Write the digits
+    let mut text = String::with_capacity(4 + 2 * nx * ny);
+    let mut push = |value: usize, len: usize| {
+        for i in (0..len).rev() {
+            text.push(ALPHABET[value / 83usize.pow(i as u32) % 83] as char);
+        }
+    };
+    push((nx - 1) + (ny - 1) * 9, 1);
+    let largest = terms[1..]
+        .iter()
+        .flatten()
+        .fold(0.0f32, |largest, t| largest.max(t.abs()));
+    let max_ac = if nx * ny > 1 {
+        let quantized = (largest * 166.0 - 0.5).floor().clamp(0.0, 82.0) as usize;
+        push(quantized, 1);
+        (quantized + 1) as f32 / 166.0
+    } else {
+        push(0, 1);
+        1.0
+    };
+    let [r, g, b] = terms[0].map(|c| linear_to_srgb(c) as usize);
+    push(r << 16 | g << 8 | b, 4);
+    for term in &terms[1..] {
+        let [r, g, b] = term.map(|t| {
+            let f = t / max_ac;
+            (f.signum() * f.abs().sqrt() * 9.0 + 9.5)
+                .floor()
+                .clamp(0.0, 18.0) as usize
+        });
+        push(r * 19 * 19 + g * 19 + b, 2);
+    }
+    Ok(text)
+}
+
+/// Converts an sRGB value from 0 to 255 to linear RGB from 0 to 1.
+fn srgb_to_linear(c: u8) -> f32 {
+    let c = c as f32 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a linear RGB value from 0 to 1 to sRGB from 0 to 255.
+fn linear_to_srgb(c: f32) -> u8 {
+    let c = c.clamp(0.0, 1.0);
+    let srgb = if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sample;
+    use crate::thumb_hash_to_average_rgba;
+
+    // The example from the reference implementation of BlurHash
+    const EXAMPLE: &str = "LEHV6nWB2yk8pyo0adR*.7kCMdnj";
+
+    fn assert_close((r, g, b, _): (f32, f32, f32, f32), (r2, g2, b2): (f32, f32, f32), max: f32) {
+        assert!((r - r2).abs() < max && (g - g2).abs() < max && (b - b2).abs() < max);
+    }
+
+    #[test]
+    fn converts_the_reference_example() {
+        let hash = blurhash_to_thumb_hash(EXAMPLE, 400, 300).unwrap();
+        let average = thumb_hash_to_average_rgba(&hash).unwrap();
+        assert_close(average, (151.0 / 255.0, 150.0 / 255.0, 149.0 / 255.0), 0.1);
+        assert_eq!(
+            crate::thumb_hash_to_approximate_aspect_ratio(&hash),
+            Ok(7.0 / 5.0)
+        );
+    }
+
+    #[test]
+    fn thumb_hash_to_blurhash_has_two_digits_per_term() {
+        let hash = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        for (nx, ny) in [(1, 1), (4, 3), (3, 4), (9, 9)] {
+            let blurhash = thumb_hash_to_blurhash(&hash, nx, ny).unwrap();
+            assert_eq!(blurhash.len(), 4 + 2 * nx * ny);
+            assert!(blurhash_to_thumb_hash(&blurhash, 32, 24).is_ok());
+        }
+        assert_eq!(thumb_hash_to_blurhash(&[], 4, 3), Err(()));
+    }
+
+    #[test]
+    fn round_trip_keeps_the_average_color() {
+        let hash = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        let blurhash = thumb_hash_to_blurhash(&hash, 4, 3).unwrap();
+        let round_trip = blurhash_to_thumb_hash(&blurhash, 32, 24).unwrap();
+        let (r, g, b, _) = thumb_hash_to_average_rgba(&hash).unwrap();
+        assert_close(
+            thumb_hash_to_average_rgba(&round_trip).unwrap(),
+            (r, g, b),
+            0.03,
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_blurhashes() {
+        assert_eq!(
+            blurhash_to_thumb_hash("LEHV6nWB2yk8pyo0adR*.7kCMdn\"", 4, 3),
+            Err(())
+        );
+        assert_eq!(blurhash_to_thumb_hash(&EXAMPLE[..27], 4, 3), Err(()));
+        assert_eq!(
+            blurhash_to_thumb_hash(&format!("{EXAMPLE}00"), 4, 3),
+            Err(())
+        );
+        assert_eq!(blurhash_to_thumb_hash("00000", 4, 3), Err(()));
+        assert_eq!(blurhash_to_thumb_hash("", 4, 3), Err(()));
+    }
+}
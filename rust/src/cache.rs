@@ -0,0 +1,143 @@
+use crate::{decode_thumb_hash_with_options, DecodeOptions, DecodedThumbHash};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// A bounded cache of decoded ThumbHashes that evicts the least recently used
+/// entry when it's full. Only available with the `cache` feature.
+///
+/// UI frameworks often decode the same visible hashes again on every layout,
+/// so this lets them share the decoded images instead. Entries are keyed by
+/// both the hash and the size it was decoded at. The cache can be shared
+/// between threads.
+#[derive(Debug)]
+pub struct ThumbHashCache {
+    capacity: usize,
+    entries: Mutex<CacheEntries>,
+}
+
+#[derive(Debug, Default)]
+struct CacheEntries {
+    map: HashMap<(Vec<u8>, usize), (Arc<DecodedThumbHash>, u64)>,
+    clock: u64,
+}
+
+impl ThumbHashCache {
+    /// Creates an empty cache that holds at most `capacity` decoded images.
+    pub fn new(capacity: usize) -> Self {
+        ThumbHashCache {
+            capacity,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Returns the maximum number of decoded images in the cache.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the number of decoded images in the cache.
+    pub fn len(&self) -> usize {
+        self.entries().map.len()
+    }
+
+    /// Returns true if the cache contains no decoded images.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes every decoded image from the cache.
+    pub fn clear(&self) {
+        self.entries().map.clear();
+    }
+
+    /// Decodes a ThumbHash like [`decode_thumb_hash_with_options`] with the
+    /// given `max_size`, or returns the result of an earlier call for the same
+    /// hash and size. An error will be returned if the input is too short.
+    pub fn decode(&self, hash: &[u8], max_size: usize) -> Result<Arc<DecodedThumbHash>, ()> {
+        let key = (hash.to_vec(), max_size);
+        {
+            let mut entries = self.entries();
+            let now = entries.tick();
+            if let Some((decoded, used)) = entries.map.get_mut(&key) {
+                *used = now;
+                return Ok(decoded.clone());
+            }
+        }
+
+        // Decode without holding the lock so other threads aren't blocked
+        let options = DecodeOptions {
+            max_size,
+            ..DecodeOptions::default()
+        };
+        let decoded = Arc::new(decode_thumb_hash_with_options(hash, &options)?);
+        if self.capacity == 0 {
+            return Ok(decoded);
+        }
+        let mut entries = self.entries();
+        if !entries.map.contains_key(&key) && entries.map.len() >= self.capacity {
+            let oldest = entries
+                .map
+                .iter()
+                .min_by_key(|(_, &(_, used))| used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.map.remove(&oldest);
+            }
+        }
+        let now = entries.tick();
+        entries.map.insert(key, (decoded.clone(), now));
+        Ok(decoded)
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, CacheEntries> {
+        // The entries are always left consistent, so a panic elsewhere is fine
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl CacheEntries {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::solid_hash;
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let cache = ThumbHashCache::new(2);
+        assert!(cache.is_empty());
+        let (a, b, c) = (solid_hash(0), solid_hash(120), solid_hash(240));
+        let first = cache.decode(&a, 32).unwrap();
+        assert!(Arc::ptr_eq(&first, &cache.decode(&a, 32).unwrap()));
+        assert_eq!(*first, crate::decode_thumb_hash(&a).unwrap());
+        cache.decode(&b, 32).unwrap();
+        cache.decode(&a, 32).unwrap();
+        cache.decode(&c, 32).unwrap();
+        assert_eq!(cache.len(), 2);
+
+        // B was used least recently, so it was evicted instead of A
+        assert!(Arc::ptr_eq(&first, &cache.decode(&a, 32).unwrap()));
+        assert_eq!(cache.len(), 2);
+        cache.decode(&b, 32).unwrap();
+        assert_eq!(cache.len(), 2);
+
+        // The size is part of the key
+        let large = cache.decode(&b, 64).unwrap();
+        assert_eq!(large.width, 64);
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.decode(&[], 32), Err(()));
+    }
+
+    #[test]
+    fn zero_capacity_does_not_cache() {
+        let cache = ThumbHashCache::new(0);
+        cache.decode(&solid_hash(0), 32).unwrap();
+        assert!(cache.is_empty());
+    }
+}
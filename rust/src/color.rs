@@ -0,0 +1,715 @@
+use crate::similarity::kmeans;
+use crate::{ac_positions, lpq_to_rgb, thumb_hash_to_average_rgba, Basis, Channels};
+use std::f32::consts::PI;
+
+/// Extracts the average color from a ThumbHash as 8-bit RGBA values.
+///
+/// RGB is not premultiplied by A. An error will be returned if the input is
+/// too short.
+pub fn thumb_hash_to_average_rgba_u8(hash: &[u8]) -> Result<[u8; 4], ()> {
+    let (r, g, b, a) = thumb_hash_to_average_rgba(hash)?;
+    Ok([to_u8(r), to_u8(g), to_u8(b), to_u8(a)])
+}
+
+/// Extracts the average color from a ThumbHash as a `#rrggbb` hex string.
+///
+/// The alpha channel is ignored. An error will be returned if the input is
+/// too short.
+pub fn thumb_hash_to_average_hex(hash: &[u8]) -> Result<String, ()> {
+    let [r, g, b, _] = thumb_hash_to_average_rgba_u8(hash)?;
+    Ok(format!("#{r:02x}{g:02x}{b:02x}"))
+}
+
+/// Extracts the average color from a ThumbHash as a CSS `rgba(...)` string.
+///
+/// An error will be returned if the input is too short.
+pub fn thumb_hash_to_average_css(hash: &[u8]) -> Result<String, ()> {
+    let (_, _, _, a) = thumb_hash_to_average_rgba(hash)?;
+    let [r, g, b, _] = thumb_hash_to_average_rgba_u8(hash)?;
+    Ok(format!(
+        "rgba({r}, {g}, {b}, {})",
+        (a * 1000.0).round() / 1000.0
+    ))
+}
+
+/// Returns true if the average color of a ThumbHash is dark.
+///
+/// This uses the relative luminance from the WCAG definition of contrast, and
+/// a hash is considered dark when white text over it would have more contrast
+/// than black text. The alpha channel is ignored. An error will be returned if
+/// the input is too short.
+pub fn is_dark(hash: &[u8]) -> Result<bool, ()> {
+    let (r, g, b, _) = thumb_hash_to_average_rgba(hash)?;
+    // This is where (1 + 0.05) / (Y + 0.05) equals (Y + 0.05) / (0 + 0.05)
+    Ok(relative_luminance(r, g, b) < 0.179)
+}
+
+/// Returns either white or black as RGB values from 0 to 1, whichever is more
+/// readable as text on top of the average color of a ThumbHash.
+///
+/// An error will be returned if the input is too short.
+pub fn contrasting_text_color(hash: &[u8]) -> Result<(f32, f32, f32), ()> {
+    Ok(if is_dark(hash)? {
+        (1.0, 1.0, 1.0)
+    } else {
+        (0.0, 0.0, 0.0)
+    })
+}
+
+/// Computes the WCAG contrast ratio between a foreground color and the
+/// average color of a ThumbHash.
+///
+/// The foreground color is given as RGB values from 0 to 1. The result ranges
+/// from 1 (no contrast) to 21 (black on white). WCAG level AA requires at
+/// least 4.5 for normal text and 3 for large text, and level AAA requires 7 and
+/// 4.5 respectively. The alpha channel is ignored. An error will be returned if
+/// the input is too short.
+pub fn contrast_ratio(hash: &[u8], (r, g, b): (f32, f32, f32)) -> Result<f32, ()> {
+    let (bg_r, bg_g, bg_b, _) = thumb_hash_to_average_rgba(hash)?;
+    let fg = relative_luminance(r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0));
+    let bg = relative_luminance(bg_r, bg_g, bg_b);
+    Ok((fg.max(bg) + 0.05) / (fg.min(bg) + 0.05))
+}
+
+/// Suggests a theme color for UI chrome (such as `<meta name="theme-color">`)
+/// from a ThumbHash.
+///
+/// This starts from the average color and nudges it toward a moderate
+/// saturation and lightness, since the average of a photo is often too muddy
+/// or too pale to use as an accent. Nearly gray images give a gray theme
+/// color. Returns RGB values from 0 to 1. An error will be returned if the
+/// input is too short.
+pub fn thumb_hash_to_theme_color(hash: &[u8]) -> Result<(f32, f32, f32), ()> {
+    let (r, g, b, _) = thumb_hash_to_average_rgba(hash)?;
+    let (hue, saturation, lightness) = rgb_to_hsl(r, g, b);
+    let saturation = if saturation < 0.05 {
+        saturation
+    } else {
+        saturation.max(0.35)
+    };
+    Ok(hsl_to_rgb(hue, saturation, lightness.clamp(0.25, 0.6)))
+}
+
+/// Estimates the most saturated prominent color of the image a ThumbHash was
+/// made from, which usually makes a better UI accent than the average color.
+///
+/// This samples the placeholder on a coarse grid, finds the most saturated
+/// sample, and then averages the samples with a similar hue so that a single
+/// stray sample doesn't determine the result. Transparent areas are ignored.
+/// Returns RGB values from 0 to 1. An error will be returned if the input is
+/// too short.
+pub fn thumb_hash_to_accent_color(hash: &[u8]) -> Result<(f32, f32, f32), ()> {
+    let channels = Channels::read(hash)?;
+    let samples = Basis::new(&channels).grid(8, 8);
+    let chroma = |&(_, p, q, a): &(f32, f32, f32, f32)| (p * p + q * q).sqrt() * a.clamp(0.0, 1.0);
+    let most = samples.iter().fold(samples[0], |best, s| {
+        if chroma(s) > chroma(&best) {
+            *s
+        } else {
+            best
+        }
+    });
+
+    // Average the samples within 30 degrees of the most saturated hue
+    let hue = most.2.atan2(most.1);
+    let (mut l, mut p, mut q, mut total) = (0.0, 0.0, 0.0, 0.0);
+    for s in &samples {
+        let delta = (s.2.atan2(s.1) - hue).abs();
+        if delta.min(2.0 * PI - delta) < PI / 6.0 {
+            let weight = chroma(s);
+            l += s.0 * weight;
+            p += s.1 * weight;
+            q += s.2 * weight;
+            total += weight;
+        }
+    }
+    let (r, g, b) = if total > 0.0 {
+        lpq_to_rgb(l / total, p / total, q / total)
+    } else {
+        lpq_to_rgb(most.0, most.1, most.2)
+    };
+    Ok((r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0)))
+}
+
+/// Extracts a palette of up to `n` representative colors from a ThumbHash.
+///
+/// This decodes the placeholder at a low resolution and clusters the pixels
+/// with k-means. The colors are sorted from most to least common, and fewer
+/// than `n` colors are returned if the placeholder doesn't have that many
+/// distinct colors. Transparent areas are ignored. Returns RGB values from 0
+/// to 1. An error will be returned if the input is too short.
+pub fn thumb_hash_to_palette(hash: &[u8], n: usize) -> Result<Vec<(f32, f32, f32)>, ()> {
+    let channels = Channels::read(hash)?;
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    let samples = Basis::new(&channels).grid(16, 16);
+    let mut points: Vec<Vec<f32>> = samples
+        .iter()
+        .filter(|&&(_, _, _, a)| a >= 0.5)
+        .map(|&(l, p, q, _)| vec![l, p, q])
+        .collect();
+    if points.is_empty() {
+        points = samples.iter().map(|&(l, p, q, _)| vec![l, p, q]).collect();
+    }
+    points.dedup();
+    let (assignments, centers) = kmeans(&points, n);
+    let mut counts = vec![0; centers.len()];
+    for &a in &assignments {
+        counts[a] += 1;
+    }
+    let mut palette: Vec<(usize, (f32, f32, f32))> = centers
+        .iter()
+        .zip(counts)
+        .filter(|&(_, count)| count > 0)
+        .map(|(c, count)| {
+            let (r, g, b) = lpq_to_rgb(c[0], c[1], c[2]);
+            (
+                count,
+                (r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0)),
+            )
+        })
+        .collect();
+    palette.sort_by_key(|&(count, _)| std::cmp::Reverse(count));
+    Ok(palette.into_iter().map(|(_, color)| color).collect())
+}
+
+/// Computes the average color of each cell when a ThumbHash is divided into a
+/// grid of `cols` by `rows` cells.
+///
+/// The averages are computed analytically from the DCT terms rather than by
+/// decoding pixels, so this works for any grid size without allocating a pixel
+/// buffer. Returns RGBA values from 0 to 1 for each cell in row-major order.
+/// RGB is not premultiplied by A. An error will be returned if the input is
+/// too short.
+pub fn thumb_hash_to_color_grid(
+    hash: &[u8],
+    cols: usize,
+    rows: usize,
+) -> Result<Vec<(f32, f32, f32, f32)>, ()> {
+    let channels = Channels::read(hash)?;
+    let basis = Basis::new(&channels);
+
+    // The average of cos(πcu) over [u0, u1] is the basis function for a cell
+    let average = |n: usize, i: usize, count: usize| -> Vec<f32> {
+        let (u0, u1) = (i as f32 / count as f32, (i + 1) as f32 / count as f32);
+        (0..n)
+            .map(|c| {
+                if c == 0 {
+                    1.0
+                } else {
+                    let k = PI * c as f32;
+                    ((k * u1).sin() - (k * u0).sin()) / (k * (u1 - u0))
+                }
+            })
+            .collect()
+    };
+    let mut colors = Vec::with_capacity(cols * rows);
+    for y in 0..rows {
+        let fy = average(basis.ny(), y, rows);
+        for x in 0..cols {
+            let fx = average(basis.nx(), x, cols);
+            colors.push(lpqa_to_rgba(basis.lpqa_with_factors(&fx, &fy)));
+        }
+    }
+    Ok(colors)
+}
+
+/// Computes the colors at the four corners of the placeholder for a ThumbHash.
+///
+/// The corners are returned in the order top-left, top-right, bottom-left, and
+/// bottom-right, which is convenient for building a four-stop gradient. Returns
+/// RGBA values from 0 to 1. RGB is not premultiplied by A. An error will be
+/// returned if the input is too short.
+pub fn thumb_hash_to_corner_colors(hash: &[u8]) -> Result<[(f32, f32, f32, f32); 4], ()> {
+    let channels = Channels::read(hash)?;
+    let basis = Basis::new(&channels);
+    let corner = |tx, ty| lpqa_to_rgba(basis.lpqa(tx, ty));
+    Ok([
+        corner(0.0, 0.0),
+        corner(PI, 0.0),
+        corner(0.0, PI),
+        corner(PI, PI),
+    ])
+}
+
+/// Computes the average color along each edge of the placeholder for a
+/// ThumbHash.
+///
+/// The edges are returned in the order top, bottom, left, and right. This is
+/// useful for extending the edges of an image into letterboxed areas around
+/// it. Returns RGBA values from 0 to 1. RGB is not premultiplied by A. An error
+/// will be returned if the input is too short.
+pub fn thumb_hash_to_edge_colors(hash: &[u8]) -> Result<[(f32, f32, f32, f32); 4], ()> {
+    let channels = Channels::read(hash)?;
+    let basis = Basis::new(&channels);
+
+    // Along an edge, each basis function is 1 or -1 on the perpendicular axis
+    // and averages to 0 on the parallel axis (except for the constant term)
+    let average: Vec<f32> = (0..16).map(|c| if c == 0 { 1.0 } else { 0.0 }).collect();
+    let start = [1.0; 16];
+    let end: Vec<f32> = (0..16)
+        .map(|c| if c % 2 == 0 { 1.0 } else { -1.0 })
+        .collect();
+    let edge = |fx: &[f32], fy: &[f32]| lpqa_to_rgba(basis.lpqa_with_factors(fx, fy));
+    Ok([
+        edge(&average, &start),
+        edge(&average, &end),
+        edge(&start, &average),
+        edge(&end, &average),
+    ])
+}
+
+/// Returns true if the image a ThumbHash was made from is effectively
+/// grayscale.
+///
+/// This only looks at the average chroma and the chroma scales of the hash,
+/// not the individual AC terms: the image is considered monochrome if both the
+/// average chroma and the amount of chroma variation across the image are
+/// within a few quantization steps of zero. The whole hash is still read so
+/// that malformed input is rejected. An error will be returned if the input is
+/// too short.
+pub fn is_monochrome(hash: &[u8]) -> Result<bool, ()> {
+    let c = Channels::read(hash)?;
+    Ok(c.p_dc.abs() < 0.05 && c.q_dc.abs() < 0.05 && c.p_scale < 0.05 && c.q_scale < 0.05)
+}
+
+/// Computes a score for how busy the image a ThumbHash was made from is.
+///
+/// This is the standard deviation of the placeholder across the image, which
+/// is computed directly from the energy of the AC terms. Luminance counts fully
+/// and chroma counts half as much. A flat image scores 0, and most photos score
+/// between 0.05 and 0.25. Higher scores mean text on top of the image is more
+/// likely to need a scrim. An error will be returned if the input is too short.
+pub fn thumb_hash_to_detail_score(hash: &[u8]) -> Result<f32, ()> {
+    let c = Channels::read(hash)?;
+    let variance = |channel: usize, nx: usize, ny: usize| -> f32 {
+        ac_positions(nx, ny)
+            .into_iter()
+            .map(|(cx, cy)| {
+                // The basis functions for the first row and column have twice
+                // the variance of the others because of how they are scaled
+                let weight = if cx == 0 || cy == 0 { 2.0 } else { 1.0 };
+                let f = c.term(channel, cx, cy);
+                f * f * weight
+            })
+            .sum()
+    };
+    let l = variance(0, c.l_nx, c.l_ny);
+    let pq = variance(1, c.pq_n, c.pq_n) + variance(2, c.pq_n, c.pq_n);
+    Ok((l + pq / 4.0).sqrt())
+}
+
+/// Estimates the main direction that luminance changes across the image a
+/// ThumbHash was made from.
+///
+/// Returns a vector `(x, y)` pointing from the darker side of the image
+/// toward the lighter side, where x points right and y points down. Its length
+/// is the luminance difference between the two sides, so a flat image gives
+/// `(0, 0)`. This comes from the first-order luminance terms and is useful for
+/// aligning a CSS gradient with the image (the CSS angle for
+/// `linear-gradient` from dark to light is `x.atan2(-y)`) or for placing text
+/// on the darker side. An error will be returned if the input is too short.
+pub fn thumb_hash_to_gradient_direction(hash: &[u8]) -> Result<(f32, f32), ()> {
+    let channels = Channels::read(hash)?;
+
+    // Each first-order term is scaled by 2 and goes from 1 to -1 across the
+    // image, so the difference between the two sides is 4 times the term
+    let x = -4.0 * channels.term(0, 1, 0);
+    let y = -4.0 * channels.term(0, 0, 1);
+    Ok((x, y))
+}
+
+/// Coarse statistics about the luminance of the image a ThumbHash was made
+/// from. Each value uses the relative luminance from the WCAG definition of
+/// contrast and ranges from 0 to 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LuminanceStats {
+    /// The luminance of the darkest part of the image, from 0 to 1.
+    pub min: f32,
+
+    /// The luminance of the lightest part of the image, from 0 to 1.
+    pub max: f32,
+
+    /// The average luminance of the image, from 0 to 1.
+    pub mean: f32,
+
+    /// The fraction of the image in each of 8 equally-sized luminance ranges,
+    /// from darkest to lightest. These add up to 1.
+    pub histogram: [f32; 8],
+}
+
+/// Computes luminance statistics for the image a ThumbHash was made from.
+///
+/// This decodes the placeholder at a low resolution and ignores transparent
+/// areas. It's useful for tuning the opacity of an overlay or for detecting
+/// mostly-dark or mostly-light images. An error will be returned if the input
+/// is too short.
+pub fn thumb_hash_to_luminance_stats(hash: &[u8]) -> Result<LuminanceStats, ()> {
+    let channels = Channels::read(hash)?;
+    let samples = Basis::new(&channels).grid(16, 16);
+    let opaque = samples.iter().any(|&(_, _, _, a)| a >= 0.5);
+    let mut stats = LuminanceStats {
+        min: 1.0,
+        max: 0.0,
+        mean: 0.0,
+        histogram: [0.0; 8],
+    };
+    let mut count = 0;
+    for lpqa in samples {
+        if opaque && lpqa.3 < 0.5 {
+            continue;
+        }
+        let (r, g, b, _) = lpqa_to_rgba(lpqa);
+        let y = relative_luminance(r, g, b);
+        stats.min = stats.min.min(y);
+        stats.max = stats.max.max(y);
+        stats.mean += y;
+        stats.histogram[((y * 8.0) as usize).min(7)] += 1.0;
+        count += 1;
+    }
+    stats.mean /= count as f32;
+    for bucket in &mut stats.histogram {
+        *bucket /= count as f32;
+    }
+    Ok(stats)
+}
+
+/// Derives a key for arranging images into a smooth color gradient.
+///
+/// Sorting hashes by this key orders them by the hue of their average color,
+/// with nearly gray images first. Within each band of similar hues, images are
+/// ordered by luminance, alternating between light-to-dark and dark-to-light
+/// so that neighboring bands blend into each other. An error will be returned
+/// if the input is too short.
+pub fn sort_key(hash: &[u8]) -> Result<u32, ()> {
+    const HUE_BANDS: u32 = 24;
+    let (r, g, b, _) = thumb_hash_to_average_rgba(hash)?;
+    let (hue, saturation, lightness) = rgb_to_hsl(r, g, b);
+    let band = if saturation < 0.1 {
+        0
+    } else {
+        1 + ((hue * HUE_BANDS as f32) as u32).min(HUE_BANDS - 1)
+    };
+    let lightness = (lightness * 65535.0).round() as u32;
+    let lightness = if band % 2 == 1 {
+        65535 - lightness
+    } else {
+        lightness
+    };
+    Ok((band << 16) | lightness)
+}
+
+fn to_u8(x: f32) -> u8 {
+    (x.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn lpqa_to_rgba((l, p, q, a): (f32, f32, f32, f32)) -> (f32, f32, f32, f32) {
+    let (r, g, b) = lpq_to_rgb(l, p, q);
+    (
+        r.clamp(0.0, 1.0),
+        g.clamp(0.0, 1.0),
+        b.clamp(0.0, 1.0),
+        a.clamp(0.0, 1.0),
+    )
+}
+
+/// Computes the relative luminance of an sRGB color as defined by WCAG.
+fn relative_luminance(r: f32, g: f32, b: f32) -> f32 {
+    let linear = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
+
+/// Converts RGB to hue, saturation, and lightness, where each ranges from 0
+/// to 1.
+fn rgb_to_hsl(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let delta = max - min;
+    if delta <= 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = if max == r {
+        ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        (b - r) / delta + 2.0
+    } else {
+        (r - g) / delta + 4.0
+    };
+    (hue / 6.0, saturation.min(1.0), lightness)
+}
+
+/// Converts hue, saturation, and lightness (each from 0 to 1) to RGB.
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (f32, f32, f32) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let (r, g, b) = match h as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    (r + m, g + m, b + m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rgba_to_thumb_hash, rgba_to_thumb_hash_extended, rgba_to_thumb_hash_progressive};
+
+    type Encoder = fn(usize, usize, &[u8]) -> Vec<u8>;
+
+    fn gradient(color: impl Fn(u8) -> [u8; 3]) -> Vec<u8> {
+        let mut rgba = Vec::new();
+        for y in 0..24 {
+            for x in 0..32 {
+                let [r, g, b] = color((x * 4 + y * 2) as u8);
+                rgba.extend_from_slice(&[r, g, b, 255]);
+            }
+        }
+        rgba
+    }
+
+    fn solid([r, g, b, a]: [u8; 4]) -> Vec<u8> {
+        let rgba: Vec<u8> = (0..16 * 16).flat_map(|_| [r, g, b, a]).collect();
+        rgba_to_thumb_hash(16, 16, &rgba)
+    }
+
+    /// Returns the hash of an image whose left half (or top half, if
+    /// `vertical` is true) is `first` and whose other half is `second`.
+    fn halves(first: [u8; 4], second: [u8; 4], vertical: bool) -> Vec<u8> {
+        let mut rgba = Vec::new();
+        for y in 0..32 {
+            for x in 0..32 {
+                let i = if vertical { y } else { x };
+                rgba.extend_from_slice(if i < 16 { &first } else { &second });
+            }
+        }
+        rgba_to_thumb_hash(32, 32, &rgba)
+    }
+
+    #[test]
+    fn is_monochrome_reads_every_format() {
+        let gray = gradient(|v| [v, v, v]);
+        let red = gradient(|v| [200, v / 4, v / 4]);
+        let encoders: [Encoder; 3] = [
+            rgba_to_thumb_hash,
+            rgba_to_thumb_hash_extended,
+            rgba_to_thumb_hash_progressive,
+        ];
+        for encode in encoders {
+            assert_eq!(is_monochrome(&encode(32, 24, &gray)), Ok(true));
+            assert_eq!(is_monochrome(&encode(32, 24, &red)), Ok(false));
+        }
+        assert_eq!(is_monochrome(&[0; 2]), Err(()));
+    }
+
+    #[test]
+    fn sort_key_orders_by_hue_then_lightness() {
+        let key = |rgb: [u8; 3]| sort_key(&solid([rgb[0], rgb[1], rgb[2], 255])).unwrap();
+        let gray = key([128, 128, 128]);
+        let oranges = [key([130, 60, 0]), key([230, 120, 20]), key([250, 180, 90])];
+        let greens = [key([0, 90, 0]), key([30, 200, 30]), key([120, 250, 120])];
+        let blue = key([30, 30, 220]);
+        assert!(gray < oranges[0] && oranges[2] < greens[2] && greens[0] < blue);
+
+        // Oranges get darker toward gray and greens get darker toward blue
+        assert!(oranges[0] < oranges[1] && oranges[1] < oranges[2]);
+        assert!(greens[2] < greens[1] && greens[1] < greens[0]);
+        assert_eq!(sort_key(&[]), Err(()));
+    }
+
+    #[test]
+    fn average_color_formats() {
+        let opaque = solid([200, 40, 40, 255]);
+        let translucent = solid([200, 40, 40, 128]);
+        assert_eq!(
+            thumb_hash_to_average_rgba_u8(&opaque),
+            Ok([198, 40, 42, 255])
+        );
+        assert_eq!(
+            thumb_hash_to_average_rgba_u8(&translucent),
+            Ok([198, 40, 42, 136])
+        );
+        assert_eq!(thumb_hash_to_average_hex(&opaque).unwrap(), "#c6282a");
+        assert_eq!(thumb_hash_to_average_hex(&translucent).unwrap(), "#c6282a");
+        assert_eq!(
+            thumb_hash_to_average_css(&opaque).unwrap(),
+            "rgba(198, 40, 42, 1)"
+        );
+        assert_eq!(
+            thumb_hash_to_average_css(&translucent).unwrap(),
+            "rgba(198, 40, 42, 0.533)"
+        );
+        assert_eq!(thumb_hash_to_average_hex(&[]), Err(()));
+    }
+
+    #[test]
+    fn picks_readable_text_colors() {
+        let navy = solid([20, 30, 90, 255]);
+        let yellow = solid([250, 220, 90, 255]);
+        assert_eq!(is_dark(&navy), Ok(true));
+        assert_eq!(is_dark(&yellow), Ok(false));
+        assert_eq!(contrasting_text_color(&navy), Ok((1.0, 1.0, 1.0)));
+        assert_eq!(contrasting_text_color(&yellow), Ok((0.0, 0.0, 0.0)));
+        assert_eq!(is_dark(&[]), Err(()));
+    }
+
+    #[test]
+    fn contrast_ratio_follows_wcag() {
+        let white = solid([255, 255, 255, 255]);
+        let black = solid([0, 0, 0, 255]);
+        let black_on_white = contrast_ratio(&white, (0.0, 0.0, 0.0)).unwrap();
+        let white_on_black = contrast_ratio(&black, (1.0, 1.0, 1.0)).unwrap();
+        assert!(black_on_white > 20.0 && black_on_white <= 21.0);
+        assert!(white_on_black > 20.0 && white_on_black <= 21.0);
+
+        // The foreground is clamped, and the same color has no contrast
+        assert_eq!(
+            contrast_ratio(&white, (-1.0, -1.0, -1.0)),
+            Ok(black_on_white)
+        );
+        let (r, g, b, _) = thumb_hash_to_average_rgba(&white).unwrap();
+        assert_eq!(contrast_ratio(&white, (r, g, b)), Ok(1.0));
+        assert_eq!(contrast_ratio(&[], (0.0, 0.0, 0.0)), Err(()));
+    }
+
+    #[test]
+    fn theme_color_has_moderate_saturation_and_lightness() {
+        for rgba in [[120, 110, 100, 255], [250, 240, 235, 255], [10, 20, 5, 255]] {
+            let (r, g, b) = thumb_hash_to_theme_color(&solid(rgba)).unwrap();
+            let (_, saturation, lightness) = rgb_to_hsl(r, g, b);
+            assert!(saturation >= 0.349, "{rgba:?}");
+            assert!((0.249..=0.601).contains(&lightness), "{rgba:?}");
+        }
+        let (r, g, b) = thumb_hash_to_theme_color(&solid([128, 128, 128, 255])).unwrap();
+        assert!(rgb_to_hsl(r, g, b).1 < 0.05);
+        assert_eq!(thumb_hash_to_theme_color(&[]), Err(()));
+    }
+
+    #[test]
+    fn accent_color_is_the_most_saturated_area() {
+        let hash = halves([130, 130, 130, 255], [220, 40, 30, 255], false);
+        let (r, g, b) = thumb_hash_to_accent_color(&hash).unwrap();
+        let (avg_r, avg_g, _, _) = thumb_hash_to_average_rgba(&hash).unwrap();
+        assert!(r - g > avg_r - avg_g && r > 0.5 && g < 0.4 && b < 0.4);
+
+        // Transparent areas don't count
+        let hash = halves([30, 40, 220, 0], [220, 120, 30, 255], true);
+        let (r, _, b) = thumb_hash_to_accent_color(&hash).unwrap();
+        assert!(r > b);
+        assert_eq!(thumb_hash_to_accent_color(&[]), Err(()));
+    }
+
+    #[test]
+    fn palette_finds_each_half() {
+        let hash = halves([220, 40, 30, 255], [30, 40, 220, 255], false);
+        let palette = thumb_hash_to_palette(&hash, 2).unwrap();
+        assert_eq!(palette.len(), 2);
+        assert!(palette.iter().any(|&(r, _, b)| r > b + 0.3));
+        assert!(palette.iter().any(|&(r, _, b)| b > r + 0.3));
+        assert_eq!(thumb_hash_to_palette(&hash, 0), Ok(Vec::new()));
+        assert_eq!(thumb_hash_to_palette(&[], 2), Err(()));
+    }
+
+    #[test]
+    fn color_grid_averages_each_cell() {
+        let hash = halves([220, 40, 30, 255], [30, 40, 220, 255], false);
+        let (r, g, b, a) = thumb_hash_to_average_rgba(&hash).unwrap();
+        let grid = thumb_hash_to_color_grid(&hash, 1, 1).unwrap();
+        let close = |x: f32, y: f32| (x - y).abs() < 1e-5;
+        assert!(close(grid[0].0, r) && close(grid[0].1, g) && close(grid[0].2, b));
+        assert!(close(grid[0].3, a));
+        let grid = thumb_hash_to_color_grid(&hash, 2, 3).unwrap();
+        assert_eq!(grid.len(), 6);
+        for row in grid.chunks(2) {
+            assert!(row[0].0 > row[0].2 && row[1].2 > row[1].0);
+        }
+        assert_eq!(thumb_hash_to_color_grid(&[], 2, 2), Err(()));
+    }
+
+    #[test]
+    fn corner_colors_are_in_order() {
+        let red = |&(r, _, b, _): &(f32, f32, f32, f32)| r > b;
+        let hash = halves([220, 40, 30, 255], [30, 40, 220, 255], false);
+        let corners = thumb_hash_to_corner_colors(&hash).unwrap();
+        assert_eq!(corners.map(|c| red(&c)), [true, false, true, false]);
+        let hash = halves([220, 40, 30, 255], [30, 40, 220, 255], true);
+        let corners = thumb_hash_to_corner_colors(&hash).unwrap();
+        assert_eq!(corners.map(|c| red(&c)), [true, true, false, false]);
+        assert!(thumb_hash_to_corner_colors(&[]).is_err());
+    }
+
+    #[test]
+    fn edge_colors_are_in_order() {
+        let hash = halves([220, 40, 30, 255], [30, 40, 220, 255], true);
+        let [top, bottom, left, right] = thumb_hash_to_edge_colors(&hash).unwrap();
+        assert!(top.0 > top.2 + 0.3 && bottom.2 > bottom.0 + 0.3);
+        assert!((left.0 - right.0).abs() < 0.1 && (left.2 - right.2).abs() < 0.1);
+        let hash = halves([220, 40, 30, 255], [30, 40, 220, 255], false);
+        let [top, bottom, left, right] = thumb_hash_to_edge_colors(&hash).unwrap();
+        assert!(left.0 > left.2 + 0.3 && right.2 > right.0 + 0.3);
+        assert!((top.0 - bottom.0).abs() < 0.1 && (top.2 - bottom.2).abs() < 0.1);
+        assert!(thumb_hash_to_edge_colors(&[]).is_err());
+    }
+
+    #[test]
+    fn detail_score_grows_with_contrast() {
+        let flat = thumb_hash_to_detail_score(&solid([90, 120, 150, 255])).unwrap();
+        let soft = halves([80, 80, 80, 255], [140, 140, 140, 255], false);
+        let hard = halves([0, 0, 0, 255], [255, 255, 255, 255], false);
+        let soft = thumb_hash_to_detail_score(&soft).unwrap();
+        let hard = thumb_hash_to_detail_score(&hard).unwrap();
+        assert!(flat < 0.02 && flat < soft && soft < hard);
+        assert_eq!(thumb_hash_to_detail_score(&[]), Err(()));
+    }
+
+    #[test]
+    fn gradient_direction_points_toward_the_light() {
+        let (x, y) = thumb_hash_to_gradient_direction(&halves(
+            [20, 20, 20, 255],
+            [235, 235, 235, 255],
+            false,
+        ))
+        .unwrap();
+        assert!(x > 0.5 && y.abs() < 0.1);
+        let (x, y) = thumb_hash_to_gradient_direction(&halves(
+            [235, 235, 235, 255],
+            [20, 20, 20, 255],
+            true,
+        ))
+        .unwrap();
+        assert!(y < -0.5 && x.abs() < 0.1);
+        let (x, y) = thumb_hash_to_gradient_direction(&solid([90, 120, 150, 255])).unwrap();
+        assert!(x.abs() < 0.05 && y.abs() < 0.05);
+        assert!(thumb_hash_to_gradient_direction(&[]).is_err());
+    }
+
+    #[test]
+    fn luminance_stats_cover_opaque_areas() {
+        let hash = halves([0, 0, 0, 255], [255, 255, 255, 255], false);
+        let stats = thumb_hash_to_luminance_stats(&hash).unwrap();
+        assert!(stats.min < 0.1 && stats.max > 0.8);
+        assert!(stats.min <= stats.mean && stats.mean <= stats.max);
+        assert!((stats.histogram.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!(stats.histogram[0] > 0.2 && stats.histogram[7] > 0.2);
+
+        // The transparent half is ignored
+        let hash = halves([255, 255, 255, 0], [20, 20, 20, 255], false);
+        let stats = thumb_hash_to_luminance_stats(&hash).unwrap();
+        assert!(stats.max < 0.5 && stats.histogram[7] == 0.0);
+        assert!(thumb_hash_to_luminance_stats(&[]).is_err());
+    }
+}
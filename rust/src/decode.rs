@@ -0,0 +1,1185 @@
+use crate::{lpq_to_rgb, lpqa_to_rgba_u8, Basis, Channels};
+use std::f32::consts::PI;
+
+/// Options for [`thumb_hash_to_rgba_with_options`].
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct DecodeOptions {
+    /// The length of the longer side of the decoded image in pixels. The
+    /// default is 32, which is what [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba)
+    /// uses. A ThumbHash doesn't have enough detail to benefit from much more
+    /// than that, but decoding at the size the placeholder is displayed at
+    /// avoids blurry upscaling in some renderers.
+    pub max_size: usize,
+
+    /// Whether to snap the aspect ratio to the closest common ratio (1:1, 4:3,
+    /// 3:2, or 16:9 in either orientation). The ratio stored in a hash is only
+    /// approximate, so a placeholder sized from it can be slightly off from the
+    /// real image and shift the layout when the image arrives. This only snaps
+    /// to ratios that would have given the same hash, so unusual ratios (e.g.
+    /// panoramas) are left alone. It also has no effect if the hash stores the
+    /// exact ratio (see [`thumb_hash_with_aspect_ratio`](crate::thumb_hash_with_aspect_ratio)).
+    /// The default is false.
+    pub snap_aspect_ratio: bool,
+
+    /// The color space of the decoded pixels. A ThumbHash stores sRGB colors,
+    /// so these are converted if another color space is requested. This only
+    /// affects the pixels and not values such as the average color. The
+    /// default is [`ColorSpace::Srgb`].
+    pub color_space: ColorSpace,
+
+    /// Whether to dither the decoded pixels with an ordered dither. Smooth
+    /// gradients can show visible bands when rounded to 8 bits, especially
+    /// when decoding large placeholders, and dithering hides them at the cost
+    /// of a faint pattern. The default is false.
+    pub dither: bool,
+
+    /// The standard deviation in pixels of a Gaussian blur applied to the
+    /// decoded image, like CSS `filter: blur()`. This softens the ripples that
+    /// sharp edges in the original image leave in the placeholder. The blur is
+    /// applied to the coefficients of the hash instead of the pixels, so it's
+    /// free and doesn't darken the edges of the image. The default is 0, which
+    /// doesn't blur.
+    pub blur: f32,
+
+    /// How much of the detail in the hash to keep, from 0 to 1. Each
+    /// coefficient is multiplied by this once per step it is from the average
+    /// color, so lower values fade out higher frequencies first. The stored
+    /// hash isn't changed. A value of 0 gives a flat wash of the average color
+    /// and the default of 1 keeps every coefficient as-is.
+    pub detail: f32,
+
+    /// The number of samples along each axis to average for each pixel, so
+    /// the default of 1 uses one sample at the center of each pixel and 2 uses
+    /// four. More samples give smoother results when decoding large
+    /// placeholders, but decoding is slower. Must be at least 1.
+    pub supersample: usize,
+
+    /// The radius in pixels of rounded corners to cut out of the decoded
+    /// image, which makes A transparent outside the rounded rectangle with
+    /// antialiased edges. The radius is limited to half the shorter side, so a
+    /// radius of `f32::INFINITY` gives a circle for square images (e.g. for
+    /// avatars). The default is 0, which keeps square corners.
+    pub corner_radius: f32,
+
+    /// The strength of a film grain effect from 0 to 1, which adds subtle
+    /// noise so the placeholder looks less artificially smooth. Values from
+    /// 0.02 to 0.1 work well. The noise is the same every time a hash is
+    /// decoded, so placeholders don't flicker when they're decoded again. The
+    /// default is 0, which doesn't add noise.
+    pub grain: f32,
+
+    /// How much to darken the edges of the decoded image, from 0 to 1, which
+    /// helps text drawn over the placeholder stay readable. RGB is multiplied
+    /// by a factor that falls off from 1 in the center to `1 - vignette` in the
+    /// corners. The default is 0, which doesn't darken anything.
+    pub vignette: f32,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        DecodeOptions {
+            max_size: 32,
+            snap_aspect_ratio: false,
+            color_space: ColorSpace::Srgb,
+            dither: false,
+            blur: 0.0,
+            detail: 1.0,
+            supersample: 1,
+            corner_radius: 0.0,
+            grain: 0.0,
+            vignette: 0.0,
+        }
+    }
+}
+
+/// The color space of the pixels from [`thumb_hash_to_rgba_with_options`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// The standard color space for the web and most images.
+    Srgb,
+
+    /// The wide-gamut color space used by Apple devices (e.g. for a canvas
+    /// with `colorSpace: "display-p3"`). It has the same transfer function
+    /// as sRGB but more saturated primaries, so sRGB colors are converted to
+    /// look the same when drawn on a Display-P3 surface.
+    DisplayP3,
+}
+
+impl DecodeOptions {
+    /// Returns the size of the decoded image for these options.
+    pub(crate) fn size(&self, channels: &Channels) -> (usize, usize) {
+        let ratio = channels.aspect_ratio();
+        if !self.snap_aspect_ratio || channels.extensions.aspect_ratio.is_some() {
+            return Channels::size_for_ratio(ratio, self.max_size);
+        }
+        let layout = (channels.lx, channels.ly);
+        let snapped = [1.0, 4.0 / 3.0, 3.0 / 2.0, 16.0 / 9.0]
+            .into_iter()
+            .flat_map(|r: f32| [r, 1.0 / r])
+            .filter(|&r| {
+                let (lx, ly, _) = Channels::layout(r, channels.term_counts().0);
+                (lx, ly) == layout
+            })
+            .min_by(|a, b| {
+                let (a, b) = ((a / ratio).ln().abs(), (b / ratio).ln().abs());
+                a.partial_cmp(&b).unwrap()
+            });
+        Channels::size_for_ratio(snapped.unwrap_or(ratio), self.max_size)
+    }
+
+    /// Renders the channels to a `w` by `h` RGBA image for these options.
+    pub(crate) fn render(&self, channels: &Channels, w: usize, h: usize) -> Vec<u8> {
+        let basis = Basis::new(channels);
+        let (nx, ny) = (basis.nx(), basis.ny());
+
+        // Blurring multiplies each basis function by a Gaussian of its frequency,
+        // and reducing the detail multiplies it by a power of the detail
+        let detail = self.detail.clamp(0.0, 1.0);
+        let gains = |n: usize, size: usize| -> Vec<f32> {
+            (0..n)
+                .map(|c| {
+                    let f = PI * self.blur * c as f32 / size as f32;
+                    (-0.5 * f * f).exp() * detail.powi(c as i32)
+                })
+                .collect()
+        };
+        let (gx, gy) = (gains(nx, w), gains(ny, h));
+
+        // Evaluate the factors for the basis functions at each sample position
+        let n = self.supersample;
+        assert!(n > 0);
+        let factors = |size: usize, gains: &[f32]| -> Vec<Vec<f32>> {
+            (0..size * n)
+                .map(|i| {
+                    let t = PI / size as f32 * ((i / n) as f32 + ((i % n) as f32 + 0.5) / n as f32);
+                    gains
+                        .iter()
+                        .enumerate()
+                        .map(|(c, gain)| (t * c as f32).cos() * gain)
+                        .collect()
+                })
+                .collect()
+        };
+        let (columns, rows) = (factors(w, &gx), factors(h, &gy));
+
+        let mut rgba = Vec::with_capacity(w * h * 4);
+        for y in 0..h {
+            for x in 0..w {
+                let mut sum = [0.0; 4];
+                for fy in &rows[y * n..(y + 1) * n] {
+                    for fx in &columns[x * n..(x + 1) * n] {
+                        let (l, p, q, a) = basis.lpqa_with_factors(fx, fy);
+                        let (r, g, b) = lpq_to_rgb(l, p, q);
+                        let (r, g, b) = match self.color_space {
+                            ColorSpace::Srgb => (r, g, b),
+                            ColorSpace::DisplayP3 => srgb_to_display_p3(r, g, b),
+                        };
+                        for (sum, c) in sum.iter_mut().zip([r, g, b, a]) {
+                            *sum += c.clamp(0.0, 1.0);
+                        }
+                    }
+                }
+
+                let count = (n * n) as f32;
+                let mut color = sum.map(|c| c / count);
+                if self.corner_radius > 0.0 {
+                    color[3] *= corner_coverage(x, y, w, h, self.corner_radius);
+                }
+                if self.vignette > 0.0 {
+                    let dx = (x as f32 + 0.5) / w as f32 - 0.5;
+                    let dy = (y as f32 + 0.5) / h as f32 - 0.5;
+                    let factor = 1.0 - self.vignette.min(1.0) * 2.0 * (dx * dx + dy * dy);
+                    for c in &mut color[..3] {
+                        *c *= factor;
+                    }
+                }
+                if self.grain > 0.0 {
+                    let noise = self.grain * (grain_noise(x, y) - 0.5);
+                    for c in &mut color[..3] {
+                        *c = (*c + noise).clamp(0.0, 1.0);
+                    }
+                }
+
+                // Values are truncated, so an offset from 0 to 1 rounds some up
+                let offset = if self.dither {
+                    (BAYER[y % 4][x % 4] as f32 + 0.5) / 16.0
+                } else {
+                    0.0
+                };
+                rgba.extend(color.map(|c| (c * 255.0 + offset).min(255.0) as u8));
+            }
+        }
+        rgba
+    }
+}
+
+/// Returns how much of the pixel at `(x, y)` is inside a `w` by `h` rectangle
+/// with rounded corners, from 0 to 1.
+fn corner_coverage(x: usize, y: usize, w: usize, h: usize, radius: f32) -> f32 {
+    let (w, h) = (w as f32, h as f32);
+    let radius = radius.min(w.min(h) / 2.0);
+    let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+    // Measure the distance to the closest point on the inset rectangle
+    let dx = px - px.clamp(radius, w - radius);
+    let dy = py - py.clamp(radius, h - radius);
+    (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0)
+}
+
+/// Returns a pseudo-random value from 0 to 1 for the pixel at `(x, y)`.
+fn grain_noise(x: usize, y: usize) -> f32 {
+    let mut bits = (x as u32).wrapping_mul(0x9E3779B1) ^ (y as u32).wrapping_mul(0x85EBCA77);
+    bits ^= bits >> 15;
+    bits = bits.wrapping_mul(0x2C1B3C6D);
+    bits ^= bits >> 12;
+    (bits >> 8) as f32 / (1 << 24) as f32
+}
+
+/// The threshold map for a 4x4 ordered dither.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Converts a gamma-encoded sRGB color to gamma-encoded Display-P3. Both use
+/// the same transfer function and white point, so only the primaries change.
+fn srgb_to_display_p3(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let to_linear = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let from_linear = |c: f32| {
+        if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    let (r, g, b) = (to_linear(r), to_linear(g), to_linear(b));
+    (
+        from_linear(0.8224621 * r + 0.177538 * g),
+        from_linear(0.0331941 * r + 0.9668058 * g),
+        from_linear(0.0170827 * r + 0.0723974 * g + 0.9105199 * b),
+    )
+}
+
+/// Decodes a ThumbHash to an RGBA image with the given options.
+///
+/// RGB is not premultiplied by A. Returns the width, height, and pixels of the
+/// rendered placeholder image. An error will be returned if the input is too
+/// short.
+pub fn thumb_hash_to_rgba_with_options(
+    hash: &[u8],
+    options: &DecodeOptions,
+) -> Result<(usize, usize, Vec<u8>), ()> {
+    let channels = Channels::read(hash)?;
+    let (w, h) = options.size(&channels);
+    Ok((w, h, options.render(&channels, w, h)))
+}
+
+/// Like [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba) but appends the
+/// pixels to `rgba` instead of returning a new `Vec`, so the caller decides
+/// where the memory comes from. Returns the width and height of the rendered
+/// placeholder image. Nothing is appended if an error is returned, which
+/// happens if the input is too short.
+pub fn thumb_hash_to_rgba_into(hash: &[u8], rgba: &mut Vec<u8>) -> Result<(usize, usize), ()> {
+    let channels = Channels::read(hash)?;
+    let (w, h) = channels.size(32);
+    channels.render_into(w, h, rgba);
+    Ok((w, h))
+}
+
+/// Like [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba) but returns the R,
+/// G, B, and A values in separate planes instead of interleaved, which is what
+/// video pipelines and some GPU uploads expect. Each plane has one byte per
+/// pixel, row-by-row. RGB is not premultiplied by A. An error will be returned
+/// if the input is too short.
+pub fn thumb_hash_to_planes(hash: &[u8]) -> Result<(usize, usize, [Vec<u8>; 4]), ()> {
+    let channels = Channels::read(hash)?;
+    let (w, h) = channels.size(32);
+    let basis = Basis::new(&channels);
+    let mut planes = [(); 4].map(|()| Vec::with_capacity(w * h));
+    for y in 0..h {
+        let ty = PI / h as f32 * (y as f32 + 0.5);
+        for x in 0..w {
+            let tx = PI / w as f32 * (x as f32 + 0.5);
+            let rgba = lpqa_to_rgba_u8(basis.lpqa(tx, ty));
+            for (plane, value) in planes.iter_mut().zip(rgba) {
+                plane.push(value);
+            }
+        }
+    }
+    Ok((w, h, planes))
+}
+
+/// Decodes a ThumbHash to separate L, P, Q, and A planes before they're
+/// converted to RGBA, so custom color transforms can be applied to the
+/// placeholder. Each plane has one value per pixel, row-by-row.
+///
+/// L is the luminance `(r + g + b) / 3`, P is yellow minus blue
+/// `(r + g) / 2 - b`, and Q is red minus green `r - g`, where RGB goes from 0
+/// to 1. The values aren't clamped, and converting them back to RGB and
+/// clamping gives the pixels from [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba).
+/// An error will be returned if the input is too short.
+pub fn thumb_hash_to_lpqa(hash: &[u8]) -> Result<(usize, usize, [Vec<f32>; 4]), ()> {
+    let channels = Channels::read(hash)?;
+    let (w, h) = channels.size(32);
+    let basis = Basis::new(&channels);
+    let mut planes = [(); 4].map(|()| Vec::with_capacity(w * h));
+    for y in 0..h {
+        let ty = PI / h as f32 * (y as f32 + 0.5);
+        for x in 0..w {
+            let tx = PI / w as f32 * (x as f32 + 0.5);
+            let (l, p, q, a) = basis.lpqa(tx, ty);
+            for (plane, value) in planes.iter_mut().zip([l, p, q, a]) {
+                plane.push(value);
+            }
+        }
+    }
+    Ok((w, h, planes))
+}
+
+/// A decoded ThumbHash along with the values that are usually needed to
+/// display it, returned by [`decode_thumb_hash`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedThumbHash {
+    /// The width of the rendered placeholder image.
+    pub width: usize,
+
+    /// The height of the rendered placeholder image.
+    pub height: usize,
+
+    /// The pixels of the rendered placeholder image, row-by-row. RGB is not
+    /// premultiplied by A.
+    pub rgba: Vec<u8>,
+
+    /// The average color as RGBA values from 0 to 1, the same as what
+    /// [`thumb_hash_to_average_rgba`](crate::thumb_hash_to_average_rgba)
+    /// returns.
+    pub average_rgba: (f32, f32, f32, f32),
+
+    /// Whether the hash has an alpha channel. If not, every pixel is opaque
+    /// (other than rounded corners from [`DecodeOptions::corner_radius`]).
+    pub has_alpha: bool,
+}
+
+/// Decodes a ThumbHash to an RGBA image along with its average color and
+/// whether it has alpha, which saves parsing the hash several times.
+///
+/// An error will be returned if the input is too short.
+pub fn decode_thumb_hash(hash: &[u8]) -> Result<DecodedThumbHash, ()> {
+    decode_thumb_hash_with_options(hash, &DecodeOptions::default())
+}
+
+/// Like [`decode_thumb_hash`] but with the given options.
+pub fn decode_thumb_hash_with_options(
+    hash: &[u8],
+    options: &DecodeOptions,
+) -> Result<DecodedThumbHash, ()> {
+    let channels = Channels::read(hash)?;
+    let (width, height) = options.size(&channels);
+    Ok(DecodedThumbHash {
+        width,
+        height,
+        rgba: options.render(&channels, width, height),
+        average_rgba: channels.average_rgba(),
+        has_alpha: channels.has_alpha,
+    })
+}
+
+/// Returns the width and height of the image that
+/// [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba) produces for a ThumbHash,
+/// without decoding any pixels.
+///
+/// This lets layouts reserve space and allocate textures before the pixels are
+/// decoded. An error will be returned if the input is too short.
+pub fn decoded_dimensions(hash: &[u8]) -> Result<(usize, usize), ()> {
+    decoded_dimensions_for(hash, 32)
+}
+
+/// Returns the width and height of the image that
+/// [`thumb_hash_to_rgba_with_options`] produces for a ThumbHash when the
+/// longer side is `max_size` pixels, without decoding any pixels.
+///
+/// An error will be returned if the input is too short.
+pub fn decoded_dimensions_for(hash: &[u8], max_size: usize) -> Result<(usize, usize), ()> {
+    let options = DecodeOptions {
+        max_size,
+        ..DecodeOptions::default()
+    };
+    decoded_dimensions_with_options(hash, &options)
+}
+
+/// Returns the width and height of the image that
+/// [`thumb_hash_to_rgba_with_options`] produces for a ThumbHash with the given
+/// options, without decoding any pixels.
+///
+/// An error will be returned if the input is too short.
+pub fn decoded_dimensions_with_options(
+    hash: &[u8],
+    options: &DecodeOptions,
+) -> Result<(usize, usize), ()> {
+    Ok(options.size(&Channels::read(hash)?))
+}
+
+/// Decodes a ThumbHash onto a canvas that may be larger than the placeholder.
+///
+/// The placeholder is rendered at its usual size in the center of a `w` by `h`
+/// RGBA canvas, and the area around it is filled by clamping the image at its
+/// borders. This gives a smoothly extended backdrop that blends into the
+/// placeholder, which is useful for "ambilight" effects. RGB is not
+/// premultiplied by A. An error will be returned if the input is too short.
+pub fn thumb_hash_to_rgba_on_canvas(hash: &[u8], w: usize, h: usize) -> Result<Vec<u8>, ()> {
+    let channels = Channels::read(hash)?;
+    let (inner_w, inner_h) = channels.size(32);
+    let basis = Basis::new(&channels);
+    let offset_x = ((w as isize - inner_w as isize) / 2) as f32;
+    let offset_y = ((h as isize - inner_h as isize) / 2) as f32;
+    let mut rgba = Vec::with_capacity(w * h * 4);
+    for y in 0..h {
+        let ty = PI / inner_h as f32 * (y as f32 + 0.5 - offset_y).clamp(0.5, inner_h as f32 - 0.5);
+        for x in 0..w {
+            let tx =
+                PI / inner_w as f32 * (x as f32 + 0.5 - offset_x).clamp(0.5, inner_w as f32 - 0.5);
+            rgba.extend_from_slice(&lpqa_to_rgba_u8(basis.lpqa(tx, ty)));
+        }
+    }
+    Ok(rgba)
+}
+
+/// Decodes a ThumbHash to an RGBA image that tiles without seams, which is
+/// useful for repeating backgrounds in headers and empty states.
+///
+/// The image is twice the usual width and height. The placeholder is in the
+/// top-left quarter and the other quarters are reflections of it, which is
+/// what the cosines in the hash give when evaluated past the edges of the
+/// image, so every edge matches the opposite one. Returns the width, height,
+/// and pixels of the image. RGB is not premultiplied by A. An error will be
+/// returned if the input is too short.
+pub fn thumb_hash_to_rgba_tileable(hash: &[u8]) -> Result<(usize, usize, Vec<u8>), ()> {
+    let channels = Channels::read(hash)?;
+    let (w, h) = channels.size(32);
+    let basis = Basis::new(&channels);
+    let mut rgba = Vec::with_capacity(w * h * 16);
+    for y in 0..h * 2 {
+        let ty = PI / h as f32 * (y as f32 + 0.5);
+        for x in 0..w * 2 {
+            let tx = PI / w as f32 * (x as f32 + 0.5);
+            rgba.extend_from_slice(&lpqa_to_rgba_u8(basis.lpqa(tx, ty)));
+        }
+    }
+    Ok((w * 2, h * 2, rgba))
+}
+
+/// Decodes a ThumbHash to an opaque RGBA image with transparent areas drawn
+/// over a checkerboard, the way image editors show alpha. This is meant for
+/// debugging and inspecting hashes in tools.
+///
+/// The checkerboard alternates between white and light gray cells that are
+/// `cell_size` pixels wide, starting with white in the top-left corner.
+/// Returns the width, height, and pixels of the image. An error will be
+/// returned if the input is too short.
+pub fn thumb_hash_to_rgba_on_checkerboard(
+    hash: &[u8],
+    cell_size: usize,
+) -> Result<(usize, usize, Vec<u8>), ()> {
+    assert!(cell_size > 0);
+    let channels = Channels::read(hash)?;
+    let (w, h) = channels.size(32);
+    let mut rgba = channels.render(w, h);
+    for (i, pixel) in rgba.chunks_exact_mut(4).enumerate() {
+        let (x, y) = (i % w / cell_size, i / w / cell_size);
+        let background = if (x + y) % 2 == 0 { 255 } else { 204 };
+        let [r, g, b] = blend_over(pixel, [background; 3]);
+        pixel.copy_from_slice(&[r, g, b, 255]);
+    }
+    Ok((w, h, rgba))
+}
+
+/// Decodes a ThumbHash to an opaque RGB image blended over a background
+/// color, for surfaces with a known background that don't want to blend alpha
+/// when drawing.
+///
+/// The pixels have 3 bytes each instead of 4. Returns the width, height, and
+/// pixels of the image. An error will be returned if the input is too short.
+pub fn thumb_hash_to_rgb_over(
+    hash: &[u8],
+    background: [u8; 3],
+) -> Result<(usize, usize, Vec<u8>), ()> {
+    let channels = Channels::read(hash)?;
+    let (w, h) = channels.size(32);
+    let rgb = channels
+        .render(w, h)
+        .chunks_exact(4)
+        .flat_map(|pixel| blend_over(pixel, background))
+        .collect();
+    Ok((w, h, rgb))
+}
+
+/// Blends an RGBA pixel that isn't premultiplied over an opaque color.
+fn blend_over(rgba: &[u8], background: [u8; 3]) -> [u8; 3] {
+    let a = rgba[3] as u32;
+    let blend =
+        |c: u8, background: u8| ((c as u32 * a + background as u32 * (255 - a) + 127) / 255) as u8;
+    [
+        blend(rgba[0], background[0]),
+        blend(rgba[1], background[1]),
+        blend(rgba[2], background[2]),
+    ]
+}
+
+/// Decodes a ThumbHash at its usual size and then scales it to `w` by `h`
+/// pixels with bilinear filtering, for when the placeholder can't be scaled
+/// when it's displayed (e.g. on e-ink devices or when generating PDFs).
+///
+/// Colors are weighted by alpha while filtering so transparent pixels don't
+/// bleed into their neighbors. RGB is not premultiplied by A in the result. An
+/// error will be returned if the input is too short.
+pub fn decode_and_upscale(hash: &[u8], w: usize, h: usize) -> Result<Vec<u8>, ()> {
+    let channels = Channels::read(hash)?;
+    let (src_w, src_h) = channels.size(32);
+    let src = channels.render(src_w, src_h);
+
+    // Pixel centers line up, and samples past the edges are clamped
+    let sample = |t: usize, size: usize, src_size: usize| {
+        let s = ((t as f32 + 0.5) * src_size as f32 / size as f32 - 0.5).max(0.0);
+        let i = (s as usize).min(src_size - 1);
+        (i, (i + 1).min(src_size - 1), s - i as f32)
+    };
+    let columns: Vec<_> = (0..w).map(|x| sample(x, w, src_w)).collect();
+    let mut rgba = Vec::with_capacity(w * h * 4);
+    for y in 0..h {
+        let (y0, y1, fy) = sample(y, h, src_h);
+        for &(x0, x1, fx) in &columns {
+            let mut sum = [0.0; 4];
+            for (x, y, f) in [
+                (x0, y0, (1.0 - fx) * (1.0 - fy)),
+                (x1, y0, fx * (1.0 - fy)),
+                (x0, y1, (1.0 - fx) * fy),
+                (x1, y1, fx * fy),
+            ] {
+                let i = (x + y * src_w) * 4;
+                let a = src[i + 3] as f32 * f;
+                sum[0] += src[i] as f32 * a;
+                sum[1] += src[i + 1] as f32 * a;
+                sum[2] += src[i + 2] as f32 * a;
+                sum[3] += a;
+            }
+            let a = sum[3];
+            let rgb = |c: f32| if a > 0.0 { (c / a).round() as u8 } else { 0 };
+            rgba.extend([rgb(sum[0]), rgb(sum[1]), rgb(sum[2]), a.round() as u8]);
+        }
+    }
+    Ok(rgba)
+}
+
+/// Decodes a ThumbHash to a complete mip chain for uploading to the GPU, which
+/// avoids shimmering when placeholders are drawn small. The hash is only
+/// parsed once for every level.
+///
+/// Level 0 is the same as from [`thumb_hash_to_rgba_with_options`] with the
+/// longer side being `max_size` pixels. Each level after that is half the size
+/// of the one before (rounded down, and at least 1 pixel) down to 1x1, and each
+/// pixel is the exact average of the placeholder over its area. Returns the
+/// width, height, and RGBA pixels of each level. RGB is not premultiplied by A.
+/// An error will be returned if the input is too short.
+pub fn thumb_hash_to_mipmaps(
+    hash: &[u8],
+    max_size: usize,
+) -> Result<Vec<(usize, usize, Vec<u8>)>, ()> {
+    let channels = Channels::read(hash)?;
+    let basis = Basis::new(&channels);
+    let (nx, ny) = (basis.nx(), basis.ny());
+    let (mut w, mut h) = channels.size(max_size);
+    let mut levels = Vec::new();
+    loop {
+        // Averaging a cosine over a pixel scales it by sinc(frequency / 2)
+        let average = !levels.is_empty();
+        let factors = |size: usize, n: usize| -> Vec<Vec<f32>> {
+            (0..size)
+                .map(|i| {
+                    let t = PI / size as f32 * (i as f32 + 0.5);
+                    (0..n)
+                        .map(|c| {
+                            let f = (t * c as f32).cos();
+                            let u = PI * c as f32 / (2 * size) as f32;
+                            if average && c > 0 {
+                                f * u.sin() / u
+                            } else {
+                                f
+                            }
+                        })
+                        .collect()
+                })
+                .collect()
+        };
+        let (columns, rows) = (factors(w, nx), factors(h, ny));
+        let mut rgba = Vec::with_capacity(w * h * 4);
+        for fy in &rows {
+            for fx in &columns {
+                rgba.extend_from_slice(&lpqa_to_rgba_u8(basis.lpqa_with_factors(fx, fy)));
+            }
+        }
+        levels.push((w, h, rgba));
+        if w == 1 && h == 1 {
+            return Ok(levels);
+        }
+        (w, h) = ((w / 2).max(1), (h / 2).max(1));
+    }
+}
+
+/// Many decoded ThumbHashes packed into one image, returned by
+/// [`decode_atlas`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedAtlas {
+    /// The width of the atlas.
+    pub width: usize,
+
+    /// The height of the atlas.
+    pub height: usize,
+
+    /// The pixels of the atlas, row-by-row. RGB is not premultiplied by A, and
+    /// pixels that aren't covered by a placeholder are transparent black.
+    pub rgba: Vec<u8>,
+
+    /// Where each placeholder is in the atlas, in the same order as the input
+    /// hashes. Each rect is `(u0, v0, u1, v1)` in texture coordinates from 0
+    /// to 1, where `(u0, v0)` is the top-left corner.
+    pub rects: Vec<(f32, f32, f32, f32)>,
+}
+
+/// Decodes many ThumbHashes into one RGBA image, so they can be uploaded to
+/// the GPU as one texture instead of one texture each.
+///
+/// Each placeholder gets a `cell_size` by `cell_size` cell in a grid that's
+/// roughly square, and is decoded so its longer side is `cell_size` pixels. An
+/// error will be returned if any input is too short.
+pub fn decode_atlas<H: AsRef<[u8]>>(hashes: &[H], cell_size: usize) -> Result<DecodedAtlas, ()> {
+    let channels = hashes
+        .iter()
+        .map(|hash| Channels::read(hash.as_ref()))
+        .collect::<Result<Vec<_>, ()>>()?;
+    assert!(cell_size > 0);
+    let columns = (hashes.len() as f32).sqrt().ceil() as usize;
+    let rows = hashes.len().div_ceil(columns.max(1));
+    let (width, height) = (columns * cell_size, rows * cell_size);
+    let mut rgba = vec![0; width * height * 4];
+    let mut rects = Vec::with_capacity(hashes.len());
+    for (i, channels) in channels.iter().enumerate() {
+        let (x, y) = (i % columns * cell_size, i / columns * cell_size);
+        let (w, h) = channels.size(cell_size);
+        channels.render_to(&mut rgba, width * 4, (x, y, w, h));
+        rects.push((
+            x as f32 / width as f32,
+            y as f32 / height as f32,
+            (x + w) as f32 / width as f32,
+            (y + h) as f32 / height as f32,
+        ));
+    }
+    Ok(DecodedAtlas {
+        width,
+        height,
+        rgba,
+        rects,
+    })
+}
+
+/// Decodes a ThumbHash straight into part of a larger RGBA buffer, such as a
+/// frame buffer, instead of into a new image that then has to be copied.
+///
+/// The placeholder is stretched to fill the rectangle, overwriting what was
+/// there. RGB is not premultiplied by A. An error will be returned if the
+/// input is too short or the rectangle isn't inside the buffer.
+///
+/// * `dst`: The pixels of the buffer, row-by-row.
+/// * `dst_stride`: The number of bytes per row of the buffer.
+/// * `rect`: The `(x, y, width, height)` to decode into.
+pub fn thumb_hash_to_rgba_in_rect(
+    hash: &[u8],
+    dst: &mut [u8],
+    dst_stride: usize,
+    (x, y, w, h): (usize, usize, usize, usize),
+) -> Result<(), ()> {
+    let channels = Channels::read(hash)?;
+    let row_end = x
+        .checked_add(w)
+        .and_then(|end| end.checked_mul(4))
+        .filter(|&end| end <= dst_stride)
+        .ok_or(())?;
+    if h > 0 {
+        let end = y
+            .checked_add(h - 1)
+            .and_then(|last_row| last_row.checked_mul(dst_stride))
+            .and_then(|start| start.checked_add(row_end))
+            .ok_or(())?;
+        if end > dst.len() {
+            return Err(());
+        }
+    }
+    channels.render_to(dst, dst_stride, (x, y, w, h));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample, sample_with_alpha};
+    use crate::{rgba_to_thumb_hash, thumb_hash_to_rgba};
+
+    #[test]
+    fn on_canvas_centers_the_placeholder_and_clamps_around_it() {
+        let hash = rgba_to_thumb_hash(40, 40, &sample(40, 40));
+        let (w, h, rgba) = thumb_hash_to_rgba(&hash).unwrap();
+        let canvas = thumb_hash_to_rgba_on_canvas(&hash, w + 8, h + 4).unwrap();
+        assert_eq!(canvas.len(), (w + 8) * (h + 4) * 4);
+        let pixel = |x: usize, y: usize| &canvas[(x + y * (w + 8)) * 4..][..4];
+        for y in 0..h {
+            for x in 0..w {
+                assert_eq!(pixel(x + 4, y + 2), &rgba[(x + y * w) * 4..][..4]);
+            }
+        }
+        assert_eq!(pixel(0, 0), pixel(4, 2));
+        assert_eq!(pixel(w + 7, h + 3), pixel(w + 3, h + 1));
+        assert_eq!(thumb_hash_to_rgba_on_canvas(&[], 10, 10), Err(()));
+    }
+
+    #[test]
+    fn decoded_dimensions_matches_the_decoder() {
+        for (w, h) in [(40, 40), (40, 30), (30, 40), (100, 20), (5, 60)] {
+            let hash = rgba_to_thumb_hash(w, h, &sample(w, h));
+            let (decoded_w, decoded_h, _) = thumb_hash_to_rgba(&hash).unwrap();
+            assert_eq!(decoded_dimensions(&hash), Ok((decoded_w, decoded_h)));
+        }
+        assert_eq!(decoded_dimensions(&[]), Err(()));
+    }
+
+    #[test]
+    fn decoded_dimensions_for_scales_the_longer_side() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        for max_size in [1, 16, 32, 100] {
+            let options = DecodeOptions {
+                max_size,
+                ..DecodeOptions::default()
+            };
+            let (w, h, _) = thumb_hash_to_rgba_with_options(&hash, &options).unwrap();
+            assert_eq!(decoded_dimensions_for(&hash, max_size), Ok((w, h)));
+            assert_eq!(decoded_dimensions_with_options(&hash, &options), Ok((w, h)));
+            assert_eq!(w, max_size);
+        }
+        assert_eq!(decoded_dimensions_for(&hash, 32), decoded_dimensions(&hash));
+    }
+
+    #[test]
+    fn snap_aspect_ratio_picks_a_common_ratio() {
+        let snap = DecodeOptions {
+            snap_aspect_ratio: true,
+            ..DecodeOptions::default()
+        };
+        for (w, h, snapped) in [(40, 30, (32, 24)), (30, 40, (24, 32)), (64, 36, (32, 18))] {
+            let hash = rgba_to_thumb_hash(w, h, &sample(w, h));
+            assert_eq!(decoded_dimensions_with_options(&hash, &snap), Ok(snapped));
+            let (decoded_w, decoded_h, _) = thumb_hash_to_rgba_with_options(&hash, &snap).unwrap();
+            assert_eq!((decoded_w, decoded_h), snapped);
+        }
+
+        // Panoramas and hashes with the exact ratio are left alone
+        let hash = rgba_to_thumb_hash(100, 20, &sample(100, 20));
+        assert_eq!(
+            decoded_dimensions_with_options(&hash, &snap),
+            decoded_dimensions(&hash)
+        );
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        assert_eq!(decoded_dimensions(&hash), Ok((32, 23)));
+        let exact = crate::thumb_hash_with_aspect_ratio(&hash, 41, 30).unwrap();
+        assert_eq!(decoded_dimensions_with_options(&exact, &snap), Ok((32, 23)));
+    }
+
+    #[test]
+    fn decode_thumb_hash_bundles_the_usual_values() {
+        let mut rgba = sample(40, 30);
+        rgba[3] = 0;
+        let hash = rgba_to_thumb_hash(40, 30, &rgba);
+        let decoded = decode_thumb_hash(&hash).unwrap();
+        let (w, h, rgba) = thumb_hash_to_rgba(&hash).unwrap();
+        assert_eq!((decoded.width, decoded.height, decoded.rgba), (w, h, rgba));
+        assert_eq!(
+            Ok(decoded.average_rgba),
+            crate::thumb_hash_to_average_rgba(&hash)
+        );
+        assert!(decoded.has_alpha);
+        let opaque = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        assert!(!decode_thumb_hash(&opaque).unwrap().has_alpha);
+        assert_eq!(decode_thumb_hash(&[]), Err(()));
+    }
+
+    #[test]
+    fn thumb_hash_to_rgba_into_appends() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let mut rgba = vec![1, 2, 3];
+        let (w, h) = thumb_hash_to_rgba_into(&hash, &mut rgba).unwrap();
+        assert_eq!(rgba[..3], [1, 2, 3]);
+        assert_eq!(
+            (w, h, rgba[3..].to_vec()),
+            thumb_hash_to_rgba(&hash).unwrap()
+        );
+        assert_eq!(thumb_hash_to_rgba_into(&[], &mut rgba), Err(()));
+        assert_eq!(rgba.len(), 3 + w * h * 4);
+    }
+
+    #[test]
+    fn decode_atlas_packs_each_placeholder_into_a_cell() {
+        let hashes = [
+            rgba_to_thumb_hash(40, 40, &sample(40, 40)),
+            rgba_to_thumb_hash(40, 20, &sample(40, 20)),
+            rgba_to_thumb_hash(20, 40, &sample(20, 40)),
+        ];
+        let atlas = decode_atlas(&hashes, 32).unwrap();
+        assert_eq!((atlas.width, atlas.height), (64, 64));
+        for (i, hash) in hashes.iter().enumerate() {
+            let (w, h, rgba) = thumb_hash_to_rgba(hash).unwrap();
+            let (x, y) = (i % 2 * 32, i / 2 * 32);
+            for row in 0..h {
+                let start = ((y + row) * 64 + x) * 4;
+                assert_eq!(atlas.rgba[start..][..w * 4], rgba[row * w * 4..][..w * 4]);
+            }
+            let (u0, v0) = (x as f32 / 64.0, y as f32 / 64.0);
+            let (u1, v1) = ((x + w) as f32 / 64.0, (y + h) as f32 / 64.0);
+            assert_eq!(atlas.rects[i], (u0, v0, u1, v1));
+        }
+
+        // The cell without a placeholder is transparent black
+        assert!(atlas.rgba[(32 * 64 + 32) * 4..][..32 * 4]
+            .iter()
+            .all(|&c| c == 0));
+        assert_eq!(decode_atlas(&[&hashes[0][..], &[]], 32), Err(()));
+    }
+
+    #[test]
+    fn in_rect_only_writes_inside_the_rect() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let (w, h, rgba) = thumb_hash_to_rgba(&hash).unwrap();
+        let stride = (w + 10) * 4 + 3;
+        let mut dst = vec![7; stride * (h + 5)];
+        thumb_hash_to_rgba_in_rect(&hash, &mut dst, stride, (6, 2, w, h)).unwrap();
+        for y in 0..h + 5 {
+            let row = &dst[y * stride..][..stride];
+            if (2..h + 2).contains(&y) {
+                assert_eq!(row[24..][..w * 4], rgba[(y - 2) * w * 4..][..w * 4]);
+                assert!(row[..24].iter().chain(&row[24 + w * 4..]).all(|&c| c == 7));
+            } else {
+                assert!(row.iter().all(|&c| c == 7));
+            }
+        }
+        assert_eq!(
+            thumb_hash_to_rgba_in_rect(&[], &mut dst, stride, (0, 0, 1, 1)),
+            Err(())
+        );
+    }
+
+    #[test]
+    fn in_rect_rejects_rects_outside_the_buffer() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let stride = 40;
+        let mut dst = vec![7; stride * 10];
+        for rect in [
+            (0, 0, 11, 1),
+            (8, 0, 3, 1),
+            (0, 0, 1, 11),
+            (0, 9, 1, 2),
+            (usize::MAX, 0, 1, 1),
+            (0, usize::MAX, 1, 1),
+            (0, 1, 1, usize::MAX),
+        ] {
+            assert_eq!(
+                thumb_hash_to_rgba_in_rect(&hash, &mut dst, stride, rect),
+                Err(())
+            );
+        }
+        assert!(dst.iter().all(|&c| c == 7));
+        assert_eq!(
+            thumb_hash_to_rgba_in_rect(&hash, &mut dst, stride, (0, 0, 10, 10)),
+            Ok(())
+        );
+    }
+
+    fn options_render(hash: &[u8], options: DecodeOptions) -> (usize, usize, Vec<u8>) {
+        thumb_hash_to_rgba_with_options(hash, &options).unwrap()
+    }
+
+    fn max_difference(a: &[u8], b: &[u8]) -> u8 {
+        assert_eq!(a.len(), b.len());
+        a.iter().zip(b).map(|(a, b)| a.abs_diff(*b)).max().unwrap()
+    }
+
+    #[test]
+    fn color_space_converts_saturated_colors() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        assert_eq!(
+            options_render(&hash, DecodeOptions::default()),
+            thumb_hash_to_rgba(&hash).unwrap()
+        );
+
+        // Pure red is inside Display-P3, so it needs less red and some green
+        let red: Vec<u8> = (0..16 * 16).flat_map(|_| [255, 0, 0, 255]).collect();
+        let red = rgba_to_thumb_hash(16, 16, &red);
+        let (_, _, srgb) = thumb_hash_to_rgba(&red).unwrap();
+        let p3 = DecodeOptions {
+            color_space: ColorSpace::DisplayP3,
+            ..DecodeOptions::default()
+        };
+        let (_, _, display_p3) = options_render(&red, p3.clone());
+        assert!(display_p3[0] < srgb[0] && display_p3[1] > srgb[1]);
+        assert_eq!(display_p3[3], srgb[3]);
+
+        // Grays are the same in both
+        let gray: Vec<u8> = (0..16 * 16).flat_map(|_| [128, 128, 128, 255]).collect();
+        let gray = rgba_to_thumb_hash(16, 16, &gray);
+        let (_, _, srgb) = thumb_hash_to_rgba(&gray).unwrap();
+        assert!(max_difference(&options_render(&gray, p3).2, &srgb) <= 1);
+    }
+
+    #[test]
+    fn dither_only_rounds_differently() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let dither = DecodeOptions {
+            dither: true,
+            ..DecodeOptions::default()
+        };
+        let (_, _, plain) = thumb_hash_to_rgba(&hash).unwrap();
+        let (_, _, dithered) = options_render(&hash, dither.clone());
+        assert_eq!(options_render(&hash, dither).2, dithered);
+        assert_ne!(dithered, plain);
+        assert!(max_difference(&dithered, &plain) <= 1);
+    }
+
+    #[test]
+    fn blur_smooths_the_placeholder() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let (_, _, plain) = thumb_hash_to_rgba(&hash).unwrap();
+        let blur = |blur: f32| {
+            let options = DecodeOptions {
+                blur,
+                ..DecodeOptions::default()
+            };
+            options_render(&hash, options).2
+        };
+        assert_eq!(blur(0.0), plain);
+        let range = |rgba: &[u8]| {
+            let red = rgba.iter().step_by(4);
+            red.clone().max().unwrap() - red.min().unwrap()
+        };
+        assert!(range(&blur(4.0)) < range(&plain));
+        assert!(range(&blur(16.0)) < range(&blur(4.0)));
+    }
+
+    #[test]
+    fn detail_fades_to_the_average_color() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let detail = |detail: f32| {
+            let options = DecodeOptions {
+                detail,
+                ..DecodeOptions::default()
+            };
+            options_render(&hash, options).2
+        };
+        assert_eq!(detail(1.0), thumb_hash_to_rgba(&hash).unwrap().2);
+        let flat = detail(0.0);
+        assert!(flat.chunks_exact(4).all(|pixel| pixel == &flat[..4]));
+        let (r, g, b, a) = crate::thumb_hash_to_average_rgba(&hash).unwrap();
+        let average = [r, g, b, a].map(|c| (c * 255.0) as u8);
+        assert!(max_difference(&flat[..4], &average) <= 1);
+    }
+
+    #[test]
+    fn supersample_averages_samples_within_each_pixel() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let supersample = |supersample: usize| {
+            let options = DecodeOptions {
+                supersample,
+                ..DecodeOptions::default()
+            };
+            options_render(&hash, options).2
+        };
+        let plain = thumb_hash_to_rgba(&hash).unwrap().2;
+        assert_eq!(supersample(1), plain);
+        assert!(max_difference(&supersample(4), &plain) <= 4);
+    }
+
+    #[test]
+    fn corner_radius_cuts_out_the_corners() {
+        let hash = rgba_to_thumb_hash(40, 40, &sample(40, 40));
+        let options = DecodeOptions {
+            corner_radius: 8.0,
+            ..DecodeOptions::default()
+        };
+        let (w, h, rounded) = options_render(&hash, options);
+        let (_, _, plain) = thumb_hash_to_rgba(&hash).unwrap();
+        let alpha = |x: usize, y: usize| rounded[(x + y * w) * 4 + 3];
+        for (x, y) in [(0, 0), (w - 1, 0), (0, h - 1), (w - 1, h - 1)] {
+            assert_eq!(alpha(x, y), 0);
+        }
+        assert_eq!(alpha(w / 2, 0), 255);
+        assert_eq!(alpha(0, h / 2), 255);
+        let row = (h / 2 * w) * 4;
+        assert_eq!(rounded[row..][..w * 4], plain[row..][..w * 4]);
+    }
+
+    #[test]
+    fn grain_is_the_same_every_time() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let grain = DecodeOptions {
+            grain: 0.1,
+            ..DecodeOptions::default()
+        };
+        let (_, _, plain) = thumb_hash_to_rgba(&hash).unwrap();
+        let (_, _, grainy) = options_render(&hash, grain.clone());
+        assert_eq!(options_render(&hash, grain).2, grainy);
+        assert_ne!(grainy, plain);
+        assert!(max_difference(&grainy, &plain) <= 13);
+        let alpha = |rgba: &[u8]| rgba.iter().skip(3).step_by(4).copied().collect::<Vec<_>>();
+        assert_eq!(alpha(&grainy), alpha(&plain));
+    }
+
+    #[test]
+    fn vignette_darkens_the_corners() {
+        let gray: Vec<u8> = (0..16 * 16).flat_map(|_| [200, 200, 200, 255]).collect();
+        let hash = rgba_to_thumb_hash(16, 16, &gray);
+        let options = DecodeOptions {
+            vignette: 0.5,
+            ..DecodeOptions::default()
+        };
+        let (w, h, darkened) = options_render(&hash, options);
+        let (_, _, plain) = thumb_hash_to_rgba(&hash).unwrap();
+        let pixel = |rgba: &[u8], x: usize, y: usize| rgba[(x + y * w) * 4];
+        assert!(pixel(&plain, w / 2, h / 2).abs_diff(pixel(&darkened, w / 2, h / 2)) <= 1);
+
+        // The factor reaches 1 - vignette at the very corner of the image
+        let d = 0.5 / w as f32 - 0.5;
+        let corner = pixel(&darkened, 0, 0) as f32 / pixel(&plain, 0, 0) as f32;
+        assert!((corner - (1.0 - 0.5 * 4.0 * d * d)).abs() < 0.01);
+        assert_eq!(darkened[3], 255);
+    }
+
+    #[test]
+    fn planes_interleave_to_the_decoded_pixels() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let (w, h, planes) = thumb_hash_to_planes(&hash).unwrap();
+        let rgba: Vec<u8> = (0..w * h)
+            .flat_map(|i| planes.each_ref().map(|plane| plane[i]))
+            .collect();
+        assert_eq!((w, h, rgba), thumb_hash_to_rgba(&hash).unwrap());
+        assert_eq!(thumb_hash_to_planes(&[]), Err(()));
+    }
+
+    #[test]
+    fn lpqa_converts_to_the_decoded_pixels() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample_with_alpha(40, 30));
+        let (w, h, [l, p, q, a]) = thumb_hash_to_lpqa(&hash).unwrap();
+        let rgba: Vec<u8> = (0..w * h)
+            .flat_map(|i| lpqa_to_rgba_u8((l[i], p[i], q[i], a[i])))
+            .collect();
+        assert_eq!((w, h, rgba), thumb_hash_to_rgba(&hash).unwrap());
+        assert_eq!(thumb_hash_to_lpqa(&[]), Err(()));
+    }
+
+    #[test]
+    fn decode_and_upscale_keeps_pixel_centers() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let (w, h, rgba) = thumb_hash_to_rgba(&hash).unwrap();
+        assert_eq!(decode_and_upscale(&hash, w, h), Ok(rgba.clone()));
+        let upscaled = decode_and_upscale(&hash, w * 4, h * 4).unwrap();
+        assert_eq!(upscaled.len(), w * h * 64);
+        assert_eq!(upscaled[..4], rgba[..4]);
+        assert_eq!(upscaled[upscaled.len() - 4..], rgba[rgba.len() - 4..]);
+        assert_eq!(decode_and_upscale(&[], 10, 10), Err(()));
+    }
+
+    #[test]
+    fn mipmaps_halve_down_to_one_pixel() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let levels = thumb_hash_to_mipmaps(&hash, 64).unwrap();
+        let options = DecodeOptions {
+            max_size: 64,
+            ..DecodeOptions::default()
+        };
+        assert_eq!(
+            levels[0],
+            thumb_hash_to_rgba_with_options(&hash, &options).unwrap()
+        );
+        for pair in levels.windows(2) {
+            let ((w, h, _), (w2, h2, _)) = (&pair[0], &pair[1]);
+            assert_eq!((*w2, *h2), ((w / 2).max(1), (h / 2).max(1)));
+        }
+        for (w, h, rgba) in &levels {
+            assert_eq!(rgba.len(), w * h * 4);
+        }
+        let (w, h, last) = levels.last().unwrap();
+        assert_eq!((*w, *h), (1, 1));
+        let (r, g, b, a) = crate::thumb_hash_to_average_rgba(&hash).unwrap();
+        assert!(max_difference(last, &[r, g, b, a].map(|c| (c * 255.0) as u8)) <= 1);
+        assert_eq!(thumb_hash_to_mipmaps(&[], 64), Err(()));
+    }
+
+    #[test]
+    fn tileable_edges_match_the_opposite_edges() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample(40, 30));
+        let (w, h, rgba) = thumb_hash_to_rgba(&hash).unwrap();
+        let (tw, th, tiled) = thumb_hash_to_rgba_tileable(&hash).unwrap();
+        assert_eq!((tw, th), (w * 2, h * 2));
+        let pixel = |x: usize, y: usize| &tiled[(x + y * tw) * 4..][..4];
+        for y in 0..h {
+            assert_eq!(tiled[y * tw * 4..][..w * 4], rgba[y * w * 4..][..w * 4]);
+        }
+        for x in 0..tw {
+            assert_eq!(pixel(x, 0), pixel(x, th - 1));
+        }
+        for y in 0..th {
+            assert_eq!(pixel(0, y), pixel(tw - 1, y));
+        }
+        assert_eq!(thumb_hash_to_rgba_tileable(&[]), Err(()));
+    }
+
+    fn composite(rgba: &[u8], background: impl Fn(usize) -> [u8; 3]) -> Vec<[u8; 3]> {
+        rgba.chunks_exact(4)
+            .enumerate()
+            .map(|(i, pixel)| {
+                let a = pixel[3] as f32 / 255.0;
+                let bg = background(i);
+                [0, 1, 2].map(|c| (pixel[c] as f32 * a + bg[c] as f32 * (1.0 - a)).round() as u8)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn on_checkerboard_composites_over_the_cells() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample_with_alpha(40, 30));
+        let (w, h, rgba) = thumb_hash_to_rgba(&hash).unwrap();
+        let (cw, ch, checkerboard) = thumb_hash_to_rgba_on_checkerboard(&hash, 4).unwrap();
+        assert_eq!((cw, ch), (w, h));
+        let expected = composite(&rgba, |i| {
+            let (x, y) = (i % w / 4, i / w / 4);
+            [if (x + y) % 2 == 0 { 255 } else { 204 }; 3]
+        });
+        for (pixel, expected) in checkerboard.chunks_exact(4).zip(expected) {
+            assert!(max_difference(&pixel[..3], &expected) <= 1);
+            assert_eq!(pixel[3], 255);
+        }
+        assert_eq!(thumb_hash_to_rgba_on_checkerboard(&[], 4), Err(()));
+    }
+
+    #[test]
+    fn rgb_over_composites_over_the_background() {
+        let hash = rgba_to_thumb_hash(40, 30, &sample_with_alpha(40, 30));
+        let (w, h, rgba) = thumb_hash_to_rgba(&hash).unwrap();
+        let background = [20, 200, 90];
+        let (rw, rh, rgb) = thumb_hash_to_rgb_over(&hash, background).unwrap();
+        assert_eq!((rw, rh, rgb.len()), (w, h, w * h * 3));
+        let expected = composite(&rgba, |_| background);
+        for (pixel, expected) in rgb.chunks_exact(3).zip(expected) {
+            assert!(max_difference(pixel, &expected) <= 1);
+        }
+        assert_eq!(thumb_hash_to_rgb_over(&[], background), Err(()));
+    }
+}
@@ -0,0 +1,28 @@
+use crate::ThumbHash;
+use diesel::backend::Backend;
+use diesel::deserialize::{self, FromSql};
+use diesel::serialize::{self, Output, ToSql};
+use diesel::sql_types::Binary;
+
+// These store a ThumbHash in a binary column (e.g. "bytea" or "BLOB") the same
+// way as a `Vec<u8>`, and check that the bytes can be decoded when reading
+// them back. Only available with the `diesel` feature.
+
+impl<DB: Backend> ToSql<Binary, DB> for ThumbHash
+where
+    [u8]: ToSql<Binary, DB>,
+{
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, DB>) -> serialize::Result {
+        <[u8] as ToSql<Binary, DB>>::to_sql(&self.bytes, out)
+    }
+}
+
+impl<DB: Backend> FromSql<Binary, DB> for ThumbHash
+where
+    Vec<u8>: FromSql<Binary, DB>,
+{
+    fn from_sql(bytes: DB::RawValue<'_>) -> deserialize::Result<Self> {
+        let bytes = <Vec<u8> as FromSql<Binary, DB>>::from_sql(bytes)?;
+        ThumbHash::try_from(bytes).map_err(|()| "invalid ThumbHash".into())
+    }
+}
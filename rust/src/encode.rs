@@ -0,0 +1,691 @@
+use crate::{thumb_hash_to_average_rgba, Channels, Format, HashBuf};
+
+#[cfg(feature = "arrayvec")]
+use crate::MAX_HASH_LEN;
+
+/// Options for [`rgba_to_thumb_hash_with_options`].
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct EncodeOptions {
+    /// The EXIF orientation of the input image, from 1 to 8. The image is
+    /// rotated and flipped to how it's meant to be displayed while it's read,
+    /// so the hash matches what the user sees. Orientations 5 to 8 swap the
+    /// width and height of the hash. The default is 1, which leaves the image
+    /// as-is.
+    pub orientation: u8,
+
+    /// Whether RGB in the input image is premultiplied by A, which is common
+    /// for screenshots from compositors and for GPU readbacks. Premultiplied
+    /// pixels are converted back to straight alpha before encoding, since
+    /// hashing them as-is darkens the edges of transparent areas. The default
+    /// is false.
+    pub premultiplied: bool,
+
+    /// The number of luminance terms along the longer side of the image,
+    /// which is 7 for opaque images and 5 for images with alpha by default.
+    /// More terms keep more structure in detailed images (about a byte for
+    /// each extra term) and fewer terms make the hash smaller. Standard hashes
+    /// can't store other numbers of terms, so setting this (from 1 to 15) to
+    /// anything but the default for the image produces an extended hash (see
+    /// [`rgba_to_thumb_hash_extended`](crate::rgba_to_thumb_hash_extended)).
+    /// The default is `None`.
+    pub luminance_limit: Option<usize>,
+
+    /// The number of chroma terms along each side of the triangles of P and Q
+    /// terms, which is 3 by default. Colorful artwork keeps its colors better
+    /// with more terms, while photos rarely need them. Like
+    /// [`luminance_limit`](EncodeOptions::luminance_limit), setting this (from
+    /// 1 to 7) to anything but 3 produces an extended hash. The default is
+    /// `None`.
+    pub chroma_terms: Option<usize>,
+
+    /// The number of alpha terms along each side of the triangle of A terms,
+    /// which is 5 by default. More terms keep the outline of cutouts such as
+    /// logos and stickers from turning into a blob. Opaque images don't have
+    /// any A terms, so this only matters for images with alpha. Like
+    /// [`luminance_limit`](EncodeOptions::luminance_limit), setting this (from
+    /// 1 to 7) to anything but 5 produces an extended hash. The default is
+    /// `None`.
+    pub alpha_terms: Option<usize>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        EncodeOptions {
+            orientation: 1,
+            premultiplied: false,
+            luminance_limit: None,
+            chroma_terms: None,
+            alpha_terms: None,
+        }
+    }
+}
+
+/// Encodes an RGBA image to a ThumbHash with the given options. The arguments
+/// are the same as for [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash), where
+/// `w` and `h` are the size of the stored pixels before applying the
+/// orientation.
+pub fn rgba_to_thumb_hash_with_options(
+    w: usize,
+    h: usize,
+    rgba: &[u8],
+    options: &EncodeOptions,
+) -> Vec<u8> {
+    assert!(w <= 100 && h <= 100);
+    assert_eq!(rgba.len(), w * h * 4);
+    assert!((1..=8).contains(&options.orientation));
+    assert!(options
+        .luminance_limit
+        .is_none_or(|n| (1..=15).contains(&n)));
+    assert!(options.chroma_terms.is_none_or(|n| (1..=7).contains(&n)));
+    assert!(options.alpha_terms.is_none_or(|n| (1..=7).contains(&n)));
+
+    // Map each displayed pixel back to where it's stored
+    let orientation = options.orientation;
+    let premultiplied = options.premultiplied;
+    let (dw, dh) = if orientation >= 5 { (h, w) } else { (w, h) };
+    let source = move |x: usize, y: usize| match orientation {
+        1 => (x, y),
+        2 => (w - 1 - x, y),
+        3 => (w - 1 - x, h - 1 - y),
+        4 => (x, h - 1 - y),
+        5 => (y, x),
+        6 => (y, h - 1 - x),
+        7 => (w - 1 - y, h - 1 - x),
+        _ => (w - 1 - y, x),
+    };
+    let pixels = || {
+        (0..dh).flat_map(move |y| {
+            (0..dw).map(move |x| {
+                let (sx, sy) = source(x, y);
+                let i = (sx + sy * w) * 4;
+                let pixel = [rgba[i], rgba[i + 1], rgba[i + 2], rgba[i + 3]];
+                if premultiplied {
+                    unpremultiply(pixel)
+                } else {
+                    pixel
+                }
+            })
+        })
+    };
+    // Only a budget that differs from the standard format needs an extended
+    // hash, and the standard budget depends on whether there's alpha
+    Channels::from_pixels_with_sizes(dw, dh, pixels, |has_alpha| {
+        let standard = Format::Standard.sizes(has_alpha);
+        let (l_limit, pq_n, a_n) = standard;
+        let sizes = (
+            options.luminance_limit.unwrap_or(l_limit),
+            options.chroma_terms.unwrap_or(pq_n),
+            if has_alpha {
+                options.alpha_terms.unwrap_or(a_n)
+            } else {
+                0
+            },
+        );
+        if sizes == standard {
+            (Format::Standard, sizes)
+        } else {
+            (Format::Extended, sizes)
+        }
+    })
+    .write()
+}
+
+/// Converts a pixel from premultiplied to straight alpha. Fully transparent
+/// pixels have no color, so they're left as transparent black.
+fn unpremultiply([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    if a == 0 {
+        return [0, 0, 0, 0];
+    }
+    let f = |c: u8| ((c as u32 * 255 + a as u32 / 2) / a as u32).min(255) as u8;
+    [f(r), f(g), f(b), a]
+}
+
+/// A ThumbHash along with the values that are usually stored next to it,
+/// returned by [`encode_with_metadata`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct EncodedThumbHash {
+    /// The bytes of the hash.
+    pub bytes: Vec<u8>,
+
+    /// The average color as RGBA values from 0 to 1, the same as what
+    /// [`thumb_hash_to_average_rgba`] returns for the hash.
+    pub average_rgba: (f32, f32, f32, f32),
+
+    /// The approximate aspect ratio, the same as what
+    /// [`thumb_hash_to_approximate_aspect_ratio`](crate::thumb_hash_to_approximate_aspect_ratio)
+    /// returns for the hash.
+    pub aspect_ratio: f32,
+}
+
+/// Encodes an RGBA image to a ThumbHash and also returns its average color and
+/// approximate aspect ratio, so they don't need to be extracted from the hash
+/// afterward. The arguments are the same as for
+/// [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash).
+pub fn encode_with_metadata(w: usize, h: usize, rgba: &[u8]) -> EncodedThumbHash {
+    assert!(w <= 100 && h <= 100);
+    assert_eq!(rgba.len(), w * h * 4);
+    let channels = Channels::from_rgba(w, h, rgba, Format::Standard);
+    let bytes = channels.write();
+
+    // Use the quantized values so these match what decoding the hash gives
+    let average_rgba = thumb_hash_to_average_rgba(&bytes).unwrap();
+    EncodedThumbHash {
+        bytes,
+        average_rgba,
+        aspect_ratio: channels.lx as f32 / channels.ly as f32,
+    }
+}
+
+/// Like [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash) but returns the hash
+/// inline instead of in a `Vec`, which avoids allocating the result when
+/// encoding many images. Only available with the `arrayvec` feature.
+#[cfg(feature = "arrayvec")]
+pub fn rgba_to_thumb_hash_arrayvec(
+    w: usize,
+    h: usize,
+    rgba: &[u8],
+) -> arrayvec::ArrayVec<u8, MAX_HASH_LEN> {
+    assert!(w <= 100 && h <= 100);
+    assert_eq!(rgba.len(), w * h * 4);
+    let mut hash = arrayvec::ArrayVec::new();
+    Channels::from_rgba(w, h, rgba, Format::Standard).write_standard_to(&mut hash);
+    hash
+}
+
+/// Like [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash) but appends the hash
+/// to `hash` instead of returning a new `Vec`, so the caller decides where the
+/// memory comes from. At most [`MAX_HASH_LEN`](crate::MAX_HASH_LEN) bytes are
+/// appended.
+pub fn rgba_to_thumb_hash_into(w: usize, h: usize, rgba: &[u8], hash: &mut Vec<u8>) {
+    assert!(w <= 100 && h <= 100);
+    assert_eq!(rgba.len(), w * h * 4);
+    let start = hash.len();
+    let channels = Channels::from_rgba(w, h, rgba, Format::Standard);
+    channels.write_standard_to(&mut Appended { vec: hash, start });
+}
+
+/// The end of a `Vec` that a hash is being appended to. This only exposes the
+/// bytes of the new hash so that checksums don't cover what came before it.
+struct Appended<'a> {
+    vec: &'a mut Vec<u8>,
+    start: usize,
+}
+
+impl AsRef<[u8]> for Appended<'_> {
+    fn as_ref(&self) -> &[u8] {
+        &self.vec[self.start..]
+    }
+}
+
+impl AsMut<[u8]> for Appended<'_> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        &mut self.vec[self.start..]
+    }
+}
+
+impl HashBuf for Appended<'_> {
+    fn push(&mut self, byte: u8) {
+        self.vec.push(byte);
+    }
+}
+
+/// Like [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash) but takes one
+/// `[r, g, b, a]` array per pixel, for when the pixels are already in a typed
+/// slice. `pixels` must have `w*h` elements.
+pub fn rgba_pixels_to_thumb_hash(w: usize, h: usize, pixels: &[[u8; 4]]) -> Vec<u8> {
+    assert!(w <= 100 && h <= 100);
+    assert_eq!(pixels.len(), w * h);
+    Channels::from_pixels(w, h, || pixels.iter().copied(), Format::Standard).write()
+}
+
+/// Like [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash) but takes a slice of
+/// any plain-old-data type whose bytes are the pixels in RGBA order (e.g.
+/// `rgb::RGBA8`), which is cast to bytes without copying. The slice must have
+/// `w*h*4` bytes in total. Only available with the `bytemuck` feature.
+#[cfg(feature = "bytemuck")]
+pub fn rgba_pod_to_thumb_hash<P: bytemuck::Pod>(w: usize, h: usize, pixels: &[P]) -> Vec<u8> {
+    crate::rgba_to_thumb_hash(w, h, bytemuck::cast_slice(pixels))
+}
+
+/// Encodes an image that's already split into L, P, Q, and A planes to a
+/// ThumbHash, which skips converting to and from RGBA for pipelines that work
+/// in that color space. The planes are in the same form that
+/// [`thumb_hash_to_lpqa`](crate::thumb_hash_to_lpqa) returns. Unlike with RGBA
+/// input, transparent pixels aren't composited atop the average color first.
+///
+/// * `w`: The width of the input image. Must be ≤100px.
+/// * `h`: The height of the input image. Must be ≤100px.
+/// * `planes`: The L, P, Q, and A values row-by-row. Each must have `w*h` elements.
+pub fn lpqa_to_thumb_hash(w: usize, h: usize, planes: [&[f32]; 4]) -> Vec<u8> {
+    assert!(w <= 100 && h <= 100);
+    assert!(planes.iter().all(|plane| plane.len() == w * h));
+    let has_alpha = planes[3].iter().any(|&a| a < 1.0);
+    let format = Format::Standard;
+    Channels::from_lpqa(w, h, planes, has_alpha, format, format.sizes(has_alpha)).write()
+}
+
+/// Like [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash) but each row of
+/// pixels starts `stride` bytes after the previous one, so padded buffers (e.g.
+/// from video decoders or GPU readbacks) can be hashed without copying them.
+/// The bytes after the first `w*4` bytes of each row are ignored.
+///
+/// * `stride`: The number of bytes per row. Must be at least `w*4`.
+/// * `rgba`: Must have at least `(h-1)*stride + w*4` elements.
+pub fn rgba_to_thumb_hash_with_stride(w: usize, h: usize, rgba: &[u8], stride: usize) -> Vec<u8> {
+    assert!(w <= 100 && h <= 100);
+    assert!(stride >= w * 4);
+    assert!(h == 0 || rgba.len() >= (h - 1) * stride + w * 4);
+    Channels::from_rgba_with_stride(w, h, rgba, stride, Format::Standard).write()
+}
+
+/// Encodes part of an RGBA image to a ThumbHash, which is useful for focal
+/// crops and for sprite sheets that contain many images in one buffer.
+///
+/// * `w`: The width of the whole input image.
+/// * `h`: The height of the whole input image.
+/// * `rgba`: The pixels in the whole input image, row-by-row. Must have `w*h*4` elements.
+/// * `rect`: The `(x, y, width, height)` of the region to encode. The width
+///   and height must be ≤100px.
+///
+/// An error will be returned if the region is empty or isn't inside the image.
+pub fn rgba_region_to_thumb_hash(
+    w: usize,
+    h: usize,
+    rgba: &[u8],
+    (x, y, region_w, region_h): (usize, usize, usize, usize),
+) -> Result<Vec<u8>, ()> {
+    assert_eq!(rgba.len(), w * h * 4);
+    let fits =
+        |start: usize, len: usize, end: usize| start.checked_add(len).is_some_and(|e| e <= end);
+    if region_w == 0 || region_h == 0 || !fits(x, region_w, w) || !fits(y, region_h, h) {
+        return Err(());
+    }
+    let start = (y * w + x) * 4;
+    Ok(rgba_to_thumb_hash_with_stride(
+        region_w,
+        region_h,
+        &rgba[start..],
+        w * 4,
+    ))
+}
+
+/// Averages several frames of a video (or any images that are the same size)
+/// into a single ThumbHash, so the placeholder reflects the overall look of a
+/// clip instead of one frame that might be black or blurry.
+///
+/// Colors are averaged with RGB premultiplied by A, so a pixel that's
+/// transparent in some frames takes its color from the frames where it's
+/// visible.
+#[derive(Clone, Debug)]
+pub struct FrameAverage {
+    w: usize,
+    h: usize,
+    // The sums of R, G, and B premultiplied by A, and of A, for each pixel
+    sum: Vec<u64>,
+    frame_count: usize,
+}
+
+impl FrameAverage {
+    /// Creates an average of no frames with the given size. The width and
+    /// height must be ≤100px.
+    pub fn new(w: usize, h: usize) -> FrameAverage {
+        assert!(w <= 100 && h <= 100);
+        FrameAverage {
+            w,
+            h,
+            sum: vec![0; w * h * 4],
+            frame_count: 0,
+        }
+    }
+
+    /// Adds a frame to the average. The pixels are row-by-row RGBA and must
+    /// have `w*h*4` elements.
+    pub fn add_frame(&mut self, rgba: &[u8]) {
+        assert_eq!(rgba.len(), self.w * self.h * 4);
+        for (sum, pixel) in self.sum.chunks_exact_mut(4).zip(rgba.chunks_exact(4)) {
+            let a = pixel[3] as u64;
+            for i in 0..3 {
+                sum[i] += pixel[i] as u64 * a;
+            }
+            sum[3] += a;
+        }
+        self.frame_count += 1;
+    }
+
+    /// Returns the number of frames that have been added.
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// Encodes the average of the frames to a ThumbHash. An error will be
+    /// returned if no frames have been added.
+    pub fn to_thumb_hash(&self) -> Result<Vec<u8>, ()> {
+        if self.frame_count == 0 {
+            return Err(());
+        }
+        let n = self.frame_count as u64;
+        let mut rgba = Vec::with_capacity(self.sum.len());
+        for sum in self.sum.chunks_exact(4) {
+            // Convert back to straight alpha, leaving transparent pixels black
+            let a = sum[3];
+            for &c in &sum[..3] {
+                rgba.push((c + a / 2).checked_div(a).unwrap_or(0) as u8);
+            }
+            rgba.push(((a + n / 2) / n) as u8);
+        }
+        Ok(crate::rgba_to_thumb_hash(self.w, self.h, &rgba))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample, sample_with_alpha};
+    use crate::{detect_version, rgba_to_thumb_hash, thumb_hash_to_rgba, ThumbHashVersion};
+
+    /// Encodes with a budget and checks that the hash reads back with the
+    /// same number of terms, returning the hash.
+    fn encode_with_budget(
+        rgba: &[u8],
+        options: EncodeOptions,
+        terms: (usize, usize, usize),
+    ) -> Vec<u8> {
+        let hash = rgba_to_thumb_hash_with_options(30, 20, rgba, &options);
+        let channels = Channels::read(&hash).unwrap();
+        assert_eq!(channels.term_counts(), terms);
+        assert_eq!(channels.write(), hash);
+        assert!(thumb_hash_to_rgba(&hash).is_ok());
+        hash
+    }
+
+    #[test]
+    fn rgba_region_to_thumb_hash_matches_a_copy_of_the_region() {
+        let (w, h) = (40, 30);
+        let rgba = sample(w, h);
+        let mut region = Vec::new();
+        for y in 5..25 {
+            region.extend_from_slice(&rgba[(y * w + 10) * 4..(y * w + 30) * 4]);
+        }
+        assert_eq!(
+            rgba_region_to_thumb_hash(w, h, &rgba, (10, 5, 20, 20)),
+            Ok(rgba_to_thumb_hash(20, 20, &region)),
+        );
+        assert_eq!(
+            rgba_region_to_thumb_hash(w, h, &rgba, (0, 0, w, h)),
+            Ok(rgba_to_thumb_hash(w, h, &rgba)),
+        );
+    }
+
+    #[test]
+    fn frame_average_ignores_the_color_of_transparent_pixels() {
+        let mut average = FrameAverage::new(2, 1);
+        average.add_frame(&[255, 0, 0, 255, 0, 0, 255, 0]);
+        average.add_frame(&[0, 255, 0, 0, 0, 0, 255, 0]);
+        average.add_frame(&[255, 0, 0, 255, 0, 0, 255, 0]);
+        assert_eq!(average.frame_count(), 3);
+        let expected = [255, 0, 0, 170, 0, 0, 0, 0];
+        assert_eq!(
+            average.to_thumb_hash(),
+            Ok(rgba_to_thumb_hash(2, 1, &expected))
+        );
+    }
+
+    #[test]
+    fn frame_average_needs_a_frame() {
+        assert_eq!(FrameAverage::new(4, 4).to_thumb_hash(), Err(()));
+    }
+
+    #[test]
+    fn rgba_region_to_thumb_hash_rejects_bad_regions() {
+        let rgba = sample(40, 30);
+        for rect in [
+            (0, 0, 0, 10),
+            (0, 0, 10, 0),
+            (35, 0, 10, 10),
+            (0, 25, 10, 10),
+            (40, 30, 0, 0),
+            (usize::MAX, 0, 2, 2),
+        ] {
+            assert_eq!(rgba_region_to_thumb_hash(40, 30, &rgba, rect), Err(()));
+        }
+    }
+
+    #[test]
+    fn encode_with_metadata_matches_the_hash() {
+        let rgba = sample(40, 30);
+        let encoded = encode_with_metadata(40, 30, &rgba);
+        assert_eq!(encoded.bytes, rgba_to_thumb_hash(40, 30, &rgba));
+        assert_eq!(
+            Ok(encoded.average_rgba),
+            thumb_hash_to_average_rgba(&encoded.bytes)
+        );
+        assert_eq!(
+            Ok(encoded.aspect_ratio),
+            crate::thumb_hash_to_approximate_aspect_ratio(&encoded.bytes)
+        );
+    }
+
+    #[cfg(feature = "arrayvec")]
+    #[test]
+    fn arrayvec_matches_the_vec() {
+        for (w, h) in [(1, 1), (40, 30), (100, 5)] {
+            let rgba = sample(w, h);
+            let hash = rgba_to_thumb_hash_arrayvec(w, h, &rgba);
+            assert_eq!(hash.as_slice(), rgba_to_thumb_hash(w, h, &rgba));
+        }
+    }
+
+    #[test]
+    fn rgba_to_thumb_hash_into_appends() {
+        let rgba = sample(40, 30);
+        let mut hash = vec![1, 2, 3];
+        rgba_to_thumb_hash_into(40, 30, &rgba, &mut hash);
+        assert_eq!(hash[..3], [1, 2, 3]);
+        assert_eq!(hash[3..], rgba_to_thumb_hash(40, 30, &rgba));
+    }
+
+    #[cfg(feature = "scratch")]
+    #[test]
+    fn scratch_buffer_reuse_gives_the_same_hash() {
+        let small = sample(10, 8);
+        let expected = std::thread::spawn(move || rgba_to_thumb_hash(10, 8, &small))
+            .join()
+            .unwrap();
+        rgba_to_thumb_hash(100, 100, &sample(100, 100));
+        assert_eq!(rgba_to_thumb_hash(10, 8, &sample(10, 8)), expected);
+    }
+
+    #[test]
+    fn stride_skips_the_padding_at_the_end_of_each_row() {
+        let (w, h) = (20, 15);
+        let rgba = sample(w, h);
+        let stride = w * 4 + 12;
+        let mut padded = vec![255; (h - 1) * stride + w * 4];
+        for y in 0..h {
+            padded[y * stride..][..w * 4].copy_from_slice(&rgba[y * w * 4..][..w * 4]);
+        }
+        assert_eq!(
+            rgba_to_thumb_hash_with_stride(w, h, &padded, stride),
+            rgba_to_thumb_hash(w, h, &rgba)
+        );
+        assert_eq!(
+            rgba_to_thumb_hash_with_stride(w, h, &rgba, w * 4),
+            rgba_to_thumb_hash(w, h, &rgba)
+        );
+    }
+
+    #[test]
+    fn empty_images_do_not_panic() {
+        assert_eq!(
+            rgba_to_thumb_hash(0, 0, &[]),
+            [0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(rgba_to_thumb_hash(0, 5, &[]).len(), 17);
+        assert_eq!(
+            rgba_to_thumb_hash_with_stride(0, 5, &[], 0),
+            rgba_to_thumb_hash(0, 5, &[])
+        );
+    }
+
+    #[test]
+    fn orientation_encodes_the_displayed_image() {
+        let (w, h) = (30, 20);
+        let rgba = sample(w, h);
+        let pixels: Vec<[u8; 4]> = rgba
+            .chunks_exact(4)
+            .map(|p| p.try_into().unwrap())
+            .collect();
+        let encode = |orientation| {
+            let options = EncodeOptions {
+                orientation,
+                ..EncodeOptions::default()
+            };
+            rgba_to_thumb_hash_with_options(w, h, &rgba, &options)
+        };
+        let hash = |w, h, pixels: Vec<[u8; 4]>| rgba_pixels_to_thumb_hash(w, h, &pixels);
+        assert_eq!(encode(1), rgba_to_thumb_hash(w, h, &rgba));
+
+        // Mirroring reverses each row, and rotating by 180 degrees reverses
+        // every pixel
+        let mirrored = pixels.chunks(w).flat_map(|row| row.iter().rev().copied());
+        assert_eq!(encode(2), hash(w, h, mirrored.collect()));
+        assert_eq!(
+            encode(3),
+            hash(w, h, pixels.iter().rev().copied().collect())
+        );
+
+        // Rotating by 90 degrees clockwise starts each row at the bottom of
+        // a column, and counterclockwise starts it at the top of one
+        let pixels = &pixels;
+        let column = |x: usize| (0..h).map(move |y| pixels[x + y * w]);
+        let clockwise = (0..w).flat_map(|x| column(x).rev());
+        assert_eq!(encode(6), hash(h, w, clockwise.collect()));
+        let counterclockwise = (0..w).rev().flat_map(column);
+        assert_eq!(encode(8), hash(h, w, counterclockwise.collect()));
+    }
+
+    #[test]
+    fn luminance_limit_sets_the_luminance_budget() {
+        let (opaque, alpha) = (sample(30, 20), sample_with_alpha(30, 20));
+        let limit = |luminance_limit| EncodeOptions {
+            luminance_limit: Some(luminance_limit),
+            ..EncodeOptions::default()
+        };
+
+        // The standard budget gives a standard hash
+        let standard = encode_with_budget(&opaque, limit(7), (7, 3, 0));
+        assert_eq!(standard, rgba_to_thumb_hash(30, 20, &opaque));
+        let standard = encode_with_budget(&alpha, limit(5), (5, 3, 5));
+        assert_eq!(standard, rgba_to_thumb_hash(30, 20, &alpha));
+
+        let more = encode_with_budget(&opaque, limit(11), (11, 3, 0));
+        assert_eq!(detect_version(&more), Ok(ThumbHashVersion::Extended));
+        let fewer = encode_with_budget(&opaque, limit(3), (3, 3, 0));
+        assert_eq!(detect_version(&fewer), Ok(ThumbHashVersion::Extended));
+        assert!(fewer.len() < standard.len() && standard.len() < more.len());
+    }
+
+    #[test]
+    fn chroma_terms_sets_the_chroma_budget() {
+        let (opaque, alpha) = (sample(30, 20), sample_with_alpha(30, 20));
+        let terms = |chroma_terms| EncodeOptions {
+            chroma_terms: Some(chroma_terms),
+            ..EncodeOptions::default()
+        };
+        let standard = encode_with_budget(&opaque, terms(3), (7, 3, 0));
+        assert_eq!(standard, rgba_to_thumb_hash(30, 20, &opaque));
+        let with_alpha = encode_with_budget(&alpha, terms(3), (5, 3, 5));
+        assert_eq!(with_alpha, rgba_to_thumb_hash(30, 20, &alpha));
+
+        let more = encode_with_budget(&opaque, terms(6), (7, 6, 0));
+        assert_eq!(detect_version(&more), Ok(ThumbHashVersion::Extended));
+        let fewer = encode_with_budget(&opaque, terms(1), (7, 1, 0));
+        assert_eq!(detect_version(&fewer), Ok(ThumbHashVersion::Extended));
+        assert!(fewer.len() < more.len());
+    }
+
+    #[test]
+    fn alpha_terms_sets_the_alpha_budget() {
+        let (opaque, alpha) = (sample(30, 20), sample_with_alpha(30, 20));
+        let terms = |alpha_terms| EncodeOptions {
+            alpha_terms: Some(alpha_terms),
+            ..EncodeOptions::default()
+        };
+        let standard = encode_with_budget(&alpha, terms(5), (5, 3, 5));
+        assert_eq!(standard, rgba_to_thumb_hash(30, 20, &alpha));
+
+        // Opaque images don't have an alpha budget to change
+        let opaque_hash = encode_with_budget(&opaque, terms(7), (7, 3, 0));
+        assert_eq!(opaque_hash, rgba_to_thumb_hash(30, 20, &opaque));
+
+        let more = encode_with_budget(&alpha, terms(7), (5, 3, 7));
+        assert_eq!(detect_version(&more), Ok(ThumbHashVersion::Extended));
+        let fewer = encode_with_budget(&alpha, terms(2), (5, 3, 2));
+        assert_eq!(detect_version(&fewer), Ok(ThumbHashVersion::Extended));
+        assert!(fewer.len() < more.len());
+    }
+
+    #[test]
+    fn typed_pixels_match_the_bytes() {
+        let rgba = sample_with_alpha(30, 20);
+        let pixels: Vec<[u8; 4]> = rgba
+            .chunks_exact(4)
+            .map(|p| p.try_into().unwrap())
+            .collect();
+        let hash = rgba_to_thumb_hash(30, 20, &rgba);
+        assert_eq!(rgba_pixels_to_thumb_hash(30, 20, &pixels), hash);
+        #[cfg(feature = "bytemuck")]
+        {
+            assert_eq!(crate::rgba_pod_to_thumb_hash(30, 20, &pixels), hash);
+            let words: Vec<u32> = pixels.iter().map(|&p| u32::from_ne_bytes(p)).collect();
+            assert_eq!(crate::rgba_pod_to_thumb_hash(30, 20, &words), hash);
+        }
+    }
+
+    #[test]
+    fn lpqa_planes_match_the_rgba_image() {
+        let rgba = sample(30, 20);
+        let mut planes = [(); 4].map(|()| Vec::with_capacity(30 * 20));
+        for pixel in rgba.chunks_exact(4) {
+            // Scale the same way the encoder does so the planes match exactly
+            let a = pixel[3] as f32 / 255.0;
+            let [r, g, b] = [0, 1, 2].map(|i| a / 255.0 * pixel[i] as f32);
+            let lpqa = [(r + g + b) / 3.0, (r + g) / 2.0 - b, r - g, a];
+            for (plane, value) in planes.iter_mut().zip(lpqa) {
+                plane.push(value);
+            }
+        }
+        let [l, p, q, a] = &planes;
+        assert_eq!(
+            lpqa_to_thumb_hash(30, 20, [l, p, q, a]),
+            rgba_to_thumb_hash(30, 20, &rgba)
+        );
+    }
+
+    #[test]
+    fn premultiplied_input_hashes_like_straight_alpha() {
+        // Premultiplying loses some precision, but not enough to change the hash
+        let mut straight = sample(30, 20);
+        for (i, pixel) in straight.chunks_exact_mut(4).enumerate() {
+            pixel[3] = [255, 0, 85, 51][i % 4];
+            if pixel[3] == 0 {
+                pixel[..3].fill(0);
+            }
+        }
+        let premultiplied: Vec<u8> = straight
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let f = |c: u8| ((c as u32 * p[3] as u32 + 127) / 255) as u8;
+                [f(p[0]), f(p[1]), f(p[2]), p[3]]
+            })
+            .collect();
+        let options = EncodeOptions {
+            premultiplied: true,
+            ..EncodeOptions::default()
+        };
+        let hash = rgba_to_thumb_hash_with_options(30, 20, &premultiplied, &options);
+        assert_eq!(hash, rgba_to_thumb_hash(30, 20, &straight));
+        assert_ne!(hash, rgba_to_thumb_hash(30, 20, &premultiplied));
+    }
+}
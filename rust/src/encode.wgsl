@@ -0,0 +1,54 @@
+// Computes the DCT of a batch of images for `BatchEncoder` in "gpu.rs". Each
+// workgroup sums one basis function for one image, and the CPU turns the sums
+// into hashes. This mirrors `Channels::from_pixels` in "lib.rs".
+
+struct Image {
+    // The index of the first pixel of the image in `pixels`
+    offset: u32,
+    w: u32,
+    h: u32,
+    // The average color (RGB), which transparent pixels are composited atop
+    average: vec3f,
+}
+
+@group(0) @binding(0) var<storage, read> images: array<Image>;
+@group(0) @binding(1) var<storage, read> pixels: array<u32>;
+@group(0) @binding(2) var<storage, read_write> dct: array<vec4f>;
+
+const PI = 3.14159265358979;
+const SIZE = 7u; // The number of basis functions along each axis
+const THREADS = 64u;
+
+var<workgroup> sums: array<vec4f, THREADS>;
+
+@compute @workgroup_size(THREADS)
+fn main(@builtin(workgroup_id) group: vec3u, @builtin(local_invocation_index) thread: u32) {
+    let image = images[group.y];
+    let cx = f32(group.x % SIZE);
+    let cy = f32(group.x / SIZE);
+    let count = image.w * image.h;
+
+    // Convert each pixel from RGBA to LPQA and multiply it by the basis function
+    var sum = vec4f(0.0);
+    for (var i = thread; i < count; i += THREADS) {
+        let rgba = unpack4x8unorm(pixels[image.offset + i]);
+        let rgb = image.average * (1.0 - rgba.a) + rgba.rgb * rgba.a;
+        let lpqa = vec4f((rgb.r + rgb.g + rgb.b) / 3.0, (rgb.r + rgb.g) / 2.0 - rgb.b, rgb.r - rgb.g, rgba.a);
+        let x = f32(i % image.w) + 0.5;
+        let y = f32(i / image.w) + 0.5;
+        sum += lpqa * cos(PI / f32(image.w) * cx * x) * cos(PI / f32(image.h) * cy * y);
+    }
+
+    // Add up the sums from every thread
+    sums[thread] = sum;
+    workgroupBarrier();
+    for (var stride = THREADS / 2u; stride > 0u; stride /= 2u) {
+        if thread < stride {
+            sums[thread] += sums[thread + stride];
+        }
+        workgroupBarrier();
+    }
+    if thread == 0u {
+        dct[group.x + group.y * SIZE * SIZE] = sums[0] / f32(count);
+    }
+}
@@ -0,0 +1,418 @@
+//! A C API for using this crate from other languages. The header for it is in
+//! "include/thumbhash.h", which is generated by running "cbindgen" in this
+//! directory. Only available with the `ffi` feature.
+//!
+//! The crate is also built as a `cdylib`, so "cargo build --release --features
+//! ffi" produces a shared library (such as "target/release/libthumbhash.so")
+//! that can be linked against directly.
+//!
+//! Dart bindings for Flutter apps can be generated from the header with
+//! "ffigen.yaml" in this directory.
+
+use crate::Channels;
+use std::{ptr, slice};
+
+/// The maximum number of bytes in a hash from `thumbhash_encode`.
+pub const THUMBHASH_MAX_HASH_LEN: usize = 25;
+
+/// The maximum number of bytes in an image from `thumbhash_decode`.
+pub const THUMBHASH_MAX_RGBA_LEN: usize = 32 * 32 * 4;
+
+/// The result of a call to this API.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThumbHashStatus {
+    /// The call succeeded.
+    Ok = 0,
+
+    /// A pointer was null or a size was out of range.
+    InvalidArgument = 1,
+
+    /// The hash couldn't be decoded.
+    InvalidHash = 2,
+
+    /// The output buffer was too small. The sizes that were written say how
+    /// big it needs to be.
+    BufferTooSmall = 3,
+}
+
+/// Encodes an RGBA image to a ThumbHash. RGB should not be premultiplied by A.
+///
+/// The image must be at most 100x100 and `rgba_len` must be `w*h*4`. The hash
+/// is written to `hash` and its length to `hash_len`. A buffer of
+/// `THUMBHASH_MAX_HASH_LEN` bytes is always big enough.
+///
+/// # Safety
+///
+/// `rgba` must point to `rgba_len` readable bytes, `hash` must point to
+/// `hash_capacity` writable bytes, and `hash_len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn thumbhash_encode(
+    w: usize,
+    h: usize,
+    rgba: *const u8,
+    rgba_len: usize,
+    hash: *mut u8,
+    hash_capacity: usize,
+    hash_len: *mut usize,
+) -> ThumbHashStatus {
+    if rgba.is_null() || hash.is_null() || hash_len.is_null() {
+        return ThumbHashStatus::InvalidArgument;
+    }
+    if w > 100 || h > 100 || rgba_len != w * h * 4 {
+        return ThumbHashStatus::InvalidArgument;
+    }
+    let bytes = crate::rgba_to_thumb_hash(w, h, slice::from_raw_parts(rgba, rgba_len));
+    *hash_len = bytes.len();
+    if bytes.len() > hash_capacity {
+        return ThumbHashStatus::BufferTooSmall;
+    }
+    slice::from_raw_parts_mut(hash, bytes.len()).copy_from_slice(&bytes);
+    ThumbHashStatus::Ok
+}
+
+/// Returns the width and height of the image that `thumbhash_decode` produces,
+/// without decoding any pixels.
+///
+/// # Safety
+///
+/// `hash` must point to `hash_len` readable bytes, and `w` and `h` must be
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn thumbhash_decoded_size(
+    hash: *const u8,
+    hash_len: usize,
+    w: *mut usize,
+    h: *mut usize,
+) -> ThumbHashStatus {
+    if hash.is_null() || w.is_null() || h.is_null() {
+        return ThumbHashStatus::InvalidArgument;
+    }
+    match Channels::read(slice::from_raw_parts(hash, hash_len)) {
+        Ok(channels) => {
+            (*w, *h) = channels.size(32);
+            ThumbHashStatus::Ok
+        }
+        Err(()) => ThumbHashStatus::InvalidHash,
+    }
+}
+
+/// Decodes a ThumbHash to an RGBA image. RGB is not premultiplied by A.
+///
+/// The pixels are written to `rgba` row-by-row, and the width and height to
+/// `w` and `h`. A buffer of `THUMBHASH_MAX_RGBA_LEN` bytes is always big
+/// enough.
+///
+/// # Safety
+///
+/// `hash` must point to `hash_len` readable bytes, `rgba` must point to
+/// `rgba_capacity` writable bytes, and `w` and `h` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn thumbhash_decode(
+    hash: *const u8,
+    hash_len: usize,
+    rgba: *mut u8,
+    rgba_capacity: usize,
+    w: *mut usize,
+    h: *mut usize,
+) -> ThumbHashStatus {
+    if hash.is_null() || rgba.is_null() || w.is_null() || h.is_null() {
+        return ThumbHashStatus::InvalidArgument;
+    }
+    let Ok(channels) = Channels::read(slice::from_raw_parts(hash, hash_len)) else {
+        return ThumbHashStatus::InvalidHash;
+    };
+    (*w, *h) = channels.size(32);
+    if *w * *h * 4 > rgba_capacity {
+        return ThumbHashStatus::BufferTooSmall;
+    }
+    let pixels = channels.render(*w, *h);
+    slice::from_raw_parts_mut(rgba, pixels.len()).copy_from_slice(&pixels);
+    ThumbHashStatus::Ok
+}
+
+/// Extracts the average color from a ThumbHash. RGB is not premultiplied by
+/// A. Each value ranges from 0 to 1.
+///
+/// # Safety
+///
+/// `hash` must point to `hash_len` readable bytes, and `r`, `g`, `b`, and `a`
+/// must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn thumbhash_average_rgba(
+    hash: *const u8,
+    hash_len: usize,
+    r: *mut f32,
+    g: *mut f32,
+    b: *mut f32,
+    a: *mut f32,
+) -> ThumbHashStatus {
+    if hash.is_null() || r.is_null() || g.is_null() || b.is_null() || a.is_null() {
+        return ThumbHashStatus::InvalidArgument;
+    }
+    match crate::thumb_hash_to_average_rgba(slice::from_raw_parts(hash, hash_len)) {
+        Ok(rgba) => {
+            (*r, *g, *b, *a) = rgba;
+            ThumbHashStatus::Ok
+        }
+        Err(()) => ThumbHashStatus::InvalidHash,
+    }
+}
+
+/// Extracts the approximate aspect ratio (width / height) of the original
+/// image.
+///
+/// # Safety
+///
+/// `hash` must point to `hash_len` readable bytes, and `ratio` must be
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn thumbhash_approximate_aspect_ratio(
+    hash: *const u8,
+    hash_len: usize,
+    ratio: *mut f32,
+) -> ThumbHashStatus {
+    if hash.is_null() || ratio.is_null() {
+        return ThumbHashStatus::InvalidArgument;
+    }
+    match crate::thumb_hash_to_approximate_aspect_ratio(slice::from_raw_parts(hash, hash_len)) {
+        Ok(value) => {
+            *ratio = value;
+            ThumbHashStatus::Ok
+        }
+        Err(()) => ThumbHashStatus::InvalidHash,
+    }
+}
+
+/// Bytes that were allocated by this API, such as an encoded hash or decoded
+/// image. This is returned by pointer so it can be released with a single call
+/// to `thumbhash_buffer_free`, which makes it easy to attach to a finalizer in
+/// languages with garbage collection (e.g. a `NativeFinalizer` in `dart:ffi`).
+#[repr(C)]
+pub struct ThumbHashBuffer {
+    /// The bytes in the buffer.
+    pub data: *mut u8,
+
+    /// The number of bytes in the buffer.
+    pub len: usize,
+
+    /// The width of the image, or 0 for a hash.
+    pub w: usize,
+
+    /// The height of the image, or 0 for a hash.
+    pub h: usize,
+}
+
+impl ThumbHashBuffer {
+    fn new(bytes: Vec<u8>, w: usize, h: usize) -> *mut ThumbHashBuffer {
+        let len = bytes.len();
+        let data = Box::into_raw(bytes.into_boxed_slice()) as *mut u8;
+        Box::into_raw(Box::new(ThumbHashBuffer { data, len, w, h }))
+    }
+}
+
+/// Like `thumbhash_encode` but returns the hash in a new buffer, or null if
+/// the arguments are invalid. The buffer must be freed with
+/// `thumbhash_buffer_free`.
+///
+/// # Safety
+///
+/// `rgba` must point to `rgba_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn thumbhash_encode_to_buffer(
+    w: usize,
+    h: usize,
+    rgba: *const u8,
+    rgba_len: usize,
+) -> *mut ThumbHashBuffer {
+    if rgba.is_null() || w > 100 || h > 100 || rgba_len != w * h * 4 {
+        return ptr::null_mut();
+    }
+    let hash = crate::rgba_to_thumb_hash(w, h, slice::from_raw_parts(rgba, rgba_len));
+    ThumbHashBuffer::new(hash, 0, 0)
+}
+
+/// Like `thumbhash_decode` but returns the image in a new buffer, or null if
+/// the hash couldn't be decoded. The buffer must be freed with
+/// `thumbhash_buffer_free`.
+///
+/// # Safety
+///
+/// `hash` must point to `hash_len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn thumbhash_decode_to_buffer(
+    hash: *const u8,
+    hash_len: usize,
+) -> *mut ThumbHashBuffer {
+    if hash.is_null() {
+        return ptr::null_mut();
+    }
+    match crate::thumb_hash_to_rgba(slice::from_raw_parts(hash, hash_len)) {
+        Ok((w, h, rgba)) => ThumbHashBuffer::new(rgba, w, h),
+        Err(()) => ptr::null_mut(),
+    }
+}
+
+/// Frees a buffer returned by this API. Passing null does nothing.
+///
+/// # Safety
+///
+/// `buffer` must be null or a buffer from this API that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn thumbhash_buffer_free(buffer: *mut ThumbHashBuffer) {
+    if buffer.is_null() {
+        return;
+    }
+    let buffer = Box::from_raw(buffer);
+    drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+        buffer.data,
+        buffer.len,
+    )));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sample;
+
+    #[test]
+    fn round_trips_through_the_c_api() {
+        let rgba = sample(40, 30);
+        let mut hash = [0; THUMBHASH_MAX_HASH_LEN];
+        let mut hash_len = 0;
+        let status = unsafe {
+            thumbhash_encode(
+                40,
+                30,
+                rgba.as_ptr(),
+                rgba.len(),
+                hash.as_mut_ptr(),
+                hash.len(),
+                &mut hash_len,
+            )
+        };
+        assert_eq!(status, ThumbHashStatus::Ok);
+        let hash = &hash[..hash_len];
+        assert_eq!(hash, crate::rgba_to_thumb_hash(40, 30, &rgba));
+
+        let (mut w, mut h) = (0, 0);
+        let status = unsafe { thumbhash_decoded_size(hash.as_ptr(), hash.len(), &mut w, &mut h) };
+        assert_eq!((status, w, h), (ThumbHashStatus::Ok, 32, 23));
+        let mut pixels = [0; THUMBHASH_MAX_RGBA_LEN];
+        let status = unsafe {
+            thumbhash_decode(
+                hash.as_ptr(),
+                hash.len(),
+                pixels.as_mut_ptr(),
+                pixels.len(),
+                &mut w,
+                &mut h,
+            )
+        };
+        assert_eq!(status, ThumbHashStatus::Ok);
+        assert_eq!(
+            (w, h, pixels[..w * h * 4].to_vec()),
+            crate::thumb_hash_to_rgba(hash).unwrap()
+        );
+
+        let (mut r, mut g, mut b, mut a) = (0.0, 0.0, 0.0, 0.0);
+        let status = unsafe {
+            thumbhash_average_rgba(hash.as_ptr(), hash.len(), &mut r, &mut g, &mut b, &mut a)
+        };
+        assert_eq!(status, ThumbHashStatus::Ok);
+        assert_eq!(Ok((r, g, b, a)), crate::thumb_hash_to_average_rgba(hash));
+        let mut ratio = 0.0;
+        let status =
+            unsafe { thumbhash_approximate_aspect_ratio(hash.as_ptr(), hash.len(), &mut ratio) };
+        assert_eq!(status, ThumbHashStatus::Ok);
+        assert_eq!(
+            Ok(ratio),
+            crate::thumb_hash_to_approximate_aspect_ratio(hash)
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_a_status() {
+        let rgba = sample(40, 30);
+        let (mut hash, mut hash_len) = ([0; THUMBHASH_MAX_HASH_LEN], 0);
+        let encode = |w, h, rgba: &[u8], hash: &mut [u8], hash_len: &mut usize| unsafe {
+            thumbhash_encode(
+                w,
+                h,
+                rgba.as_ptr(),
+                rgba.len(),
+                hash.as_mut_ptr(),
+                hash.len(),
+                hash_len,
+            )
+        };
+        assert_eq!(
+            encode(40, 29, &rgba, &mut hash, &mut hash_len),
+            ThumbHashStatus::InvalidArgument
+        );
+        assert_eq!(
+            encode(40, 30, &rgba, &mut hash[..4], &mut hash_len),
+            ThumbHashStatus::BufferTooSmall
+        );
+        assert_eq!(hash_len, crate::rgba_to_thumb_hash(40, 30, &rgba).len());
+        let status = unsafe {
+            thumbhash_encode(
+                40,
+                30,
+                ptr::null(),
+                rgba.len(),
+                hash.as_mut_ptr(),
+                hash.len(),
+                &mut hash_len,
+            )
+        };
+        assert_eq!(status, ThumbHashStatus::InvalidArgument);
+
+        let (mut w, mut h) = (0, 0);
+        let status = unsafe { thumbhash_decoded_size(hash.as_ptr(), 4, &mut w, &mut h) };
+        assert_eq!(status, ThumbHashStatus::InvalidHash);
+        encode(40, 30, &rgba, &mut hash, &mut hash_len);
+        let mut pixels = [0; 16];
+        let status = unsafe {
+            thumbhash_decode(
+                hash.as_ptr(),
+                hash_len,
+                pixels.as_mut_ptr(),
+                pixels.len(),
+                &mut w,
+                &mut h,
+            )
+        };
+        assert_eq!((status, w, h), (ThumbHashStatus::BufferTooSmall, 32, 23));
+
+        // The alpha bit is set but the alpha byte is missing
+        let truncated = [0, 0, 0x80, 1, 0];
+        let (mut r, mut g, mut b, mut a) = (0.0, 0.0, 0.0, 0.0);
+        let status = unsafe {
+            thumbhash_average_rgba(truncated.as_ptr(), 5, &mut r, &mut g, &mut b, &mut a)
+        };
+        assert_eq!(status, ThumbHashStatus::InvalidHash);
+    }
+
+    #[test]
+    fn buffers_hold_the_encoded_and_decoded_bytes() {
+        let rgba = sample(40, 30);
+        unsafe {
+            let hash = thumbhash_encode_to_buffer(40, 30, rgba.as_ptr(), rgba.len());
+            let bytes = slice::from_raw_parts((*hash).data, (*hash).len).to_vec();
+            assert_eq!(bytes, crate::rgba_to_thumb_hash(40, 30, &rgba));
+            assert_eq!(((*hash).w, (*hash).h), (0, 0));
+            thumbhash_buffer_free(hash);
+
+            let image = thumbhash_decode_to_buffer(bytes.as_ptr(), bytes.len());
+            let pixels = slice::from_raw_parts((*image).data, (*image).len).to_vec();
+            let expected = crate::thumb_hash_to_rgba(&bytes).unwrap();
+            assert_eq!(((*image).w, (*image).h, pixels), expected);
+            thumbhash_buffer_free(image);
+
+            assert!(thumbhash_encode_to_buffer(40, 29, rgba.as_ptr(), rgba.len()).is_null());
+            assert!(thumbhash_decode_to_buffer(bytes.as_ptr(), 4).is_null());
+            thumbhash_buffer_free(ptr::null_mut());
+        }
+    }
+}
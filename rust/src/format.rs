@@ -0,0 +1,1384 @@
+use crate::{
+    ac_count, ac_positions, luminance_counts, quantize, read_ac, read_byte,
+    thumb_hash_to_approximate_aspect_ratio, Channels, HashBuf,
+};
+
+// Every standard hash stores a luminance coefficient count of at least 1 in
+// the low 3 bits of byte 3, so a hash where those bits are 0 is an envelope
+// for some other format instead. The high 4 bits of that byte are the format
+// version and bit 3 is set if the hash ends with a checksum record (so that a
+// hash that lost its checksum can't pass for one that never had one). The
+// first 3 bytes keep the same layout as a standard hash so the average color
+// can still be read from them, and the rest of the hash is the
+// version-specific payload.
+const ENVELOPE_VERSION_BYTE: usize = 3;
+
+/// The bit of the envelope version byte that marks a hash with a checksum.
+const ENVELOPE_CHECKSUM: u8 = 1 << 3;
+
+/// The length of a mini hash.
+const MINI_LEN: usize = 8;
+
+/// The envelope version of the extended format.
+const EXTENDED_VERSION: u8 = 1;
+
+/// The envelope version of the progressive format.
+const PROGRESSIVE_VERSION: u8 = 2;
+
+/// The byte layouts a hash can be read from and written to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Format {
+    Standard,
+    Extended,
+    Micro,
+    Mini,
+    Progressive,
+}
+
+impl Format {
+    /// Returns the maximum number of luminance coefficients along the longer
+    /// side of the image, the size of the P and Q triangles, and the size of
+    /// the A triangle (which is 0 without alpha).
+    pub(crate) fn sizes(self, has_alpha: bool) -> (usize, usize, usize) {
+        // Use fewer luminance bits if there's alpha
+        match (self, has_alpha) {
+            (Format::Standard | Format::Progressive, false) => (7, 3, 0),
+            (Format::Standard | Format::Progressive, true) => (5, 3, 5),
+            (Format::Extended, false) => (9, 5, 0),
+            (Format::Extended, true) => (7, 5, 5),
+            (Format::Micro, false) => (7, 1, 0),
+            (Format::Micro, true) => (7, 1, 1),
+            (Format::Mini, false) => (7, 2, 0),
+            (Format::Mini, true) => (7, 2, 1),
+        }
+    }
+
+    /// Returns the size of the luminance triangle given the number of
+    /// luminance coefficients that encode the aspect ratio.
+    pub(crate) fn l_triangle(self, lx: usize, ly: usize) -> (usize, usize) {
+        match self {
+            Format::Standard | Format::Extended | Format::Progressive => (lx.max(3), ly.max(3)),
+            Format::Micro => (1, 1),
+            Format::Mini => (2, 2),
+        }
+    }
+
+    /// Returns the size of one quantization step of the DC term and the scale
+    /// of each channel (L, P, Q, and A) in this format.
+    pub(crate) fn steps(self) -> ([f32; 4], [f32; 4]) {
+        match self {
+            Format::Standard | Format::Progressive => (
+                [1.0 / 63.0, 2.0 / 63.0, 2.0 / 63.0, 1.0 / 15.0],
+                [1.0 / 31.0, 1.0 / 63.0, 1.0 / 63.0, 1.0 / 15.0],
+            ),
+            Format::Extended => (
+                [1.0 / 63.0, 2.0 / 63.0, 2.0 / 63.0, 1.0 / 15.0],
+                [1.0 / 31.0, 1.0 / 255.0, 1.0 / 255.0, 1.0 / 15.0],
+            ),
+            Format::Micro => ([1.0 / 63.0, 2.0 / 31.0, 2.0 / 31.0, 1.0 / 15.0], [1.0; 4]),
+            Format::Mini => (
+                [1.0 / 63.0, 2.0 / 63.0, 2.0 / 63.0, 1.0 / 15.0],
+                [1.0 / 31.0, 1.0 / 15.0, 1.0 / 15.0, 1.0],
+            ),
+        }
+    }
+
+    /// Returns true if hashes in this format can have extension records.
+    pub(crate) fn has_extensions(self) -> bool {
+        matches!(self, Format::Extended | Format::Progressive)
+    }
+}
+
+/// The format of a ThumbHash, as returned by [`detect_version`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ThumbHashVersion {
+    /// The original format produced by [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash).
+    Standard,
+
+    /// The larger format produced by [`rgba_to_thumb_hash_extended`].
+    Extended,
+
+    /// The 3-byte format produced by [`rgba_to_thumb_hash_micro`]. These
+    /// hashes don't say which format they're in either.
+    Micro,
+
+    /// The 8-byte format produced by [`rgba_to_thumb_hash_mini`]. These hashes
+    /// don't say which format they're in, so [`detect_version`] never returns
+    /// this.
+    Mini,
+
+    /// The prefix-decodable format produced by
+    /// [`rgba_to_thumb_hash_progressive`] and [`thumb_hash_to_progressive`].
+    Progressive,
+
+    /// An envelope with a version that this crate doesn't know how to decode,
+    /// probably written by a newer version of this crate.
+    Unknown(u8),
+}
+
+/// Determines which format a ThumbHash uses.
+///
+/// New formats are stored in a versioned envelope that is distinct from the
+/// standard format, so this tells you whether the hash can be decoded by this
+/// crate (and by other ThumbHash implementations, which only understand the
+/// standard format). Micro and mini hashes are too short for an envelope, so
+/// they aren't recognized at all and must be decoded with
+/// [`thumb_hash_micro_to_rgba`] and [`thumb_hash_mini_to_rgba`] instead. An
+/// error will be returned if the input is too short.
+pub fn detect_version(hash: &[u8]) -> Result<ThumbHashVersion, ()> {
+    match envelope_version(hash) {
+        Some(EXTENDED_VERSION) => Ok(ThumbHashVersion::Extended),
+        Some(PROGRESSIVE_VERSION) => Ok(ThumbHashVersion::Progressive),
+        Some(version) => Ok(ThumbHashVersion::Unknown(version)),
+        None if hash.len() < 5 => Err(()),
+        None => Ok(ThumbHashVersion::Standard),
+    }
+}
+
+/// Returns true if a ThumbHash has an alpha channel.
+///
+/// This only looks at the header, so it's cheap enough to call before deciding
+/// how to render the placeholder. Hashes without alpha always decode to fully
+/// opaque pixels. An error will be returned if the input is too short or isn't
+/// in a format this crate understands.
+pub fn has_alpha(hash: &[u8]) -> Result<bool, ()> {
+    match detect_version(hash)? {
+        ThumbHashVersion::Micro | ThumbHashVersion::Mini | ThumbHashVersion::Unknown(_) => Err(()),
+        _ => Ok((hash[2] & 0x80) != 0),
+    }
+}
+
+/// Returns true if a ThumbHash decodes to fully opaque pixels, which means
+/// renderers can skip blending (see [`has_alpha`]).
+pub fn is_opaque(hash: &[u8]) -> Result<bool, ()> {
+    Ok(!has_alpha(hash)?)
+}
+
+/// Returns the version of an enveloped hash, or `None` for a standard hash.
+pub(crate) fn envelope_version(hash: &[u8]) -> Option<u8> {
+    match hash.get(ENVELOPE_VERSION_BYTE) {
+        Some(&byte) if byte & 7 == 0 => Some(byte >> 4),
+        _ => None,
+    }
+}
+
+/// Returns the number of bytes in the ThumbHash that
+/// [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash) produces for a `w` by `h`
+/// image.
+///
+/// The length only depends on the aspect ratio and on whether the image has
+/// any pixels that aren't fully opaque, so this can be used to size buffers
+/// and fixed-width database columns up front. It's between 17 and 24 bytes
+/// for opaque images and between 23 and 25 bytes otherwise.
+pub fn expected_hash_len(w: usize, h: usize, has_alpha: bool) -> usize {
+    let (l_limit, pq_n, a_n) = Format::Standard.sizes(has_alpha);
+    let (lx, ly) = luminance_counts(w, h, l_limit);
+    let (l_nx, l_ny) = Format::Standard.l_triangle(lx, ly);
+    let nibbles = ac_count(l_nx, l_ny) + 2 * ac_count(pq_n, pq_n) + ac_count(a_n, a_n);
+    5 + has_alpha as usize + nibbles.div_ceil(2)
+}
+
+/// Checks that a ThumbHash is exactly what a well-behaved encoder would have
+/// produced.
+///
+/// The decoders in this crate are lenient: they ignore bytes after the end of
+/// the hash and don't check the header fields beyond what's needed to decode.
+/// This instead returns an error if the hash has extra bytes (other than the
+/// extension records of an extended or progressive hash), unused bits that
+/// aren't zero, or a
+/// luminance layout that doesn't match its aspect ratio. Ingestion pipelines
+/// can use this to reject malformed data from buggy encoders. Progressive
+/// hashes may be truncated, but only at the end of a level.
+pub fn validate_thumb_hash(hash: &[u8]) -> Result<(), ()> {
+    let channels = Channels::read(hash)?;
+    let (l_limit, _, _) = channels.format.sizes(channels.has_alpha);
+    let (long, short) = if channels.is_landscape {
+        (channels.lx, channels.ly)
+    } else {
+        (channels.ly, channels.lx)
+    };
+    if long != l_limit || short > long {
+        return Err(());
+    }
+
+    // Reading a hash and writing it back out again gives the same bytes, so
+    // this catches anything that the reader skipped over
+    if channels.write() != hash {
+        return Err(());
+    }
+    Ok(())
+}
+
+/// Decodes a ThumbHash to an RGBA image like
+/// [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba), but first checks it with
+/// [`validate_thumb_hash`].
+pub fn thumb_hash_to_rgba_strict(hash: &[u8]) -> Result<(usize, usize, Vec<u8>), ()> {
+    validate_thumb_hash(hash)?;
+    crate::thumb_hash_to_rgba(hash)
+}
+
+/// Encodes an RGBA image to an extended ThumbHash. RGB should not be
+/// premultiplied by A.
+///
+/// This stores up to 9 luminance coefficients along the longer side instead
+/// of 7 and 5x5 chroma coefficients instead of 3x3, which gives a noticeably
+/// sharper placeholder for large hero images at the cost of a hash that's
+/// about 45 bytes instead of about 25. Only this crate can decode extended
+/// hashes (see [`detect_version`]). The arguments are the same as for
+/// [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash).
+pub fn rgba_to_thumb_hash_extended(w: usize, h: usize, rgba: &[u8]) -> Vec<u8> {
+    assert!(w <= 100 && h <= 100);
+    assert_eq!(rgba.len(), w * h * 4);
+    Channels::from_rgba(w, h, rgba, Format::Extended).write()
+}
+
+// The extended format starts with the same 3 bytes as the standard format and
+// then the envelope version byte. After that is a byte with the luminance
+// coefficient counts (4 bits each), a byte with the size of the P and Q
+// triangle (3 bits), the size of the A triangle (3 bits), and the landscape
+// flag, and then a byte each for the P and Q scales. The alpha byte and the AC
+// terms follow, exactly like the standard format.
+pub(crate) fn read_extended(original: &[u8]) -> Result<Channels, ()> {
+    let mut hash = original;
+    let header24 = read_byte(&mut hash)? as u32
+        | ((read_byte(&mut hash)? as u32) << 8)
+        | ((read_byte(&mut hash)? as u32) << 16);
+    let envelope = read_byte(&mut hash)?;
+    if envelope & !ENVELOPE_CHECKSUM != EXTENDED_VERSION << 4 {
+        return Err(());
+    }
+    let counts = read_byte(&mut hash)?;
+    let sizes = read_byte(&mut hash)?;
+    let p_scale = read_byte(&mut hash)? as f32 / 255.0;
+    let q_scale = read_byte(&mut hash)? as f32 / 255.0;
+    let l_dc = (header24 & 63) as f32 / 63.0;
+    let p_dc = ((header24 >> 6) & 63) as f32 / 31.5 - 1.0;
+    let q_dc = ((header24 >> 12) & 63) as f32 / 31.5 - 1.0;
+    let l_scale = ((header24 >> 18) & 31) as f32 / 31.0;
+    let has_alpha = (header24 >> 23) != 0;
+    let lx = (counts & 15) as usize;
+    let ly = (counts >> 4) as usize;
+    let pq_n = (sizes & 7) as usize;
+    let a_n = ((sizes >> 3) & 7) as usize;
+    let is_landscape = (sizes >> 6) & 1 != 0;
+    if lx == 0 || ly == 0 || pq_n == 0 || sizes >> 7 != 0 || has_alpha != (a_n > 0) {
+        return Err(());
+    }
+    let (l_nx, l_ny) = Format::Extended.l_triangle(lx, ly);
+    let (a_dc, a_scale) = if has_alpha {
+        let header8 = read_byte(&mut hash)?;
+        ((header8 & 15) as f32 / 15.0, (header8 >> 4) as f32 / 15.0)
+    } else {
+        (1.0, 1.0)
+    };
+    let [l_ac, p_ac, q_ac, a_ac] = read_ac(&mut hash, (l_nx, l_ny), pq_n, a_n)?;
+    let has_checksum = envelope & ENVELOPE_CHECKSUM != 0;
+    let extensions = Extensions::read(original, original.len() - hash.len(), has_checksum)?;
+
+    Ok(Channels {
+        l_dc,
+        p_dc,
+        q_dc,
+        a_dc,
+        l_scale,
+        p_scale,
+        q_scale,
+        a_scale,
+        l_ac,
+        p_ac,
+        q_ac,
+        a_ac,
+        lx,
+        ly,
+        is_landscape,
+        has_alpha,
+        l_nx,
+        l_ny,
+        pq_n,
+        a_n,
+        format: Format::Extended,
+        extensions,
+    })
+}
+
+pub(crate) fn write_extended(channels: &Channels) -> Vec<u8> {
+    let c = channels;
+    let header24 = c.header24();
+    let mut hash = Vec::with_capacity(48);
+    hash.extend_from_slice(&[
+        (header24 & 255) as u8,
+        ((header24 >> 8) & 255) as u8,
+        (header24 >> 16) as u8,
+        EXTENDED_VERSION << 4 | c.extensions.envelope_flags(),
+        c.lx as u8 | ((c.ly as u8) << 4),
+        c.pq_n as u8 | ((c.a_n as u8) << 3) | if c.is_landscape { 1 << 6 } else { 0 },
+        quantize(255.0 * c.p_scale, 255),
+        quantize(255.0 * c.q_scale, 255),
+    ]);
+    if c.has_alpha {
+        hash.push(quantize(15.0 * c.a_dc, 15) | (quantize(15.0 * c.a_scale, 15) << 4));
+    }
+    c.write_ac(&mut hash);
+    c.extensions.write(&mut hash);
+    hash
+}
+
+/// Encodes an RGBA image to a 3-byte micro ThumbHash. RGB should not be
+/// premultiplied by A.
+///
+/// This only stores the average color and a coarse aspect ratio, so it
+/// decodes to a solid color. It's meant for long lists where even a standard
+/// hash per item adds up. Micro hashes are too short to say which format
+/// they're in, so the other decoders in this crate reject them and they must be
+/// decoded with [`thumb_hash_micro_to_rgba`]. These hashes can't have
+/// extension records. The arguments are the same as for
+/// [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash).
+pub fn rgba_to_thumb_hash_micro(w: usize, h: usize, rgba: &[u8]) -> Vec<u8> {
+    assert!(w <= 100 && h <= 100);
+    assert_eq!(rgba.len(), w * h * 4);
+    Channels::from_rgba(w, h, rgba, Format::Micro).write()
+}
+
+/// Decodes a micro ThumbHash (see [`rgba_to_thumb_hash_micro`]) to an RGBA
+/// image like [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba).
+///
+/// The placeholder is a solid color. An error will be returned if the input
+/// isn't exactly 3 bytes long or its luminance coefficient count is 0.
+pub fn thumb_hash_micro_to_rgba(hash: &[u8]) -> Result<(usize, usize, Vec<u8>), ()> {
+    let channels = read_micro(hash)?;
+    let (w, h) = channels.size(32);
+    Ok((w, h, channels.render(w, h)))
+}
+
+// The micro format packs 24 bits: L (6 bits), P (5 bits), Q (5 bits), A (4
+// bits, where 15 means there's no alpha), the luminance coefficient count
+// along the shorter side (3 bits), and the landscape flag.
+pub(crate) fn read_micro(hash: &[u8]) -> Result<Channels, ()> {
+    let &[b0, b1, b2] = hash else {
+        return Err(());
+    };
+    let bits = b0 as u32 | ((b1 as u32) << 8) | ((b2 as u32) << 16);
+    let alpha = (bits >> 16) & 15;
+    let count = ((bits >> 20) & 7) as usize;
+    let is_landscape = (bits >> 23) != 0;
+    if count == 0 {
+        return Err(());
+    }
+    let has_alpha = alpha != 15;
+    let (l_limit, pq_n, a_n) = Format::Micro.sizes(has_alpha);
+    let (lx, ly) = if is_landscape {
+        (l_limit, count)
+    } else {
+        (count, l_limit)
+    };
+
+    Ok(Channels {
+        l_dc: (bits & 63) as f32 / 63.0,
+        p_dc: ((bits >> 6) & 31) as f32 / 15.5 - 1.0,
+        q_dc: ((bits >> 11) & 31) as f32 / 15.5 - 1.0,
+        a_dc: alpha as f32 / 15.0,
+        l_scale: 0.0,
+        p_scale: 0.0,
+        q_scale: 0.0,
+        a_scale: 0.0,
+        l_ac: Vec::new(),
+        p_ac: Vec::new(),
+        q_ac: Vec::new(),
+        a_ac: Vec::new(),
+        lx,
+        ly,
+        is_landscape,
+        has_alpha,
+        l_nx: 1,
+        l_ny: 1,
+        pq_n,
+        a_n,
+        format: Format::Micro,
+        extensions: Extensions::default(),
+    })
+}
+
+pub(crate) fn write_micro(channels: &Channels) -> Vec<u8> {
+    let c = channels;
+    let alpha = if c.has_alpha {
+        quantize(15.0 * c.a_dc, 14)
+    } else {
+        15
+    };
+    let count = if c.is_landscape { c.ly } else { c.lx };
+    let bits = quantize(63.0 * c.l_dc, 63) as u32
+        | ((quantize(15.5 + 15.5 * c.p_dc, 31) as u32) << 6)
+        | ((quantize(15.5 + 15.5 * c.q_dc, 31) as u32) << 11)
+        | ((alpha as u32) << 16)
+        | ((count.clamp(1, 7) as u32) << 20)
+        | if c.is_landscape { 1 << 23 } else { 0 };
+    vec![bits as u8, (bits >> 8) as u8, (bits >> 16) as u8]
+}
+
+/// Encodes an RGBA image to an 8-byte mini ThumbHash. RGB should not be
+/// premultiplied by A.
+///
+/// This stores the average color and a coarse aspect ratio along with the
+/// lowest horizontal and vertical frequency of each color channel, which is
+/// enough to hint at the structure of the image (e.g. a bright sky over dark
+/// ground). Like micro hashes, mini hashes don't say which format they're in,
+/// so they must be decoded with [`thumb_hash_mini_to_rgba`]. These hashes
+/// can't have extension records. The arguments are the same as for
+/// [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash).
+pub fn rgba_to_thumb_hash_mini(w: usize, h: usize, rgba: &[u8]) -> Vec<u8> {
+    assert!(w <= 100 && h <= 100);
+    assert_eq!(rgba.len(), w * h * 4);
+    Channels::from_rgba(w, h, rgba, Format::Mini).write()
+}
+
+/// Decodes a mini ThumbHash (see [`rgba_to_thumb_hash_mini`]) to an RGBA image
+/// like [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba).
+///
+/// An error will be returned if the input isn't exactly 8 bytes long or has
+/// header fields that a mini hash can't have.
+pub fn thumb_hash_mini_to_rgba(hash: &[u8]) -> Result<(usize, usize, Vec<u8>), ()> {
+    let channels = read_mini(hash)?;
+    let (w, h) = channels.size(32);
+    Ok((w, h, channels.render(w, h)))
+}
+
+// The mini format packs 64 bits: L, P, and Q (6 bits each), the L scale (5
+// bits), the landscape flag, the luminance coefficient count along the shorter
+// side (3 bits), A (4 bits, where 15 means there's no alpha), the P and Q
+// scales (4 bits each), a reserved bit, and then the 2 AC terms of each of L,
+// P, and Q (4 bits each). The count is never 0, so the low 3 bits of byte 3
+// never look like an envelope.
+pub(crate) fn read_mini(hash: &[u8]) -> Result<Channels, ()> {
+    let bytes: [u8; MINI_LEN] = hash.try_into().map_err(|_| ())?;
+    let bits = u64::from_le_bytes(bytes);
+    let is_landscape = (bits >> 23) & 1 != 0;
+    let count = ((bits >> 24) & 7) as usize;
+    let alpha = (bits >> 27) & 15;
+    if count == 0 || (bits >> 39) & 1 != 0 {
+        return Err(());
+    }
+    let has_alpha = alpha != 15;
+    let (l_limit, pq_n, a_n) = Format::Mini.sizes(has_alpha);
+    let (lx, ly) = if is_landscape {
+        (l_limit, count)
+    } else {
+        (count, l_limit)
+    };
+    let (l_nx, l_ny) = Format::Mini.l_triangle(lx, ly);
+    let nibble = |i: u32| ((bits >> (40 + 4 * i)) & 15) as u8;
+
+    Ok(Channels {
+        l_dc: (bits & 63) as f32 / 63.0,
+        p_dc: ((bits >> 6) & 63) as f32 / 31.5 - 1.0,
+        q_dc: ((bits >> 12) & 63) as f32 / 31.5 - 1.0,
+        a_dc: alpha as f32 / 15.0,
+        l_scale: ((bits >> 18) & 31) as f32 / 31.0,
+        p_scale: ((bits >> 31) & 15) as f32 / 15.0,
+        q_scale: ((bits >> 35) & 15) as f32 / 15.0,
+        a_scale: 0.0,
+        l_ac: vec![nibble(0), nibble(1)],
+        p_ac: vec![nibble(2), nibble(3)],
+        q_ac: vec![nibble(4), nibble(5)],
+        a_ac: Vec::new(),
+        lx,
+        ly,
+        is_landscape,
+        has_alpha,
+        l_nx,
+        l_ny,
+        pq_n,
+        a_n,
+        format: Format::Mini,
+        extensions: Extensions::default(),
+    })
+}
+
+pub(crate) fn write_mini(channels: &Channels) -> Vec<u8> {
+    let c = channels;
+    let alpha = if c.has_alpha {
+        quantize(15.0 * c.a_dc, 14)
+    } else {
+        15
+    };
+    let count = if c.is_landscape { c.ly } else { c.lx };
+    let mut bits = quantize(63.0 * c.l_dc, 63) as u64
+        | ((quantize(31.5 + 31.5 * c.p_dc, 63) as u64) << 6)
+        | ((quantize(31.5 + 31.5 * c.q_dc, 63) as u64) << 12)
+        | ((quantize(31.0 * c.l_scale, 31) as u64) << 18)
+        | if c.is_landscape { 1 << 23 } else { 0 }
+        | ((count.clamp(1, 7) as u64) << 24)
+        | ((alpha as u64) << 27)
+        | ((quantize(15.0 * c.p_scale, 15) as u64) << 31)
+        | ((quantize(15.0 * c.q_scale, 15) as u64) << 35);
+    let ac = c.l_ac.iter().chain(&c.p_ac).chain(&c.q_ac);
+    for (i, &u) in ac.enumerate() {
+        bits |= (u as u64) << (40 + 4 * i);
+    }
+    bits.to_le_bytes().to_vec()
+}
+
+/// Encodes an RGBA image to a progressive ThumbHash. RGB should not be
+/// premultiplied by A.
+///
+/// This contains the same information as a standard hash (plus one byte), but
+/// the AC terms are stored from the lowest frequency to the highest across all
+/// channels. That means any prefix of the hash that's at least 6 bytes long (7
+/// bytes with alpha) decodes to a blurrier but still valid placeholder, so a
+/// streaming protocol can send the first few bytes immediately and the rest
+/// later. Only this crate can decode progressive hashes (see
+/// [`detect_version`]). The arguments are the same as for
+/// [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash).
+pub fn rgba_to_thumb_hash_progressive(w: usize, h: usize, rgba: &[u8]) -> Vec<u8> {
+    assert!(w <= 100 && h <= 100);
+    assert_eq!(rgba.len(), w * h * 4);
+    Channels::from_rgba(w, h, rgba, Format::Progressive).write()
+}
+
+/// The largest term that the mini format can leave out of an adaptive hash.
+const ADAPTIVE_MINI_TOLERANCE: f32 = 0.02;
+
+/// The largest term that the standard format can leave out of an adaptive
+/// hash before the extended format is used instead.
+const ADAPTIVE_STANDARD_TOLERANCE: f32 = 0.04;
+
+/// Encodes an RGBA image to whichever of the mini, standard, and extended
+/// formats suits it best. RGB should not be premultiplied by A.
+///
+/// The image is analyzed to find the terms that each format would leave out,
+/// which is where the fine detail, the variation in color, and the variation
+/// in alpha of the image are. Nearly uniform images get an 8-byte mini hash,
+/// images with detail that a standard hash would lose get an extended hash, and
+/// everything else gets a standard hash. This makes hashes smaller on average
+/// across a mix of images without making any of them look worse.
+///
+/// Returns the format that was picked along with the hash. Mini hashes can't
+/// be recognized from their bytes (see [`detect_version`]), so store the format
+/// next to the hash and decode mini hashes with [`thumb_hash_mini_to_rgba`].
+/// The arguments are the same as for
+/// [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash).
+pub fn rgba_to_thumb_hash_adaptive(w: usize, h: usize, rgba: &[u8]) -> (ThumbHashVersion, Vec<u8>) {
+    assert!(w <= 100 && h <= 100);
+    assert_eq!(rgba.len(), w * h * 4);
+    let extended = Channels::from_rgba(w, h, rgba, Format::Extended);
+    if largest_dropped_term(&extended, w, h, Format::Mini) <= ADAPTIVE_MINI_TOLERANCE {
+        let mini = Channels::from_rgba(w, h, rgba, Format::Mini);
+        (ThumbHashVersion::Mini, mini.write())
+    } else if largest_dropped_term(&extended, w, h, Format::Standard) <= ADAPTIVE_STANDARD_TOLERANCE
+    {
+        let standard = Channels::from_rgba(w, h, rgba, Format::Standard);
+        (ThumbHashVersion::Standard, standard.write())
+    } else {
+        (ThumbHashVersion::Extended, extended.write())
+    }
+}
+
+/// Returns the magnitude of the largest term of `channels` that a hash of a
+/// `w` by `h` image in `format` doesn't store.
+fn largest_dropped_term(channels: &Channels, w: usize, h: usize, format: Format) -> f32 {
+    let (l_limit, pq_n, a_n) = format.sizes(channels.has_alpha);
+    let (lx, ly) = luminance_counts(w, h, l_limit);
+    let (l_nx, l_ny) = format.l_triangle(lx, ly);
+    let triangles = [
+        ((channels.l_nx, channels.l_ny), (l_nx, l_ny)),
+        ((channels.pq_n, channels.pq_n), (pq_n, pq_n)),
+        ((channels.pq_n, channels.pq_n), (pq_n, pq_n)),
+        ((channels.a_n, channels.a_n), (a_n, a_n)),
+    ];
+    let mut largest = 0.0f32;
+    for (channel, ((nx, ny), (kept_nx, kept_ny))) in triangles.into_iter().enumerate() {
+        let kept = ac_positions(kept_nx, kept_ny);
+        for (cx, cy) in ac_positions(nx, ny) {
+            if !kept.contains(&(cx, cy)) {
+                largest = largest.max(channels.term(channel, cx, cy).abs());
+            }
+        }
+    }
+    largest
+}
+
+/// Converts a standard ThumbHash to the progressive format (see
+/// [`rgba_to_thumb_hash_progressive`]) without losing any information.
+///
+/// An error will be returned if the input is too short or isn't a standard or
+/// progressive hash.
+pub fn thumb_hash_to_progressive(hash: &[u8]) -> Result<Vec<u8>, ()> {
+    let mut channels = Channels::read(hash)?;
+    if !matches!(channels.format, Format::Standard | Format::Progressive) {
+        return Err(());
+    }
+    channels.format = Format::Progressive;
+    Ok(channels.write())
+}
+
+/// Returns every AC term of every channel in the order that the progressive
+/// format stores them, given the size of each channel's triangle. Each term is
+/// `(level, channel, cx, cy)`. Level `k` completes the triangles of size `k`
+/// (clamped to the size of each channel), and since smaller triangles are
+/// nested inside larger ones, stopping after any level leaves every channel
+/// with a valid (smaller) triangle.
+fn progressive_order(channels: [(usize, usize); 4]) -> Vec<(usize, usize, usize, usize)> {
+    let levels = channels.iter().map(|&(nx, ny)| nx.max(ny)).max().unwrap();
+    let mut order = Vec::new();
+    for level in 2..=levels {
+        for (channel, &(nx, ny)) in channels.iter().enumerate() {
+            let size = |k: usize| (k.min(nx), k.min(ny));
+            let (prev_nx, prev_ny) = size(level - 1);
+            let has_term = |cx: usize, cy: usize| cx * prev_ny < prev_nx * (prev_ny - cy);
+            let (level_nx, level_ny) = size(level);
+            for (cx, cy) in ac_positions(level_nx, level_ny) {
+                if cy >= prev_ny || !has_term(cx, cy) {
+                    order.push((level, channel, cx, cy));
+                }
+            }
+        }
+    }
+    order
+}
+
+// The progressive format starts with the same 3 bytes as the standard format,
+// then the envelope version byte, and then the same 2 bytes and optional alpha
+// byte that come next in the standard format. The AC terms are stored in the
+// order given by `progressive_order`, and the decoder uses every complete
+// level. The writer only ever ends a hash on a level boundary, and no level
+// adds a single term after an odd number of terms, so the padding nibble at
+// the end of a hash is never mistaken for a complete level.
+pub(crate) fn read_progressive(original: &[u8]) -> Result<Channels, ()> {
+    let mut hash = original;
+    let header24 = read_byte(&mut hash)? as u32
+        | ((read_byte(&mut hash)? as u32) << 8)
+        | ((read_byte(&mut hash)? as u32) << 16);
+    let envelope = read_byte(&mut hash)?;
+    if envelope & !ENVELOPE_CHECKSUM != PROGRESSIVE_VERSION << 4 {
+        return Err(());
+    }
+    let has_checksum = envelope & ENVELOPE_CHECKSUM != 0;
+    let header16 = read_byte(&mut hash)? as u16 | ((read_byte(&mut hash)? as u16) << 8);
+    let l_dc = (header24 & 63) as f32 / 63.0;
+    let p_dc = ((header24 >> 6) & 63) as f32 / 31.5 - 1.0;
+    let q_dc = ((header24 >> 12) & 63) as f32 / 31.5 - 1.0;
+    let l_scale = ((header24 >> 18) & 31) as f32 / 31.0;
+    let has_alpha = (header24 >> 23) != 0;
+    let p_scale = ((header16 >> 3) & 63) as f32 / 63.0;
+    let q_scale = ((header16 >> 9) & 63) as f32 / 63.0;
+    let is_landscape = (header16 >> 15) != 0;
+    let count = (header16 & 7) as usize;
+    if count == 0 {
+        return Err(());
+    }
+    let (l_limit, pq_n, a_n) = Format::Progressive.sizes(has_alpha);
+    let lx = if is_landscape { l_limit } else { count };
+    let ly = if is_landscape { count } else { l_limit };
+    let (l_nx, l_ny) = Format::Progressive.l_triangle(lx, ly);
+    let (a_dc, a_scale) = if has_alpha {
+        let header8 = read_byte(&mut hash)?;
+        ((header8 & 15) as f32 / 15.0, (header8 >> 4) as f32 / 15.0)
+    } else {
+        (1.0, 1.0)
+    };
+
+    // Read as many complete levels as are present
+    let order = progressive_order([(l_nx, l_ny), (pq_n, pq_n), (pq_n, pq_n), (a_n, a_n)]);
+    let available = order.len().min(hash.len() * 2);
+    let nibble = |i: usize| (hash[i / 2] >> (4 * (i % 2))) & 15;
+    let level = match order.get(available) {
+        Some(&(level, ..)) => level - 1,
+        None => order.last().map_or(1, |&(level, ..)| level),
+    };
+    let sizes = [(l_nx, l_ny), (pq_n, pq_n), (pq_n, pq_n), (a_n, a_n)]
+        .map(|(nx, ny): (usize, usize)| (nx.min(level), ny.min(level)));
+    let mut ac = sizes.map(|(nx, ny)| vec![0; ac_count(nx, ny)]);
+    for (i, &(term_level, channel, cx, cy)) in order.iter().enumerate() {
+        if term_level <= level {
+            let (nx, ny) = sizes[channel];
+            let index = ac_positions(nx, ny).iter().position(|&pos| pos == (cx, cy));
+            ac[channel][index.unwrap()] = nibble(i);
+        }
+    }
+    let [l_ac, p_ac, q_ac, a_ac] = ac;
+    let [(l_nx, l_ny), (pq_n, _), _, (a_n, _)] = sizes;
+
+    // A hash with a checksum can't be truncated, since that would also remove
+    // the checksum
+    let extensions = if available == order.len() {
+        let offset = original.len() - hash.len() + order.len().div_ceil(2);
+        Extensions::read(original, offset, has_checksum)?
+    } else if has_checksum {
+        return Err(());
+    } else {
+        Extensions::default()
+    };
+
+    Ok(Channels {
+        l_dc,
+        p_dc,
+        q_dc,
+        a_dc,
+        l_scale,
+        p_scale,
+        q_scale,
+        a_scale,
+        l_ac,
+        p_ac,
+        q_ac,
+        a_ac,
+        lx,
+        ly,
+        is_landscape,
+        has_alpha,
+        l_nx,
+        l_ny,
+        pq_n,
+        a_n,
+        format: Format::Progressive,
+        extensions,
+    })
+}
+
+pub(crate) fn write_progressive(channels: &Channels) -> Vec<u8> {
+    let c = channels;
+    let header24 = c.header24();
+    let header16 = c.header16();
+    let mut hash = Vec::with_capacity(26);
+    hash.extend_from_slice(&[
+        (header24 & 255) as u8,
+        ((header24 >> 8) & 255) as u8,
+        (header24 >> 16) as u8,
+        PROGRESSIVE_VERSION << 4 | c.extensions.envelope_flags(),
+        (header16 & 255) as u8,
+        (header16 >> 8) as u8,
+    ]);
+    if c.has_alpha {
+        hash.push(quantize(15.0 * c.a_dc, 15) | (quantize(15.0 * c.a_scale, 15) << 4));
+    }
+
+    // Write the AC terms from the lowest frequency to the highest
+    let sizes = [
+        (c.l_nx, c.l_ny),
+        (c.pq_n, c.pq_n),
+        (c.pq_n, c.pq_n),
+        (c.a_n, c.a_n),
+    ];
+    let ac = [&c.l_ac, &c.p_ac, &c.q_ac, &c.a_ac];
+    for (i, (_, channel, cx, cy)) in progressive_order(sizes).into_iter().enumerate() {
+        let (nx, ny) = sizes[channel];
+        let index = ac_positions(nx, ny).iter().position(|&pos| pos == (cx, cy));
+        let u = ac[channel][index.unwrap()];
+        if i % 2 == 1 {
+            *hash.last_mut().unwrap() |= u << 4;
+        } else {
+            hash.push(u);
+        }
+    }
+    c.extensions.write(&mut hash);
+    hash
+}
+
+// An extended or progressive hash may have extension records after the
+// coefficients. Standard hashes never do, so that bytes after the end of a
+// standard hash are ignored like they always have been instead of changing how
+// it decodes. Each record starts with a tag byte that determines how long the
+// rest of the record is. Parsing stops at the first unknown tag.
+const EXTENSION_ASPECT_RATIO: u8 = 0x01;
+const EXTENSION_CHECKSUM: u8 = 0x02;
+
+/// The optional data stored in the extension records of a hash.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Extensions {
+    /// The exact aspect ratio, stored as `(log2(ratio) + 8) * 4096`. This
+    /// covers ratios from 1:256 to 256:1 with an error of less than 0.01%.
+    pub(crate) aspect_ratio: Option<u16>,
+
+    /// Whether the hash ends with a CRC-8 of all of the bytes before it. This
+    /// is always the last record so that it covers the other records too.
+    pub(crate) checksum: bool,
+}
+
+impl Extensions {
+    /// Parses the records that start at `offset` in `hash`, where
+    /// `has_checksum` is whether the envelope says the hash has a checksum. An
+    /// error will be returned if the checksum is missing, isn't the last 2
+    /// bytes of the hash, or doesn't match.
+    pub(crate) fn read(hash: &[u8], offset: usize, has_checksum: bool) -> Result<Extensions, ()> {
+        let mut extensions = Extensions::default();
+        let mut bytes = &hash[offset..];
+        while let Some((&tag, rest)) = bytes.split_first() {
+            match (tag, rest) {
+                (EXTENSION_ASPECT_RATIO, &[lo, hi, ..]) => {
+                    extensions.aspect_ratio = Some(lo as u16 | ((hi as u16) << 8));
+                    bytes = &rest[2..];
+                }
+                (EXTENSION_CHECKSUM, _) => {
+                    if !has_checksum || rest.len() != 1 || crc8(&hash[..hash.len() - 1]) != rest[0]
+                    {
+                        return Err(());
+                    }
+                    extensions.checksum = true;
+                    break;
+                }
+                _ => break,
+            }
+        }
+        if has_checksum != extensions.checksum {
+            return Err(());
+        }
+        Ok(extensions)
+    }
+
+    /// Returns the bits of the envelope version byte that describe the records.
+    pub(crate) fn envelope_flags(&self) -> u8 {
+        if self.checksum {
+            ENVELOPE_CHECKSUM
+        } else {
+            0
+        }
+    }
+
+    pub(crate) fn write(&self, hash: &mut impl HashBuf) {
+        if let Some(ratio) = self.aspect_ratio {
+            hash.push(EXTENSION_ASPECT_RATIO);
+            hash.push(ratio as u8);
+            hash.push((ratio >> 8) as u8);
+        }
+        if self.checksum {
+            hash.push(EXTENSION_CHECKSUM);
+            hash.push(crc8(hash.as_ref()));
+        }
+    }
+
+    pub(crate) fn aspect_ratio(&self) -> Option<f32> {
+        self.aspect_ratio
+            .map(|ratio| (ratio as f32 / 4096.0 - 8.0).exp2())
+    }
+
+    pub(crate) fn set_aspect_ratio(&mut self, ratio: f32) {
+        self.aspect_ratio =
+            Some(((ratio.log2() + 8.0) * 4096.0).round().clamp(0.0, 65535.0) as u16);
+    }
+}
+
+/// Reads a hash that extension records are about to be added to, which means
+/// converting a standard hash to the progressive format.
+fn read_for_extensions(hash: &[u8]) -> Result<Channels, ()> {
+    let mut channels = Channels::read(hash)?;
+    if channels.format == Format::Standard {
+        channels.format = Format::Progressive;
+    }
+    if !channels.format.has_extensions() {
+        return Err(());
+    }
+    Ok(channels)
+}
+
+/// Returns true if `hash` is a hash with a checksum where one of the low bits
+/// of the envelope version byte was flipped, which makes it look like a
+/// standard hash. Standard hashes are never written with trailing bytes, so
+/// this is only checked for standard hashes that have them.
+pub(crate) fn is_damaged_checksum_envelope(hash: &[u8]) -> bool {
+    let [.., EXTENSION_CHECKSUM, crc] = *hash else {
+        return false;
+    };
+    let mut restored = hash[..hash.len() - 1].to_vec();
+    let Some(envelope) = restored.get_mut(ENVELOPE_VERSION_BYTE) else {
+        return false;
+    };
+    *envelope &= !7;
+    *envelope & ENVELOPE_CHECKSUM != 0
+        && matches!(
+            envelope_version(&restored),
+            Some(EXTENDED_VERSION | PROGRESSIVE_VERSION)
+        )
+        && crc8(&restored) == crc
+}
+
+/// CRC-8 with the polynomial `x^8 + x^2 + x + 1` (0x07) and an initial value
+/// of 0, which detects all single-bit errors and all burst errors of up to 8
+/// bits.
+fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Adds the exact aspect ratio of the original image to a ThumbHash.
+///
+/// The ratio normally stored in a hash is quantized quite coarsely, so a
+/// placeholder sized from it may not exactly match the slot sized from the
+/// real image dimensions. This appends a 3-byte extension record with the
+/// precise ratio of a `w` by `h` image (or replaces an existing one), and
+/// this crate's decoder uses it to size the decoded image. Only extended and
+/// progressive hashes can have extension records, so a standard hash is
+/// converted to the progressive format first (see [`thumb_hash_to_progressive`]),
+/// which decodes to the same placeholder. An error will be returned if the
+/// input is too short or is a micro or mini hash.
+pub fn thumb_hash_with_aspect_ratio(hash: &[u8], w: usize, h: usize) -> Result<Vec<u8>, ()> {
+    let mut channels = read_for_extensions(hash)?;
+    channels.extensions.set_aspect_ratio(w as f32 / h as f32);
+    Ok(channels.write())
+}
+
+/// Extracts the exact aspect ratio of the original image if it was added with
+/// [`thumb_hash_with_aspect_ratio`].
+///
+/// Returns `None` if the hash doesn't store the exact ratio. An error will be
+/// returned if the input is too short.
+pub fn thumb_hash_to_exact_aspect_ratio(hash: &[u8]) -> Result<Option<f32>, ()> {
+    Ok(Channels::read(hash)?.extensions.aspect_ratio())
+}
+
+/// Extracts the aspect ratio of the original image, which is exact if the hash
+/// stores it (see [`thumb_hash_with_aspect_ratio`]) and approximate otherwise.
+///
+/// An error will be returned if the input is too short.
+pub fn thumb_hash_to_aspect_ratio(hash: &[u8]) -> Result<f32, ()> {
+    match thumb_hash_to_exact_aspect_ratio(hash)? {
+        Some(ratio) => Ok(ratio),
+        None => thumb_hash_to_approximate_aspect_ratio(hash),
+    }
+}
+
+/// Adds a checksum to a ThumbHash so that corruption can be detected.
+///
+/// This appends a 2-byte extension record with a CRC-8 of the rest of the
+/// hash. Decoding a hash with a checksum that doesn't match returns an error
+/// instead of rendering a garbage placeholder, which catches most accidental
+/// changes such as mangled bytes from copy-and-paste. The envelope also
+/// records that the hash has a checksum, so a hash that was truncated (e.g. by
+/// a database column that's too short) fails to decode too, even though
+/// progressive hashes without a checksum can be truncated on purpose. Hashes
+/// derived from this hash by other functions in this crate keep the checksum.
+/// Like [`thumb_hash_with_aspect_ratio`], this converts a standard hash to the
+/// progressive format first. An error will be returned if the input is too
+/// short, is a micro or mini hash, or already has a checksum that doesn't
+/// match.
+pub fn thumb_hash_with_checksum(hash: &[u8]) -> Result<Vec<u8>, ()> {
+    let mut channels = read_for_extensions(hash)?;
+    channels.extensions.checksum = true;
+    Ok(channels.write())
+}
+
+/// Returns true if a ThumbHash has a checksum (see [`thumb_hash_with_checksum`]).
+///
+/// An error will be returned if the input is too short or the checksum doesn't
+/// match.
+pub fn has_checksum(hash: &[u8]) -> Result<bool, ()> {
+    Ok(Channels::read(hash)?.extensions.checksum)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample, sample_with_alpha};
+    use crate::{rgba_to_thumb_hash, thumb_hash_to_rgba};
+
+    #[test]
+    fn micro_is_byte_exact() {
+        let opaque = rgba_to_thumb_hash_micro(32, 24, &sample(32, 24));
+        let alpha = rgba_to_thumb_hash_micro(32, 24, &sample_with_alpha(32, 24));
+        assert_eq!(opaque, [158, 148, 223]);
+        assert_eq!(alpha, [223, 156, 216]);
+        for hash in [&opaque, &alpha] {
+            assert_eq!(read_micro(hash).unwrap().write(), *hash);
+        }
+        assert!(!read_micro(&opaque).unwrap().has_alpha);
+        assert!(read_micro(&alpha).unwrap().has_alpha);
+
+        // The placeholder is a solid color
+        let (w, h, rgba) = thumb_hash_micro_to_rgba(&alpha).unwrap();
+        assert_eq!((w, h), (32, 23));
+        assert!(rgba.chunks_exact(4).all(|p| p == &rgba[..4]));
+        assert_eq!(rgba[3], 136);
+    }
+
+    #[test]
+    fn mini_is_byte_exact() {
+        let landscape = rgba_to_thumb_hash_mini(32, 24, &sample(32, 24));
+        let portrait = rgba_to_thumb_hash_mini(24, 32, &sample(24, 32));
+        let alpha = rgba_to_thumb_hash_mini(32, 24, &sample_with_alpha(32, 24));
+        assert_eq!(landscape, [94, 73, 134, 253, 8, 14, 14, 254]);
+        assert_eq!(portrait, [94, 73, 6, 253, 8, 13, 13, 253]);
+        assert_eq!(alpha, [159, 105, 134, 69, 8, 12, 12, 252]);
+        for hash in [&landscape, &portrait, &alpha] {
+            assert_eq!(read_mini(hash).unwrap().write(), *hash);
+        }
+        let (w, h, _) = thumb_hash_mini_to_rgba(&landscape).unwrap();
+        assert_eq!((w, h), (32, 23));
+        let (w, h, _) = thumb_hash_mini_to_rgba(&portrait).unwrap();
+        assert_eq!((w, h), (23, 32));
+        assert!(read_mini(&alpha).unwrap().has_alpha);
+    }
+
+    #[test]
+    fn micro_is_only_read_by_its_own_decoder() {
+        let micro = rgba_to_thumb_hash_micro(32, 24, &sample(32, 24));
+        assert_eq!(detect_version(&micro), Err(()));
+        assert_eq!(thumb_hash_to_rgba(&micro), Err(()));
+        assert_eq!(has_alpha(&micro), Err(()));
+        assert!(thumb_hash_micro_to_rgba(&micro).is_ok());
+        assert_eq!(
+            thumb_hash_micro_to_rgba(&[&micro[..], &[0]].concat()),
+            Err(())
+        );
+        assert_eq!(thumb_hash_micro_to_rgba(&micro[..2]), Err(()));
+
+        // A truncated standard hash is too short rather than a micro hash
+        let standard = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        assert_eq!(thumb_hash_to_rgba(&standard[..3]), Err(()));
+    }
+
+    #[test]
+    fn mini_is_only_read_by_its_own_decoder() {
+        let mini = rgba_to_thumb_hash_mini(32, 24, &sample(32, 24));
+        assert_eq!(thumb_hash_to_rgba(&mini), Err(()));
+        assert!(thumb_hash_mini_to_rgba(&mini).is_ok());
+        assert_eq!(thumb_hash_mini_to_rgba(&mini[..MINI_LEN - 1]), Err(()));
+        assert_eq!(
+            thumb_hash_mini_to_rgba(&[&mini[..], &[0]].concat()),
+            Err(())
+        );
+
+        // A truncated standard hash is too short rather than a mini hash
+        let standard = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        assert_eq!(
+            detect_version(&standard[..MINI_LEN]),
+            Ok(ThumbHashVersion::Standard)
+        );
+        assert_eq!(thumb_hash_to_rgba(&standard[..MINI_LEN]), Err(()));
+    }
+
+    #[test]
+    fn adaptive_returns_the_format_it_picked() {
+        let flat = sample(32, 24);
+        let (version, hash) = rgba_to_thumb_hash_adaptive(32, 24, &flat);
+        assert_eq!(version, ThumbHashVersion::Mini);
+        assert_eq!(hash, rgba_to_thumb_hash_mini(32, 24, &flat));
+
+        // An image with a hard edge needs more terms than a mini hash has
+        let halves: Vec<u8> = (0..32 * 24)
+            .flat_map(|i| [(i % 32 / 16 * 255) as u8; 4])
+            .collect();
+        let (version, hash) = rgba_to_thumb_hash_adaptive(32, 24, &halves);
+        assert_ne!(version, ThumbHashVersion::Mini);
+        assert_eq!(detect_version(&hash), Ok(version));
+    }
+
+    #[test]
+    fn aspect_ratio_extension_is_byte_exact() {
+        let extended = rgba_to_thumb_hash_extended(30, 20, &sample_with_alpha(30, 20));
+        let with_ratio = thumb_hash_with_aspect_ratio(&extended, 30, 20).unwrap();
+        assert_eq!(with_ratio, [&extended[..], &[1, 92, 137]].concat());
+        assert_eq!(detect_version(&with_ratio), Ok(ThumbHashVersion::Extended));
+        assert_eq!(validate_thumb_hash(&with_ratio), Ok(()));
+        let ratio = thumb_hash_to_exact_aspect_ratio(&with_ratio)
+            .unwrap()
+            .unwrap();
+        assert!((ratio - 1.5).abs() < 0.001);
+        assert_eq!(thumb_hash_to_aspect_ratio(&with_ratio), Ok(ratio));
+        assert_eq!(thumb_hash_to_exact_aspect_ratio(&extended), Ok(None));
+
+        // Replacing the ratio doesn't add another record
+        let replaced = thumb_hash_with_aspect_ratio(&with_ratio, 20, 30).unwrap();
+        assert_eq!(replaced.len(), with_ratio.len());
+        let ratio = thumb_hash_to_exact_aspect_ratio(&replaced)
+            .unwrap()
+            .unwrap();
+        assert!((ratio - 2.0 / 3.0).abs() < 0.001);
+
+        let micro = rgba_to_thumb_hash_micro(30, 20, &sample(30, 20));
+        assert_eq!(thumb_hash_with_aspect_ratio(&micro, 30, 20), Err(()));
+    }
+
+    #[test]
+    fn aspect_ratio_moves_standard_hashes_into_an_envelope() {
+        let standard = rgba_to_thumb_hash(30, 20, &sample(30, 20));
+        let with_ratio = thumb_hash_with_aspect_ratio(&standard, 30, 20).unwrap();
+        assert_eq!(
+            with_ratio,
+            [
+                94, 73, 6, 32, 29, 140, 13, 13, 253, 136, 136, 136, 120, 136, 136, 135, 136, 136,
+                136, 120, 135, 136, 1, 92, 137
+            ]
+        );
+        assert_eq!(
+            detect_version(&with_ratio),
+            Ok(ThumbHashVersion::Progressive)
+        );
+        let ratio = thumb_hash_to_exact_aspect_ratio(&with_ratio)
+            .unwrap()
+            .unwrap();
+        assert!((ratio - 1.5).abs() < 0.001);
+    }
+
+    #[test]
+    fn checksum_extension_is_byte_exact() {
+        let extended = rgba_to_thumb_hash_extended(30, 20, &sample_with_alpha(30, 20));
+        let with_ratio = thumb_hash_with_aspect_ratio(&extended, 30, 20).unwrap();
+        let hash = thumb_hash_with_checksum(&with_ratio).unwrap();
+        let mut expected = [&with_ratio[..], &[2, 131]].concat();
+        expected[ENVELOPE_VERSION_BYTE] |= ENVELOPE_CHECKSUM;
+        assert_eq!(hash, expected);
+        assert_eq!(has_checksum(&hash), Ok(true));
+        assert_eq!(has_checksum(&with_ratio), Ok(false));
+        assert_eq!(validate_thumb_hash(&hash), Ok(()));
+        assert_eq!(thumb_hash_with_checksum(&hash), Ok(hash.clone()));
+
+        assert_checksum_detects_corruption(&hash);
+    }
+
+    /// Checks that flipping any bit of a hash with a checksum or truncating it
+    /// to any length makes it fail to decode.
+    fn assert_checksum_detects_corruption(hash: &[u8]) {
+        for i in 0..hash.len() * 8 {
+            let mut corrupted = hash.to_vec();
+            corrupted[i / 8] ^= 1 << (i % 8);
+            assert!(thumb_hash_to_rgba(&corrupted).is_err(), "bit {i}");
+        }
+        for len in 0..hash.len() {
+            assert!(thumb_hash_to_rgba(&hash[..len]).is_err(), "length {len}");
+        }
+    }
+
+    #[test]
+    fn checksum_moves_standard_hashes_into_an_envelope() {
+        let standard = rgba_to_thumb_hash(30, 20, &sample(30, 20));
+        let hash = thumb_hash_with_checksum(&standard).unwrap();
+        assert_eq!(
+            hash,
+            [
+                94, 73, 6, 40, 29, 140, 13, 13, 253, 136, 136, 136, 120, 136, 136, 135, 136, 136,
+                136, 120, 135, 136, 2, 124
+            ]
+        );
+        assert_eq!(detect_version(&hash), Ok(ThumbHashVersion::Progressive));
+        assert_eq!(has_checksum(&hash), Ok(true));
+        assert_checksum_detects_corruption(&hash);
+        let with_alpha = rgba_to_thumb_hash(30, 20, &sample_with_alpha(30, 20));
+        assert_checksum_detects_corruption(&thumb_hash_with_checksum(&with_alpha).unwrap());
+        let micro = rgba_to_thumb_hash_micro(30, 20, &sample(30, 20));
+        assert_eq!(thumb_hash_with_checksum(&micro), Err(()));
+    }
+
+    #[test]
+    fn standard_hashes_ignore_trailing_bytes() {
+        let standard = rgba_to_thumb_hash(30, 20, &sample(30, 20));
+        let expected = thumb_hash_to_rgba(&standard).unwrap();
+        for trailing in [&[1, 92, 137][..], &[2, 0], &[255]] {
+            let hash = [&standard[..], trailing].concat();
+            assert_eq!(thumb_hash_to_rgba(&hash), Ok(expected.clone()));
+            assert_eq!(thumb_hash_to_exact_aspect_ratio(&hash), Ok(None));
+            assert_eq!(has_checksum(&hash), Ok(false));
+            assert_eq!(validate_thumb_hash(&hash), Err(()));
+        }
+    }
+
+    fn decoded_size(hash: &[u8]) -> (usize, usize) {
+        Channels::read(hash).unwrap().size(32)
+    }
+
+    #[test]
+    fn detect_version_reads_the_envelope() {
+        let standard = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        assert_eq!(detect_version(&standard), Ok(ThumbHashVersion::Standard));
+        assert_eq!(envelope_version(&standard), None);
+        let mut future = standard.clone();
+        future[ENVELOPE_VERSION_BYTE] = 9 << 4;
+        assert_eq!(detect_version(&future), Ok(ThumbHashVersion::Unknown(9)));
+        assert!(Channels::read(&future).is_err());
+        assert_eq!(has_alpha(&future), Err(()));
+        assert_eq!(detect_version(&standard[..4]), Err(()));
+        assert_eq!(detect_version(&[]), Err(()));
+    }
+
+    #[test]
+    fn extended_is_byte_exact() {
+        let opaque = rgba_to_thumb_hash_extended(32, 24, &sample(32, 24));
+        let alpha = rgba_to_thumb_hash_extended(24, 32, &sample_with_alpha(24, 32));
+        assert_eq!(
+            opaque,
+            [
+                94, 73, 6, 16, 121, 69, 12, 24, 142, 136, 120, 136, 112, 135, 135, 136, 135, 136,
+                136, 120, 136, 135, 136, 136, 120, 120, 119, 142, 136, 128, 135, 136, 120, 135,
+                142, 136, 127, 136, 136, 136, 136
+            ]
+        );
+        assert_eq!(
+            alpha,
+            [
+                159, 105, 134, 16, 117, 45, 6, 13, 56, 172, 136, 80, 120, 136, 136, 119, 119, 136,
+                120, 119, 172, 136, 80, 120, 135, 120, 135, 172, 136, 175, 136, 135, 136, 136, 143,
+                136, 136, 136, 136, 136, 135
+            ]
+        );
+        for hash in [&opaque, &alpha] {
+            assert_eq!(detect_version(hash), Ok(ThumbHashVersion::Extended));
+            assert_eq!(validate_thumb_hash(hash), Ok(()));
+            assert_eq!(Channels::read(hash).unwrap().write(), *hash);
+        }
+        let c = Channels::read(&opaque).unwrap();
+        assert_eq!((c.lx, c.ly, c.pq_n, c.a_n), (9, 7, 5, 0));
+        let c = Channels::read(&alpha).unwrap();
+        assert_eq!((c.lx, c.ly, c.pq_n, c.a_n), (5, 7, 5, 5));
+
+        // The first 3 bytes are the same as in a standard hash
+        let standard = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        assert_eq!(opaque[..3], standard[..3]);
+        assert_eq!(decoded_size(&opaque), (32, 25));
+        assert!(Channels::read(&opaque[..opaque.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn progressive_is_byte_exact() {
+        let opaque = rgba_to_thumb_hash_progressive(32, 24, &sample(32, 24));
+        let alpha = rgba_to_thumb_hash_progressive(32, 24, &sample_with_alpha(32, 24));
+        assert_eq!(
+            opaque,
+            [
+                94, 73, 6, 32, 29, 140, 14, 14, 254, 120, 135, 136, 120, 136, 135, 135, 136, 136,
+                120, 136, 120, 136
+            ]
+        );
+        assert_eq!(
+            alpha,
+            [
+                159, 105, 134, 32, 20, 134, 56, 12, 12, 252, 127, 90, 167, 133, 170, 120, 135, 136,
+                120, 136, 120, 120, 120, 135, 136, 7
+            ]
+        );
+        for hash in [&opaque, &alpha] {
+            assert_eq!(detect_version(hash), Ok(ThumbHashVersion::Progressive));
+            assert_eq!(validate_thumb_hash(hash), Ok(()));
+        }
+
+        // Converting a standard hash gives the same bytes and the same image
+        let standard = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        assert_eq!(thumb_hash_to_progressive(&standard), Ok(opaque.clone()));
+        assert_eq!(thumb_hash_to_progressive(&opaque), Ok(opaque.clone()));
+        assert_eq!(thumb_hash_to_rgba(&opaque), thumb_hash_to_rgba(&standard));
+        let extended = rgba_to_thumb_hash_extended(32, 24, &sample(32, 24));
+        assert_eq!(thumb_hash_to_progressive(&extended), Err(()));
+    }
+
+    #[test]
+    fn progressive_prefixes_decode() {
+        for (hash, header_len) in [
+            (rgba_to_thumb_hash_progressive(32, 24, &sample(32, 24)), 6),
+            (
+                rgba_to_thumb_hash_progressive(32, 24, &sample_with_alpha(32, 24)),
+                7,
+            ),
+        ] {
+            assert!(thumb_hash_to_rgba(&hash[..header_len - 1]).is_err());
+            let full = thumb_hash_to_rgba(&hash).unwrap();
+            let mut previous_error = u64::MAX;
+            for len in header_len..=hash.len() {
+                let (w, h, rgba) = thumb_hash_to_rgba(&hash[..len]).unwrap();
+                assert_eq!((w, h), (full.0, full.1));
+                let error: u64 = rgba
+                    .iter()
+                    .zip(&full.2)
+                    .map(|(&a, &b)| (a as i64 - b as i64).unsigned_abs())
+                    .sum();
+                assert!(error <= previous_error, "{len}");
+                previous_error = error;
+            }
+            assert_eq!(previous_error, 0);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_what_the_decoder_tolerates() {
+        let standard = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        assert_eq!(validate_thumb_hash(&standard), Ok(()));
+        assert_eq!(
+            thumb_hash_to_rgba_strict(&standard),
+            thumb_hash_to_rgba(&standard)
+        );
+
+        // An extra byte, a nonzero padding nibble, and a luminance count that
+        // doesn't match the aspect ratio all decode but aren't valid
+        let extra = [&standard[..], &[0]].concat();
+        let mut padding = rgba_to_thumb_hash(32, 32, &sample(32, 32));
+        *padding.last_mut().unwrap() |= 0xf0;
+        let mut counts = rgba_to_thumb_hash(32, 24, &sample_with_alpha(32, 24));
+        counts[3] |= 7;
+        counts.extend_from_slice(&[0; 8]);
+        for hash in [&extra, &padding, &counts] {
+            assert!(thumb_hash_to_rgba(hash).is_ok());
+            assert_eq!(validate_thumb_hash(hash), Err(()));
+            assert!(thumb_hash_to_rgba_strict(hash).is_err());
+        }
+
+        // Progressive hashes can only be cut at the end of a level
+        let progressive = rgba_to_thumb_hash_progressive(32, 24, &sample(32, 24));
+        let valid: Vec<usize> = (6..=progressive.len())
+            .filter(|&len| validate_thumb_hash(&progressive[..len]).is_ok())
+            .collect();
+        assert_eq!(valid, [6, 9, 18, 22]);
+    }
+
+    #[test]
+    fn expected_hash_len_matches_the_encoder() {
+        for (w, h) in [
+            (2, 2),
+            (60, 2),
+            (2, 100),
+            (32, 24),
+            (24, 32),
+            (60, 20),
+            (60, 61),
+        ] {
+            for alpha in [false, true] {
+                let rgba = if alpha {
+                    sample_with_alpha(w, h)
+                } else {
+                    sample(w, h)
+                };
+                let hash = rgba_to_thumb_hash(w, h, &rgba);
+                assert_eq!(
+                    expected_hash_len(w, h, alpha),
+                    hash.len(),
+                    "{w}x{h} {alpha}"
+                );
+            }
+        }
+        assert_eq!(expected_hash_len(100, 1, false), 17);
+        assert_eq!(expected_hash_len(1, 1, false), 24);
+        assert_eq!(expected_hash_len(1, 1, true), 25);
+    }
+
+    #[test]
+    fn has_alpha_reads_every_format() {
+        type Encoder = fn(usize, usize, &[u8]) -> Vec<u8>;
+        let encoders: [Encoder; 3] = [
+            rgba_to_thumb_hash,
+            rgba_to_thumb_hash_extended,
+            rgba_to_thumb_hash_progressive,
+        ];
+        for encode in encoders {
+            let opaque = encode(32, 24, &sample(32, 24));
+            let alpha = encode(32, 24, &sample_with_alpha(32, 24));
+            assert_eq!(
+                (has_alpha(&opaque), is_opaque(&opaque)),
+                (Ok(false), Ok(true))
+            );
+            assert_eq!(
+                (has_alpha(&alpha), is_opaque(&alpha)),
+                (Ok(true), Ok(false))
+            );
+            assert_eq!(
+                has_alpha(&alpha),
+                Ok(Channels::read(&alpha).unwrap().has_alpha)
+            );
+        }
+        assert_eq!(is_opaque(&[]), Err(()));
+    }
+}
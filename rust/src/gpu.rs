@@ -0,0 +1,358 @@
+use crate::format::Format;
+use crate::{average_color, normalize_channel, rgba_to_thumb_hash, Channels, DecodeOptions};
+use std::sync::mpsc;
+use wgpu::util::{DeviceExt, TextureDataOrder};
+
+/// Decodes a ThumbHash and uploads it to a new texture in one call, which is
+/// useful for showing placeholders in game engines and GPU-accelerated
+/// galleries. Only available with the `wgpu` feature.
+///
+/// The longer side of the texture is `max_size` pixels (see
+/// [`DecodeOptions::max_size`]). The texture has the `Rgba8UnormSrgb` format
+/// with RGB not premultiplied by A, and can be used for sampling and as a copy
+/// destination. An error will be returned if the input is too short.
+pub fn upload_thumb_hash(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    hash: &[u8],
+    max_size: usize,
+) -> Result<wgpu::Texture, ()> {
+    let channels = Channels::read(hash)?;
+    let options = DecodeOptions {
+        max_size,
+        ..DecodeOptions::default()
+    };
+    let (w, h) = options.size(&channels);
+    let rgba = channels.render(w, h);
+    let desc = wgpu::TextureDescriptor {
+        label: Some("ThumbHash"),
+        size: wgpu::Extent3d {
+            width: w as u32,
+            height: h as u32,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba8UnormSrgb,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    };
+    Ok(device.create_texture_with_data(queue, &desc, TextureDataOrder::LayerMajor, &rgba))
+}
+
+/// The number of basis functions along each axis that the compute shader
+/// evaluates, which is enough for every channel of the standard format.
+const DCT_SIZE: usize = 7;
+
+/// Encodes many images to ThumbHashes at once, using a compute shader on the
+/// GPU if one is available and the CPU otherwise. Only available with the
+/// `wgpu` feature.
+///
+/// This is meant for ingesting lots of images, where sending a whole batch to
+/// the GPU is much faster than encoding the images one at a time. The GPU adds
+/// up values in a different order than the CPU, so a hash from the GPU can
+/// occasionally differ from [`rgba_to_thumb_hash`] by one step in a
+/// coefficient.
+pub struct BatchEncoder {
+    gpu: Option<Gpu>,
+}
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl BatchEncoder {
+    /// Creates an encoder that runs on the CPU, for when there's no GPU.
+    pub fn cpu() -> BatchEncoder {
+        BatchEncoder { gpu: None }
+    }
+
+    /// Creates an encoder that runs on the given device.
+    pub fn gpu(device: &wgpu::Device, queue: &wgpu::Queue) -> BatchEncoder {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("ThumbHash"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("encode.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("ThumbHash"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        BatchEncoder {
+            gpu: Some(Gpu {
+                device: device.clone(),
+                queue: queue.clone(),
+                pipeline,
+            }),
+        }
+    }
+
+    /// Returns true if this encoder runs on the GPU.
+    pub fn is_gpu(&self) -> bool {
+        self.gpu.is_some()
+    }
+
+    /// Encodes each image, which is its width, height, and pixels in the same
+    /// form that [`rgba_to_thumb_hash`] takes. Returns the hashes in the same
+    /// order as the images. If the GPU fails (e.g. the device is lost), the
+    /// affected images are encoded on the CPU instead.
+    pub fn encode(&self, images: &[(usize, usize, &[u8])]) -> Vec<Vec<u8>> {
+        for &(w, h, rgba) in images {
+            assert!(w <= 100 && h <= 100);
+            assert_eq!(rgba.len(), w * h * 4);
+        }
+        let Some(gpu) = &self.gpu else {
+            return images
+                .iter()
+                .map(|&(w, h, rgba)| rgba_to_thumb_hash(w, h, rgba))
+                .collect();
+        };
+
+        // Split the images into batches that fit within the limits of the device
+        let limits = gpu.device.limits();
+        let max_bytes = limits.max_storage_buffer_binding_size as usize;
+        let max_images = (limits.max_compute_workgroups_per_dimension as usize)
+            .min(max_bytes / (DCT_SIZE * DCT_SIZE * 16));
+        let mut hashes = Vec::with_capacity(images.len());
+        let mut start = 0;
+        while start < images.len() {
+            let mut end = start;
+            let mut bytes = 0;
+            while end < images.len() && end - start < max_images {
+                bytes += images[end].2.len();
+                if bytes > max_bytes && end > start {
+                    break;
+                }
+                end += 1;
+            }
+            hashes.extend(gpu.encode(&images[start..end]));
+            start = end;
+        }
+        hashes
+    }
+}
+
+impl Gpu {
+    fn encode(&self, images: &[(usize, usize, &[u8])]) -> Vec<Vec<u8>> {
+        let averages: Vec<_> = images
+            .iter()
+            .map(|&(_, _, rgba)| average_color(rgba.chunks_exact(4).map(pixel)))
+            .collect();
+
+        // Empty images can't be divided by their size, so leave them to the CPU
+        let on_gpu: Vec<usize> = (0..images.len())
+            .filter(|&i| images[i].0 * images[i].1 > 0)
+            .collect();
+        let dct = if on_gpu.is_empty() {
+            None
+        } else {
+            self.dct(images, &averages, &on_gpu)
+        };
+
+        let mut dct = dct
+            .as_deref()
+            .map(|dct| dct.chunks_exact(DCT_SIZE * DCT_SIZE));
+        images
+            .iter()
+            .zip(&averages)
+            .map(|(&(w, h, rgba), average)| match (&mut dct, w * h > 0) {
+                (Some(dct), true) => {
+                    let dct = dct.next().unwrap();
+                    let has_alpha = average.3 < (w * h) as f32;
+                    let format = Format::Standard;
+                    let sizes = format.sizes(has_alpha);
+                    Channels::from_dct(w, h, has_alpha, format, sizes, |c, nx, ny| {
+                        normalize_channel(nx, ny, |cx, cy| dct[cx + cy * DCT_SIZE][c])
+                    })
+                    .write()
+                }
+                _ => rgba_to_thumb_hash(w, h, rgba),
+            })
+            .collect()
+    }
+
+    /// Runs the compute shader on the images at the given indices and returns
+    /// the DCT of each one, or `None` if the GPU failed.
+    fn dct(
+        &self,
+        images: &[(usize, usize, &[u8])],
+        averages: &[(f32, f32, f32, f32)],
+        indices: &[usize],
+    ) -> Option<Vec<[f32; 4]>> {
+        // Upload the images, which must match the "Image" struct in the shader
+        let mut info = Vec::with_capacity(indices.len() * 32);
+        let mut pixels = Vec::new();
+        for &i in indices {
+            let (w, h, rgba) = images[i];
+            let (r, g, b, _) = averages[i];
+            for word in [(pixels.len() / 4) as u32, w as u32, h as u32, 0] {
+                info.extend_from_slice(&word.to_le_bytes());
+            }
+            for value in [r, g, b, 0.0] {
+                info.extend_from_slice(&value.to_le_bytes());
+            }
+            pixels.extend_from_slice(rgba);
+        }
+        let device = &self.device;
+        let storage = |contents: &[u8]| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("ThumbHash"),
+                contents,
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        };
+        let (info, pixels) = (storage(&info), storage(&pixels));
+        let size = (indices.len() * DCT_SIZE * DCT_SIZE * 16) as u64;
+        let output = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ThumbHash"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ThumbHash"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ThumbHash"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: info.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: pixels.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+        });
+
+        // Run one workgroup per basis function per image
+        let mut encoder = device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups((DCT_SIZE * DCT_SIZE) as u32, indices.len() as u32, 1);
+        }
+        encoder.copy_buffer_to_buffer(&output, 0, &readback, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        // Wait for the results
+        let (sender, receiver) = mpsc::channel();
+        readback.map_async(wgpu::MapMode::Read, .., move |result| {
+            let _ = sender.send(result);
+        });
+        device.poll(wgpu::PollType::wait_indefinitely()).ok()?;
+        receiver.recv().ok()?.ok()?;
+        let data = readback.get_mapped_range(..).ok()?;
+        let dct = data
+            .chunks_exact(16)
+            .map(|v| {
+                let f = |i: usize| f32::from_le_bytes([v[i], v[i + 1], v[i + 2], v[i + 3]]);
+                [f(0), f(4), f(8), f(12)]
+            })
+            .collect();
+        Some(dct)
+    }
+}
+
+fn pixel(rgba: &[u8]) -> [u8; 4] {
+    [rgba[0], rgba[1], rgba[2], rgba[3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample, sample_with_alpha};
+
+    use std::future::Future;
+    use std::task::{Context, Poll, Waker};
+
+    /// Waits for a future from wgpu, which is already ready (or soon will be)
+    /// for native backends.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    /// Returns a device if this machine has an adapter, which isn't the case
+    /// on most CI machines.
+    fn device() -> Option<(wgpu::Device, wgpu::Queue)> {
+        let instance = wgpu::Instance::default();
+        let adapter = block_on(instance.request_adapter(&Default::default())).ok()?;
+        block_on(adapter.request_device(&Default::default())).ok()
+    }
+
+    #[test]
+    fn upload_thumb_hash_creates_a_texture_of_the_decoded_size() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+        let hash = rgba_to_thumb_hash(40, 20, &sample(40, 20));
+        let texture = upload_thumb_hash(&device, &queue, &hash, 64).unwrap();
+        let (w, h) = crate::decoded_dimensions_for(&hash, 64).unwrap();
+        assert_eq!((texture.width(), texture.height()), (w as u32, h as u32));
+        assert_eq!(texture.format(), wgpu::TextureFormat::Rgba8UnormSrgb);
+        assert!(upload_thumb_hash(&device, &queue, &hash[..4], 32).is_err());
+    }
+
+    #[test]
+    fn cpu_batch_encoder_matches_rgba_to_thumb_hash() {
+        let (opaque, alpha) = (sample(40, 20), sample_with_alpha(30, 30));
+        let encoder = BatchEncoder::cpu();
+        assert!(!encoder.is_gpu());
+        assert_eq!(
+            encoder.encode(&[(40, 20, &opaque), (30, 30, &alpha), (0, 0, &[])]),
+            [
+                rgba_to_thumb_hash(40, 20, &opaque),
+                rgba_to_thumb_hash(30, 30, &alpha),
+                rgba_to_thumb_hash(0, 0, &[]),
+            ],
+        );
+    }
+
+    #[test]
+    fn gpu_batch_encoder_matches_rgba_to_thumb_hash() {
+        let Some((device, queue)) = device() else {
+            return;
+        };
+        let (opaque, alpha) = (sample(40, 20), sample_with_alpha(30, 30));
+        let encoder = BatchEncoder::gpu(&device, &queue);
+        assert!(encoder.is_gpu());
+
+        // The GPU can round a coefficient differently, so allow one step
+        let hashes = encoder.encode(&[(40, 20, &opaque), (0, 0, &[]), (30, 30, &alpha)]);
+        let expected = [
+            rgba_to_thumb_hash(40, 20, &opaque),
+            rgba_to_thumb_hash(0, 0, &[]),
+            rgba_to_thumb_hash(30, 30, &alpha),
+        ];
+        for (hash, expected) in hashes.iter().zip(&expected) {
+            assert_eq!(hash.len(), expected.len());
+            assert_eq!(hash[..5], expected[..5]);
+            for (&a, &b) in hash[5..].iter().zip(&expected[5..]) {
+                assert!((a >> 4).abs_diff(b >> 4) <= 1 && (a & 15).abs_diff(b & 15) <= 1);
+            }
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use crate::{base64, ThumbHash};
+use async_graphql::{InputValueError, InputValueResult, Scalar, ScalarType, Value};
+
+/// A ThumbHash, sent as a base64 string. Input is checked to make sure it can
+/// be decoded. Only available with the `async-graphql` feature.
+#[Scalar(name = "ThumbHash")]
+impl ScalarType for ThumbHash {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        let Value::String(text) = &value else {
+            return Err(InputValueError::expected_type(value));
+        };
+        let bytes = base64::decode(text).map_err(|()| InputValueError::custom("Invalid base64"))?;
+        ThumbHash::try_from(bytes).map_err(|()| InputValueError::custom("Invalid ThumbHash"))
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(base64::encode(&self.bytes))
+    }
+}
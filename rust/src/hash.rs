@@ -0,0 +1,257 @@
+use crate::{detect_version, Channels, ThumbHashVersion};
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+/// A ThumbHash that is known to be decodable.
+///
+/// This wraps the bytes of the hash, which are checked when the value is
+/// created, so decoding it can't fail. It also provides typed access to the
+/// fields in the header of the hash, which is mostly useful for tooling and
+/// tests that want to look at what the encoder did. The header is read once
+/// when the value is created, so these accessors are cheap. Comparing and
+/// hashing only look at the bytes.
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "diesel",
+    derive(diesel::expression::AsExpression, diesel::deserialize::FromSqlRow),
+    diesel(sql_type = diesel::sql_types::Binary)
+)]
+pub struct ThumbHash {
+    pub(crate) bytes: Vec<u8>,
+    header: Header,
+}
+
+/// The fields of a hash that [`ThumbHash`] provides access to.
+#[derive(Clone, Copy, Debug)]
+struct Header {
+    version: ThumbHashVersion,
+    decoded_dimensions: (usize, usize),
+    l_dc: f32,
+    p_dc: f32,
+    q_dc: f32,
+    a_dc: f32,
+    l_scale: f32,
+    p_scale: f32,
+    q_scale: f32,
+    a_scale: f32,
+    lx: usize,
+    ly: usize,
+    is_landscape: bool,
+    has_alpha: bool,
+}
+
+impl ThumbHash {
+    /// Encodes an RGBA image to a ThumbHash. This is the same as
+    /// [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash).
+    pub fn from_rgba(w: usize, h: usize, rgba: &[u8]) -> ThumbHash {
+        ThumbHash::try_from(crate::rgba_to_thumb_hash(w, h, rgba)).unwrap()
+    }
+
+    /// Wraps the bytes of a ThumbHash. An error will be returned if the bytes
+    /// can't be decoded.
+    pub fn from_bytes(bytes: &[u8]) -> Result<ThumbHash, ()> {
+        ThumbHash::try_from(bytes.to_vec())
+    }
+
+    /// Returns the bytes of the hash.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the bytes of the hash, consuming it.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Decodes the hash to an RGBA image. This is the same as
+    /// [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba).
+    pub fn to_rgba(&self) -> (usize, usize, Vec<u8>) {
+        crate::thumb_hash_to_rgba(&self.bytes).unwrap()
+    }
+
+    /// Returns the width and height of the image that [`ThumbHash::to_rgba`]
+    /// produces, without decoding any pixels.
+    pub fn decoded_dimensions(&self) -> (usize, usize) {
+        self.header.decoded_dimensions
+    }
+
+    /// Returns the format of the hash.
+    pub fn version(&self) -> ThumbHashVersion {
+        self.header.version
+    }
+
+    /// The average luminance, from 0 to 1.
+    pub fn l_dc(&self) -> f32 {
+        self.header.l_dc
+    }
+
+    /// The average of the yellow-blue chroma channel, from -1 to 1.
+    pub fn p_dc(&self) -> f32 {
+        self.header.p_dc
+    }
+
+    /// The average of the red-green chroma channel, from -1 to 1.
+    pub fn q_dc(&self) -> f32 {
+        self.header.q_dc
+    }
+
+    /// The average alpha, from 0 to 1. This is 1 if the hash has no alpha.
+    pub fn a_dc(&self) -> f32 {
+        self.header.a_dc
+    }
+
+    /// The magnitude of the largest luminance AC term.
+    pub fn l_scale(&self) -> f32 {
+        self.header.l_scale
+    }
+
+    /// The magnitude of the largest yellow-blue AC term.
+    pub fn p_scale(&self) -> f32 {
+        self.header.p_scale
+    }
+
+    /// The magnitude of the largest red-green AC term.
+    pub fn q_scale(&self) -> f32 {
+        self.header.q_scale
+    }
+
+    /// The magnitude of the largest alpha AC term.
+    pub fn a_scale(&self) -> f32 {
+        self.header.a_scale
+    }
+
+    /// The number of horizontal luminance coefficients that encode the aspect
+    /// ratio. The decoder uses at least 3 regardless.
+    pub fn lx(&self) -> usize {
+        self.header.lx
+    }
+
+    /// The number of vertical luminance coefficients that encode the aspect
+    /// ratio. The decoder uses at least 3 regardless.
+    pub fn ly(&self) -> usize {
+        self.header.ly
+    }
+
+    /// Whether the original image was wider than it was tall.
+    pub fn is_landscape(&self) -> bool {
+        self.header.is_landscape
+    }
+
+    /// Whether the hash has an alpha channel.
+    pub fn has_alpha(&self) -> bool {
+        self.header.has_alpha
+    }
+}
+
+impl PartialEq for ThumbHash {
+    fn eq(&self, other: &ThumbHash) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for ThumbHash {}
+
+impl Hash for ThumbHash {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bytes.hash(state);
+    }
+}
+
+impl PartialOrd for ThumbHash {
+    fn partial_cmp(&self, other: &ThumbHash) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for ThumbHash {
+    fn cmp(&self, other: &ThumbHash) -> Ordering {
+        self.bytes.cmp(&other.bytes)
+    }
+}
+
+impl AsRef<[u8]> for ThumbHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl TryFrom<&[u8]> for ThumbHash {
+    type Error = ();
+
+    fn try_from(bytes: &[u8]) -> Result<ThumbHash, ()> {
+        ThumbHash::from_bytes(bytes)
+    }
+}
+
+impl TryFrom<Vec<u8>> for ThumbHash {
+    type Error = ();
+
+    fn try_from(bytes: Vec<u8>) -> Result<ThumbHash, ()> {
+        let c = Channels::read(&bytes)?;
+        let header = Header {
+            version: detect_version(&bytes)?,
+            decoded_dimensions: c.size(32),
+            l_dc: c.l_dc,
+            p_dc: c.p_dc,
+            q_dc: c.q_dc,
+            a_dc: c.a_dc,
+            l_scale: c.l_scale,
+            p_scale: c.p_scale,
+            q_scale: c.q_scale,
+            a_scale: c.a_scale,
+            lx: c.lx,
+            ly: c.ly,
+            is_landscape: c.is_landscape,
+            has_alpha: c.has_alpha,
+        };
+        Ok(ThumbHash { bytes, header })
+    }
+}
+
+impl From<ThumbHash> for Vec<u8> {
+    fn from(hash: ThumbHash) -> Vec<u8> {
+        hash.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sample_with_alpha;
+    use crate::{rgba_to_thumb_hash, thumb_hash_to_rgba};
+    use std::collections::HashSet;
+
+    #[test]
+    fn header_matches_the_bytes() {
+        let bytes = rgba_to_thumb_hash(40, 30, &sample_with_alpha(40, 30));
+        let hash = ThumbHash::from_bytes(&bytes).unwrap();
+        let c = Channels::read(&bytes).unwrap();
+        assert_eq!(hash.version(), ThumbHashVersion::Standard);
+        assert_eq!(
+            (hash.l_dc(), hash.p_dc(), hash.q_dc()),
+            (c.l_dc, c.p_dc, c.q_dc)
+        );
+        assert_eq!((hash.a_dc(), hash.a_scale()), (c.a_dc, c.a_scale));
+        assert_eq!((hash.lx(), hash.ly()), (c.lx, c.ly));
+        assert!(hash.is_landscape() && hash.has_alpha());
+        let (w, h, rgba) = thumb_hash_to_rgba(&bytes).unwrap();
+        assert_eq!(hash.decoded_dimensions(), (w, h));
+        assert_eq!(hash.to_rgba(), (w, h, rgba));
+        assert_eq!(
+            hash,
+            ThumbHash::from_rgba(40, 30, &sample_with_alpha(40, 30))
+        );
+        assert_eq!(hash.into_bytes(), bytes);
+        assert_eq!(ThumbHash::from_bytes(&bytes[..4]), Err(()));
+    }
+
+    #[test]
+    fn compares_by_bytes() {
+        let a = ThumbHash::from_rgba(40, 30, &sample_with_alpha(40, 30));
+        let b = ThumbHash::from_rgba(30, 40, &sample_with_alpha(30, 40));
+        assert_ne!(a, b);
+        assert_eq!(a.cmp(&b), a.as_bytes().cmp(b.as_bytes()));
+        let set: HashSet<ThumbHash> = [a.clone(), b, a].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+}
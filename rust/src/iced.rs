@@ -0,0 +1,49 @@
+use crate::Channels;
+use iced_core::image::Handle;
+
+/// The longest side of the images from [`thumb_hash_to_iced_handle`]. iced
+/// scales the image up to fill larger widgets.
+const MAX_SIZE: usize = 256;
+
+/// Decodes a ThumbHash to an image handle for iced that fills as much of a
+/// `width` by `height` box as it can while keeping the aspect ratio of the
+/// original image. RGB is not premultiplied by A. Only available with the
+/// `iced` feature.
+///
+/// Decoding at the size the placeholder is shown at avoids blurry upscaling,
+/// but the longer side is limited to 256 pixels so that large widgets don't
+/// take long to decode. An error will be returned if the input is too short.
+pub fn thumb_hash_to_iced_handle(hash: &[u8], width: u32, height: u32) -> Result<Handle, ()> {
+    let channels = Channels::read(hash)?;
+    let (w, h) = handle_size(channels.aspect_ratio(), width, height);
+    Ok(Handle::from_rgba(w as u32, h as u32, channels.render(w, h)))
+}
+
+/// Returns the size of the image for a box, given the aspect ratio of the
+/// original image.
+fn handle_size(ratio: f32, width: u32, height: u32) -> (usize, usize) {
+    let (w, h) = (width.max(1) as f32, height.max(1) as f32);
+    let (w, h) = if w / h > ratio {
+        ((h * ratio).round().max(1.0), h)
+    } else {
+        (w, (w / ratio).round().max(1.0))
+    };
+    if w.max(h) > MAX_SIZE as f32 {
+        return Channels::size_for_ratio(ratio, MAX_SIZE);
+    }
+    (w as usize, h as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handle_size_fits_the_box_up_to_the_limit() {
+        assert_eq!(handle_size(1.5, 100, 100), (100, 67));
+        assert_eq!(handle_size(1.5, 300, 100), (150, 100));
+        assert_eq!(handle_size(16.0 / 9.0, 3840, 2160), (256, 144));
+        assert_eq!(handle_size(0.5, 1000, 4000), (128, 256));
+        assert_eq!(handle_size(1.0, 0, 0), (1, 1));
+    }
+}
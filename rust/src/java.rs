@@ -0,0 +1,89 @@
+use crate::Channels;
+use jni::errors::{Error, Result, ThrowRuntimeExAndDefault};
+use jni::objects::{JByteArray, JByteBuffer, JClass, JIntArray};
+use jni::sys::jint;
+use jni::{jni_str, Env, EnvUnowned};
+use std::slice;
+
+// These are the native methods of the "com.madebyevan.thumbhash.NativeThumbHash"
+// class in the "java" directory, which is a thin wrapper around them. Only
+// available with the `jni` feature.
+
+/// Encodes an RGBA image in a direct `ByteBuffer` to a ThumbHash. RGB should
+/// not be premultiplied by A.
+#[no_mangle]
+pub extern "system" fn Java_com_madebyevan_thumbhash_NativeThumbHash_rgbaToThumbHash<'local>(
+    mut env: EnvUnowned<'local>,
+    _class: JClass<'local>,
+    w: jint,
+    h: jint,
+    rgba: JByteBuffer<'local>,
+) -> JByteArray<'local> {
+    env.with_env(|env| -> Result<_> {
+        if !(0..=100).contains(&w) || !(0..=100).contains(&h) {
+            return Err(illegal_argument(env, "The image must be at most 100x100"));
+        }
+        let (w, h) = (w as usize, h as usize);
+        let Ok(address) = env.get_direct_buffer_address(&rgba) else {
+            return Err(illegal_argument(env, "The buffer must be direct"));
+        };
+        if env.get_direct_buffer_capacity(&rgba)? < w * h * 4 {
+            return Err(illegal_argument(env, "The image must have w*h*4 bytes"));
+        }
+        // Safety: the buffer has at least this many bytes and outlives this call
+        let rgba = unsafe { slice::from_raw_parts(address, w * h * 4) };
+        env.byte_array_from_slice(&crate::rgba_to_thumb_hash(w, h, rgba))
+    })
+    .resolve::<ThrowRuntimeExAndDefault>()
+}
+
+/// Decodes a ThumbHash to an image of ARGB pixels, which is the layout that
+/// `android.graphics.Bitmap` and `java.awt.image.BufferedImage` use. RGB is
+/// not premultiplied by A. The width and height are written to `size`.
+#[no_mangle]
+pub extern "system" fn Java_com_madebyevan_thumbhash_NativeThumbHash_thumbHashToARGB<'local>(
+    mut env: EnvUnowned<'local>,
+    _class: JClass<'local>,
+    hash: JByteArray<'local>,
+    size: JIntArray<'local>,
+) -> JIntArray<'local> {
+    env.with_env(|env| -> Result<_> {
+        let hash = env.convert_byte_array(&hash)?;
+        let Ok(channels) = Channels::read(&hash) else {
+            return Err(illegal_argument(env, "Invalid ThumbHash"));
+        };
+        let (w, h) = channels.size(32);
+        let argb = rgba_to_argb(&channels.render(w, h));
+        size.set_region(env, 0, &[w as jint, h as jint])?;
+        let pixels = env.new_int_array(argb.len())?;
+        pixels.set_region(env, 0, &argb)?;
+        Ok(pixels)
+    })
+    .resolve::<ThrowRuntimeExAndDefault>()
+}
+
+/// Packs RGBA bytes into the `0xAARRGGBB` ints that Java image APIs use.
+fn rgba_to_argb(rgba: &[u8]) -> Vec<jint> {
+    rgba.chunks_exact(4)
+        .map(|p| i32::from_be_bytes([p[3], p[0], p[1], p[2]]))
+        .collect()
+}
+
+fn illegal_argument(env: &mut Env, message: &str) -> Error {
+    let message = jni::strings::JNIString::from(message);
+    match env.throw_new(jni_str!("java/lang/IllegalArgumentException"), message) {
+        Ok(()) => Error::JavaException,
+        Err(err) => err,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_pixels_as_argb() {
+        let argb = rgba_to_argb(&[0x11, 0x22, 0x33, 0xff, 1, 2, 3, 0]);
+        assert_eq!(argb, [0xff112233u32 as jint, 0x00010203]);
+    }
+}
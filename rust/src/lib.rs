@@ -1,6 +1,123 @@
+#![allow(clippy::needless_range_loop, clippy::result_unit_err)]
+
 use std::f32::consts::PI;
 use std::io::Read;
 
+use format::{Extensions, Format};
+
+#[cfg(any(feature = "async-graphql", feature = "bevy", feature = "metadata"))]
+mod base64;
+#[cfg(feature = "bevy")]
+mod bevy;
+mod blurhash;
+#[cfg(feature = "cache")]
+mod cache;
+mod color;
+mod decode;
+#[cfg(feature = "diesel")]
+mod diesel;
+mod encode;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod format;
+#[cfg(feature = "wgpu")]
+mod gpu;
+#[cfg(feature = "async-graphql")]
+mod graphql;
+mod hash;
+#[cfg(feature = "iced")]
+mod iced;
+#[cfg(feature = "jni")]
+mod java;
+#[cfg(feature = "metadata")]
+mod metadata;
+#[cfg(feature = "node")]
+#[cfg_attr(test, allow(dead_code))] // napi doesn't register exports in tests
+mod node;
+mod ops;
+#[cfg(feature = "image")]
+mod pixel;
+#[cfg(feature = "python")]
+mod python;
+mod quality;
+mod shader;
+mod similarity;
+#[cfg(feature = "sqlx")]
+mod sqlx;
+mod tiled;
+mod timeline;
+#[cfg(feature = "ffmpeg")]
+mod video;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod yuv;
+
+#[cfg(feature = "bevy")]
+pub use bevy::{add_thumb_hash_image, thumb_hash_to_bevy_image};
+pub use blurhash::{blurhash_to_thumb_hash, thumb_hash_to_blurhash};
+#[cfg(feature = "cache")]
+pub use cache::ThumbHashCache;
+pub use color::{
+    contrast_ratio, contrasting_text_color, is_dark, is_monochrome, sort_key,
+    thumb_hash_to_accent_color, thumb_hash_to_average_css, thumb_hash_to_average_hex,
+    thumb_hash_to_average_rgba_u8, thumb_hash_to_color_grid, thumb_hash_to_corner_colors,
+    thumb_hash_to_detail_score, thumb_hash_to_edge_colors, thumb_hash_to_gradient_direction,
+    thumb_hash_to_luminance_stats, thumb_hash_to_palette, thumb_hash_to_theme_color,
+    LuminanceStats,
+};
+pub use decode::{
+    decode_and_upscale, decode_atlas, decode_thumb_hash, decode_thumb_hash_with_options,
+    decoded_dimensions, decoded_dimensions_for, decoded_dimensions_with_options,
+    thumb_hash_to_lpqa, thumb_hash_to_mipmaps, thumb_hash_to_planes, thumb_hash_to_rgb_over,
+    thumb_hash_to_rgba_in_rect, thumb_hash_to_rgba_into, thumb_hash_to_rgba_on_canvas,
+    thumb_hash_to_rgba_on_checkerboard, thumb_hash_to_rgba_tileable,
+    thumb_hash_to_rgba_with_options, ColorSpace, DecodeOptions, DecodedAtlas, DecodedThumbHash,
+};
+#[cfg(feature = "bytemuck")]
+pub use encode::rgba_pod_to_thumb_hash;
+#[cfg(feature = "arrayvec")]
+pub use encode::rgba_to_thumb_hash_arrayvec;
+pub use encode::{
+    encode_with_metadata, lpqa_to_thumb_hash, rgba_pixels_to_thumb_hash, rgba_region_to_thumb_hash,
+    rgba_to_thumb_hash_into, rgba_to_thumb_hash_with_options, rgba_to_thumb_hash_with_stride,
+    EncodeOptions, EncodedThumbHash, FrameAverage,
+};
+pub use format::{
+    detect_version, expected_hash_len, has_alpha, has_checksum, is_opaque,
+    rgba_to_thumb_hash_adaptive, rgba_to_thumb_hash_extended, rgba_to_thumb_hash_micro,
+    rgba_to_thumb_hash_mini, rgba_to_thumb_hash_progressive, thumb_hash_micro_to_rgba,
+    thumb_hash_mini_to_rgba, thumb_hash_to_aspect_ratio, thumb_hash_to_exact_aspect_ratio,
+    thumb_hash_to_progressive, thumb_hash_to_rgba_strict, thumb_hash_with_aspect_ratio,
+    thumb_hash_with_checksum, validate_thumb_hash, ThumbHashVersion,
+};
+#[cfg(feature = "wgpu")]
+pub use gpu::{upload_thumb_hash, BatchEncoder};
+pub use hash::ThumbHash;
+#[cfg(feature = "iced")]
+pub use iced::thumb_hash_to_iced_handle;
+#[cfg(feature = "metadata")]
+pub use metadata::{embed_thumb_hash, extract_thumb_hash, XMP_NAMESPACE};
+pub use ops::{
+    adjust_brightness, adjust_saturation, canonicalize, crop_to_aspect, flatten_onto, hue_rotate,
+    lerp, retarget_aspect_ratio, strip_alpha, tint, to_dark_mode,
+};
+#[cfg(feature = "image")]
+pub use pixel::thumb_hash_to_image;
+pub use quality::{thumb_hash_quality, ThumbHashQuality};
+pub use shader::{thumb_hash_to_shader, ShaderLanguage};
+pub use similarity::{
+    approx_eq, cluster_thumb_hashes, find_duplicates, fingerprint, thumb_hash_distance,
+    ThumbHashIndex,
+};
+pub use tiled::TiledThumbHash;
+pub use timeline::HashTimeline;
+#[cfg(feature = "ffmpeg")]
+pub use video::thumb_hash_from_video;
+pub use yuv::{nv12_to_thumb_hash, yuv420_to_thumb_hash, YuvColorSpace};
+
+/// The maximum number of bytes in a hash from [`rgba_to_thumb_hash`].
+pub const MAX_HASH_LEN: usize = 25;
+
 /// Encodes an RGBA image to a ThumbHash. RGB should not be premultiplied by A.
 ///
 /// * `w`: The width of the input image. Must be ≤100px.
@@ -10,323 +127,989 @@ pub fn rgba_to_thumb_hash(w: usize, h: usize, rgba: &[u8]) -> Vec<u8> {
     // Encoding an image larger than 100x100 is slow with no benefit
     assert!(w <= 100 && h <= 100);
     assert_eq!(rgba.len(), w * h * 4);
+    Channels::from_rgba(w, h, rgba, Format::Standard).write()
+}
 
-    // Determine the average color
-    let mut avg_r = 0.0;
-    let mut avg_g = 0.0;
-    let mut avg_b = 0.0;
-    let mut avg_a = 0.0;
-    for rgba in rgba.chunks_exact(4) {
-        let alpha = rgba[3] as f32 / 255.0;
-        avg_r += alpha / 255.0 * rgba[0] as f32;
-        avg_g += alpha / 255.0 * rgba[1] as f32;
-        avg_b += alpha / 255.0 * rgba[2] as f32;
-        avg_a += alpha;
+/// Decodes a ThumbHash to an RGBA image.
+///
+/// RGB is not be premultiplied by A. Returns the width, height, and pixels of
+/// the rendered placeholder image. An error will be returned if the input is
+/// too short, has a checksum that doesn't match (see
+/// [`thumb_hash_with_checksum`]), or isn't in a format this crate understands
+/// (see [`detect_version`]).
+pub fn thumb_hash_to_rgba(hash: &[u8]) -> Result<(usize, usize, Vec<u8>), ()> {
+    let channels = Channels::read(hash)?;
+    let (w, h) = channels.size(32);
+    Ok((w, h, channels.render(w, h)))
+}
+
+/// Extracts the average color from a ThumbHash.
+///
+/// Returns the RGBA values where each value ranges from 0 to 1. RGB is not be
+/// premultiplied by A. An error will be returned if the input is too short.
+pub fn thumb_hash_to_average_rgba(hash: &[u8]) -> Result<(f32, f32, f32, f32), ()> {
+    if detect_version(hash)? != ThumbHashVersion::Standard {
+        return Ok(Channels::read(hash)?.average_rgba());
     }
-    if avg_a > 0.0 {
-        avg_r /= avg_a;
-        avg_g /= avg_a;
-        avg_b /= avg_a;
+    if hash.len() < 5 {
+        return Err(());
+    }
+    let header = hash[0] as u32 | ((hash[1] as u32) << 8) | ((hash[2] as u32) << 16);
+    let l = (header & 63) as f32 / 63.0;
+    let p = ((header >> 6) & 63) as f32 / 31.5 - 1.0;
+    let q = ((header >> 12) & 63) as f32 / 31.5 - 1.0;
+    let has_alpha = (header >> 23) != 0;
+    let a = if has_alpha {
+        (hash.get(5).ok_or(())? & 15) as f32 / 15.0
+    } else {
+        1.0
+    };
+    let (r, g, b) = lpq_to_rgb(l, p, q);
+    Ok((r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0), a))
+}
+
+/// Extracts the approximate aspect ratio of the original image.
+///
+/// An error will be returned if the input is too short or isn't in a format
+/// this crate understands (see [`detect_version`]).
+pub fn thumb_hash_to_approximate_aspect_ratio(hash: &[u8]) -> Result<f32, ()> {
+    if detect_version(hash)? != ThumbHashVersion::Standard {
+        let channels = Channels::read(hash)?;
+        return Ok(channels.lx as f32 / channels.ly as f32);
     }
+    if hash.len() < 5 {
+        return Err(());
+    }
+    let has_alpha = (hash[2] & 0x80) != 0;
+    let l_max = if has_alpha { 5 } else { 7 };
+    let l_min = hash[3] & 7;
+    let is_landscape = (hash[4] & 0x80) != 0;
+    let lx = if is_landscape { l_max } else { l_min };
+    let ly = if is_landscape { l_min } else { l_max };
+    Ok(lx as f32 / ly as f32)
+}
 
-    let has_alpha = avg_a < (w * h) as f32;
-    let l_limit = if has_alpha { 5 } else { 7 }; // Use fewer luminance bits if there's alpha
-    let lx = (((l_limit * w) as f32 / w.max(h) as f32).round() as usize).max(1);
-    let ly = (((l_limit * h) as f32 / w.max(h) as f32).round() as usize).max(1);
-    let mut l = Vec::with_capacity(w * h); // luminance
-    let mut p = Vec::with_capacity(w * h); // yellow - blue
-    let mut q = Vec::with_capacity(w * h); // red - green
-    let mut a = Vec::with_capacity(w * h); // alpha
-
-    // Convert the image from RGBA to LPQA (composite atop the average color)
-    for rgba in rgba.chunks_exact(4) {
-        let alpha = rgba[3] as f32 / 255.0;
-        let r = avg_r * (1.0 - alpha) + alpha / 255.0 * rgba[0] as f32;
-        let g = avg_g * (1.0 - alpha) + alpha / 255.0 * rgba[1] as f32;
-        let b = avg_b * (1.0 - alpha) + alpha / 255.0 * rgba[2] as f32;
-        l.push((r + g + b) / 3.0);
-        p.push((r + g) / 2.0 - b);
-        q.push(r - g);
-        a.push(alpha);
-    }
-
-    // Encode using the DCT into DC (constant) and normalized AC (varying) terms
-    let encode_channel = |channel: &[f32], nx: usize, ny: usize| -> (f32, Vec<f32>, f32) {
-        let mut dc = 0.0;
-        let mut ac = Vec::with_capacity(nx * ny / 2);
-        let mut scale = 0.0;
-        let mut fx = [0.0].repeat(w);
-        for cy in 0..ny {
-            let mut cx = 0;
-            while cx * ny < nx * (ny - cy) {
-                let mut f = 0.0;
-                for x in 0..w {
-                    fx[x] = (PI / w as f32 * cx as f32 * (x as f32 + 0.5)).cos();
-                }
-                for y in 0..h {
-                    let fy = (PI / h as f32 * cy as f32 * (y as f32 + 0.5)).cos();
-                    for x in 0..w {
-                        f += channel[x + y * w] * fx[x] * fy;
-                    }
-                }
-                f /= (w * h) as f32;
-                if cx > 0 || cy > 0 {
-                    ac.push(f);
-                    scale = f.abs().max(scale);
+/// The contents of a ThumbHash in unpacked form.
+///
+/// The DC terms and scales are stored as floats, but the AC terms are kept as
+/// the 4-bit values that appear in the hash. This means reading a hash and
+/// writing it back out again gives the same bytes, and rendering matches the
+/// reference decoder exactly.
+#[derive(Clone, Debug)]
+struct Channels {
+    l_dc: f32,
+    p_dc: f32,
+    q_dc: f32,
+    a_dc: f32,
+    l_scale: f32,
+    p_scale: f32,
+    q_scale: f32,
+    a_scale: f32,
+    l_ac: Vec<u8>,
+    p_ac: Vec<u8>,
+    q_ac: Vec<u8>,
+    a_ac: Vec<u8>,
+
+    // The number of luminance coefficients along each axis (before being
+    // clamped to at least 3), which is what encodes the aspect ratio
+    lx: usize,
+    ly: usize,
+    is_landscape: bool,
+    has_alpha: bool,
+
+    // The size of the triangle of coefficients used for each channel
+    l_nx: usize,
+    l_ny: usize,
+    pq_n: usize,
+    a_n: usize,
+
+    // How the hash is laid out in bytes
+    format: Format,
+
+    // Optional data stored after the coefficients
+    extensions: Extensions,
+}
+
+impl Channels {
+    fn from_rgba(w: usize, h: usize, rgba: &[u8], format: Format) -> Channels {
+        Channels::from_rgba_with_stride(w, h, rgba, w * 4, format)
+    }
+
+    /// Like [`Channels::from_rgba`] but each row of pixels starts `stride`
+    /// bytes after the previous one.
+    fn from_rgba_with_stride(
+        w: usize,
+        h: usize,
+        rgba: &[u8],
+        stride: usize,
+        format: Format,
+    ) -> Channels {
+        // Empty images have no rows to split, but chunks() needs a nonzero size
+        let pixels = || {
+            rgba.chunks(stride.max(1))
+                .take(h)
+                .flat_map(|row| row[..w * 4].chunks_exact(4))
+                .map(|rgba| [rgba[0], rgba[1], rgba[2], rgba[3]])
+        };
+        Channels::from_pixels(w, h, pixels, format)
+    }
+
+    /// Encodes a `w` by `h` image whose pixels are visited row-by-row by the
+    /// iterators that `pixels` returns.
+    fn from_pixels<I: Iterator<Item = impl Pixel>>(
+        w: usize,
+        h: usize,
+        pixels: impl Fn() -> I,
+        format: Format,
+    ) -> Channels {
+        Channels::from_pixels_with_sizes(w, h, pixels, |has_alpha| {
+            (format, format.sizes(has_alpha))
+        })
+    }
+
+    /// Like [`Channels::from_pixels`] but `budget(has_alpha)` returns the
+    /// format and the number of terms to encode in the same form as
+    /// [`Format::sizes`], which must fit in the format.
+    fn from_pixels_with_sizes<I: Iterator<Item = impl Pixel>>(
+        w: usize,
+        h: usize,
+        pixels: impl Fn() -> I,
+        budget: impl FnOnce(bool) -> (Format, (usize, usize, usize)),
+    ) -> Channels {
+        let (avg_r, avg_g, avg_b, avg_a) = average_color(pixels());
+        let has_alpha = avg_a < (w * h) as f32;
+        let (format, sizes) = budget(has_alpha);
+        with_scratch(w * h * 4, |scratch| {
+            let (l, rest) = scratch.split_at_mut(w * h); // luminance
+            let (p, rest) = rest.split_at_mut(w * h); // yellow - blue
+            let (q, a) = rest.split_at_mut(w * h); // red - green, alpha
+
+            // Convert the image from RGBA to LPQA (composite atop the average color)
+            for (i, pixel) in pixels().enumerate() {
+                let ([r, g, b], alpha) = pixel.premultiplied();
+                let r = avg_r * (1.0 - alpha) + r;
+                let g = avg_g * (1.0 - alpha) + g;
+                let b = avg_b * (1.0 - alpha) + b;
+                l[i] = (r + g + b) / 3.0;
+                p[i] = (r + g) / 2.0 - b;
+                q[i] = r - g;
+                a[i] = alpha;
+            }
+
+            Channels::from_lpqa(w, h, [l, p, q, a], has_alpha, format, sizes)
+        })
+    }
+
+    /// Encodes a `w` by `h` image from its L, P, Q, and A planes, with as many
+    /// terms as `sizes` gives in the same form as [`Format::sizes`].
+    fn from_lpqa(
+        w: usize,
+        h: usize,
+        channels: [&[f32]; 4],
+        has_alpha: bool,
+        format: Format,
+        sizes: (usize, usize, usize),
+    ) -> Channels {
+        // Encode using the DCT into DC (constant) and normalized AC (varying) terms
+        Channels::from_dct(w, h, has_alpha, format, sizes, |channel, nx, ny| {
+            encode_channel(w, h, channels[channel], nx, ny)
+        })
+    }
+
+    /// Builds the channels of a `w` by `h` image from its DCT, where
+    /// `encode(channel, nx, ny)` returns the DC term, AC terms, and scale of
+    /// channel 0 (L), 1 (P), 2 (Q), or 3 (A) for a triangle of `nx` by `ny`
+    /// terms (see [`encode_channel`]). The number of terms is given by `sizes`
+    /// in the same form as [`Format::sizes`].
+    fn from_dct(
+        w: usize,
+        h: usize,
+        has_alpha: bool,
+        format: Format,
+        (l_limit, pq_n, a_n): (usize, usize, usize),
+        mut encode: impl FnMut(usize, usize, usize) -> (f32, Vec<u8>, f32),
+    ) -> Channels {
+        let (lx, ly) = luminance_counts(w, h, l_limit);
+        let (l_nx, l_ny) = format.l_triangle(lx, ly);
+        let l = encode(0, l_nx, l_ny);
+        let p = encode(1, pq_n, pq_n);
+        let q = encode(2, pq_n, pq_n);
+        let a = if has_alpha {
+            encode(3, a_n, a_n)
+        } else {
+            (1.0, Vec::new(), 1.0)
+        };
+        let ((l_dc, l_ac, l_scale), (p_dc, p_ac, p_scale)) = (l, p);
+        let ((q_dc, q_ac, q_scale), (a_dc, a_ac, a_scale)) = (q, a);
+
+        Channels {
+            l_dc,
+            p_dc,
+            q_dc,
+            a_dc,
+            l_scale,
+            p_scale,
+            q_scale,
+            a_scale,
+            l_ac,
+            p_ac,
+            q_ac,
+            a_ac,
+            lx,
+            ly,
+            is_landscape: w > h,
+            has_alpha,
+            l_nx,
+            l_ny,
+            pq_n,
+            a_n,
+            format,
+            extensions: Extensions::default(),
+        }
+    }
+
+    fn read(hash: &[u8]) -> Result<Channels, ()> {
+        match detect_version(hash)? {
+            ThumbHashVersion::Standard => Channels::read_standard(hash),
+            ThumbHashVersion::Extended => format::read_extended(hash),
+            ThumbHashVersion::Progressive => format::read_progressive(hash),
+            ThumbHashVersion::Micro | ThumbHashVersion::Mini | ThumbHashVersion::Unknown(_) => {
+                Err(())
+            }
+        }
+    }
+
+    fn read_standard(original: &[u8]) -> Result<Channels, ()> {
+        let mut hash = original;
+
+        // Read the constants
+        let header24 = read_byte(&mut hash)? as u32
+            | ((read_byte(&mut hash)? as u32) << 8)
+            | ((read_byte(&mut hash)? as u32) << 16);
+        let header16 = read_byte(&mut hash)? as u16 | ((read_byte(&mut hash)? as u16) << 8);
+        let l_dc = (header24 & 63) as f32 / 63.0;
+        let p_dc = ((header24 >> 6) & 63) as f32 / 31.5 - 1.0;
+        let q_dc = ((header24 >> 12) & 63) as f32 / 31.5 - 1.0;
+        let l_scale = ((header24 >> 18) & 31) as f32 / 31.0;
+        let has_alpha = (header24 >> 23) != 0;
+        let p_scale = ((header16 >> 3) & 63) as f32 / 63.0;
+        let q_scale = ((header16 >> 9) & 63) as f32 / 63.0;
+        let is_landscape = (header16 >> 15) != 0;
+        let l_max = if has_alpha { 5 } else { 7 };
+        let lx = if is_landscape { l_max } else { header16 & 7 } as usize;
+        let ly = if is_landscape { header16 & 7 } else { l_max } as usize;
+        let (l_nx, l_ny) = (lx.max(3), ly.max(3));
+        let a_n = if has_alpha { 5 } else { 0 };
+        let (a_dc, a_scale) = if has_alpha {
+            let header8 = read_byte(&mut hash)?;
+            ((header8 & 15) as f32 / 15.0, (header8 >> 4) as f32 / 15.0)
+        } else {
+            (1.0, 1.0)
+        };
+
+        // Read the varying factors
+        let [l_ac, p_ac, q_ac, a_ac] = read_ac(&mut hash, (l_nx, l_ny), 3, a_n)?;
+        if !hash.is_empty() && format::is_damaged_checksum_envelope(original) {
+            return Err(());
+        }
+
+        Ok(Channels {
+            l_dc,
+            p_dc,
+            q_dc,
+            a_dc,
+            l_scale,
+            p_scale,
+            q_scale,
+            a_scale,
+            l_ac,
+            p_ac,
+            q_ac,
+            a_ac,
+            lx,
+            ly,
+            is_landscape,
+            has_alpha,
+            l_nx,
+            l_ny,
+            pq_n: 3,
+            a_n,
+            format: Format::Standard,
+            extensions: Extensions::default(),
+        })
+    }
+
+    fn write(&self) -> Vec<u8> {
+        match self.format {
+            Format::Standard => self.write_standard(),
+            Format::Extended => format::write_extended(self),
+            Format::Micro => format::write_micro(self),
+            Format::Mini => format::write_mini(self),
+            Format::Progressive => format::write_progressive(self),
+        }
+    }
+
+    fn write_standard(&self) -> Vec<u8> {
+        let mut hash = Vec::with_capacity(MAX_HASH_LEN);
+        self.write_standard_to(&mut hash);
+        hash
+    }
+
+    fn write_standard_to(&self, hash: &mut impl HashBuf) {
+        // Write the constants
+        let header24 = self.header24();
+        let header16 = self.header16();
+        for byte in [
+            (header24 & 255) as u8,
+            ((header24 >> 8) & 255) as u8,
+            (header24 >> 16) as u8,
+            (header16 & 255) as u8,
+            (header16 >> 8) as u8,
+        ] {
+            hash.push(byte);
+        }
+        if self.has_alpha {
+            hash.push(quantize(15.0 * self.a_dc, 15) | (quantize(15.0 * self.a_scale, 15) << 4));
+        }
+
+        // Write the varying factors
+        self.write_ac(hash);
+    }
+
+    /// Returns the first 3 bytes of the standard format, which other formats
+    /// reuse so that the average color can always be read from them.
+    fn header24(&self) -> u32 {
+        quantize(63.0 * self.l_dc, 63) as u32
+            | ((quantize(31.5 + 31.5 * self.p_dc, 63) as u32) << 6)
+            | ((quantize(31.5 + 31.5 * self.q_dc, 63) as u32) << 12)
+            | ((quantize(31.0 * self.l_scale, 31) as u32) << 18)
+            | if self.has_alpha { 1 << 23 } else { 0 }
+    }
+
+    /// Returns the next 2 bytes of the standard format.
+    fn header16(&self) -> u16 {
+        (if self.is_landscape { self.ly } else { self.lx }) as u16
+            | ((quantize(63.0 * self.p_scale, 63) as u16) << 3)
+            | ((quantize(63.0 * self.q_scale, 63) as u16) << 9)
+            | if self.is_landscape { 1 << 15 } else { 0 }
+    }
+
+    /// Appends the AC terms of every channel, packed as 4-bit values.
+    fn write_ac(&self, hash: &mut impl HashBuf) {
+        let mut is_odd = false;
+        let a_ac: &[u8] = if self.has_alpha { &self.a_ac } else { &[] };
+        for ac in [&self.l_ac, &self.p_ac, &self.q_ac, a_ac] {
+            for &u in ac {
+                if is_odd {
+                    *hash.as_mut().last_mut().unwrap() |= u << 4;
                 } else {
-                    dc = f;
+                    hash.push(u);
                 }
-                cx += 1;
+                is_odd = !is_odd;
             }
         }
-        if scale > 0.0 {
-            for ac in &mut ac {
-                *ac = 0.5 + 0.5 / scale * *ac;
-            }
+    }
+
+    /// Returns the number of terms in the same form as [`Format::sizes`], which
+    /// can differ from the defaults of the format for extended hashes.
+    fn term_counts(&self) -> (usize, usize, usize) {
+        (self.lx.max(self.ly), self.pq_n, self.a_n)
+    }
+
+    /// Returns the aspect ratio of the original image, which is exact if the
+    /// hash stores it and approximate otherwise.
+    fn aspect_ratio(&self) -> f32 {
+        self.extensions
+            .aspect_ratio()
+            .unwrap_or(self.lx as f32 / self.ly as f32)
+    }
+
+    /// Returns the average color as RGBA values from 0 to 1.
+    fn average_rgba(&self) -> (f32, f32, f32, f32) {
+        let (r, g, b) = lpq_to_rgb(self.l_dc, self.p_dc, self.q_dc);
+        (
+            r.clamp(0.0, 1.0),
+            g.clamp(0.0, 1.0),
+            b.clamp(0.0, 1.0),
+            self.a_dc,
+        )
+    }
+
+    /// Returns the size of the image the decoder renders for this hash, where
+    /// the longer side is `max_size` pixels. The shorter side is at least 1
+    /// pixel.
+    fn size(&self, max_size: usize) -> (usize, usize) {
+        Channels::size_for_ratio(self.aspect_ratio(), max_size)
+    }
+
+    /// Like [`Channels::size`] but for an arbitrary aspect ratio.
+    fn size_for_ratio(ratio: f32, max_size: usize) -> (usize, usize) {
+        if ratio > 1.0 {
+            (
+                max_size,
+                ((max_size as f32 / ratio).round() as usize).max(1),
+            )
+        } else {
+            (
+                ((max_size as f32 * ratio).round() as usize).max(1),
+                max_size,
+            )
         }
-        (dc, ac, scale)
-    };
-    let (l_dc, l_ac, l_scale) = encode_channel(&l, lx.max(3), ly.max(3));
-    let (p_dc, p_ac, p_scale) = encode_channel(&p, 3, 3);
-    let (q_dc, q_ac, q_scale) = encode_channel(&q, 3, 3);
-    let (a_dc, a_ac, a_scale) = if has_alpha {
-        encode_channel(&a, 5, 5)
-    } else {
-        (1.0, Vec::new(), 1.0)
-    };
+    }
+
+    /// Returns the luminance coefficient counts that the encoder would pick
+    /// for an image with the given aspect ratio and at most `l_limit` terms
+    /// along the longer side.
+    fn layout(ratio: f32, l_limit: usize) -> (usize, usize, bool) {
+        if ratio > 1.0 {
+            let ly = ((l_limit as f32 / ratio).round() as usize).clamp(1, l_limit);
+            (l_limit, ly, true)
+        } else {
+            let lx = ((l_limit as f32 * ratio).round() as usize).clamp(1, l_limit);
+            (lx, l_limit, false)
+        }
+    }
+
+    /// Returns the term at `(cx, cy)` for a channel (0 for L, 1 for P, 2 for Q,
+    /// and 3 for A), or 0 if the hash doesn't store that term.
+    fn term(&self, channel: usize, cx: usize, cy: usize) -> f32 {
+        let (dc, ac, scale, nx, ny) = match channel {
+            0 => (self.l_dc, &self.l_ac, self.l_scale, self.l_nx, self.l_ny),
+            1 => (self.p_dc, &self.p_ac, self.p_scale, self.pq_n, self.pq_n),
+            2 => (self.q_dc, &self.q_ac, self.q_scale, self.pq_n, self.pq_n),
+            _ => (self.a_dc, &self.a_ac, self.a_scale, self.a_n, self.a_n),
+        };
+        if cx == 0 && cy == 0 {
+            return dc;
+        }
+        match ac_positions(nx, ny).iter().position(|&pos| pos == (cx, cy)) {
+            Some(i) => (ac[i] as f32 / 7.5 - 1.0) * scale,
+            None => 0.0,
+        }
+    }
+
+    /// Builds channels with the given layout and sizes of the P, Q, and A
+    /// triangles (where an A size of 0 means there's no alpha), where `term`
+    /// returns the value of each term the same way [`Channels::term`] does.
+    fn from_terms(
+        (lx, ly, is_landscape): (usize, usize, bool),
+        (pq_n, a_n): (usize, usize),
+        format: Format,
+        term: impl Fn(usize, usize, usize) -> f32,
+    ) -> Channels {
+        let (l_nx, l_ny) = format.l_triangle(lx, ly);
+        let has_alpha = a_n > 0;
+        let channel = |channel: usize, nx: usize, ny: usize| -> (f32, Vec<u8>, f32) {
+            let values: Vec<f32> = ac_positions(nx, ny)
+                .into_iter()
+                .map(|(cx, cy)| term(channel, cx, cy))
+                .collect();
+            let mut ac = Vec::new();
+            let scale = Channels::set_ac_values(&mut ac, &values);
+            (term(channel, 0, 0), ac, scale)
+        };
+        let (l_dc, l_ac, l_scale) = channel(0, l_nx, l_ny);
+        let (p_dc, p_ac, p_scale) = channel(1, pq_n, pq_n);
+        let (q_dc, q_ac, q_scale) = channel(2, pq_n, pq_n);
+        let (a_dc, a_ac, a_scale) = if has_alpha {
+            channel(3, a_n, a_n)
+        } else {
+            (1.0, Vec::new(), 1.0)
+        };
+        Channels {
+            l_dc,
+            p_dc,
+            q_dc,
+            a_dc,
+            l_scale,
+            p_scale,
+            q_scale,
+            a_scale,
+            l_ac,
+            p_ac,
+            q_ac,
+            a_ac,
+            lx,
+            ly,
+            is_landscape,
+            has_alpha,
+            l_nx,
+            l_ny,
+            pq_n,
+            a_n,
+            format,
+            extensions: Extensions::default(),
+        }
+    }
 
-    // Write the constants
-    let is_landscape = w > h;
-    let header24 = (63.0 * l_dc).round() as u32
-        | (((31.5 + 31.5 * p_dc).round() as u32) << 6)
-        | (((31.5 + 31.5 * q_dc).round() as u32) << 12)
-        | (((31.0 * l_scale).round() as u32) << 18)
-        | if has_alpha { 1 << 23 } else { 0 };
-    let header16 = (if is_landscape { ly } else { lx }) as u16
-        | (((63.0 * p_scale).round() as u16) << 3)
-        | (((63.0 * q_scale).round() as u16) << 9)
-        | if is_landscape { 1 << 15 } else { 0 };
-    let mut hash = Vec::with_capacity(25);
-    hash.extend_from_slice(&[
-        (header24 & 255) as u8,
-        ((header24 >> 8) & 255) as u8,
-        (header24 >> 16) as u8,
-        (header16 & 255) as u8,
-        (header16 >> 8) as u8,
-    ]);
-    let mut is_odd = false;
-    if has_alpha {
-        hash.push((15.0 * a_dc).round() as u8 | (((15.0 * a_scale).round() as u8) << 4));
-    }
-
-    // Write the varying factors
-    for ac in [l_ac, p_ac, q_ac] {
-        for f in ac {
-            let u = (15.0 * f).round() as u8;
-            if is_odd {
-                *hash.last_mut().unwrap() |= u << 4;
+    /// Returns the actual values of the AC terms for a channel, undoing the
+    /// normalization done by the encoder.
+    fn ac_values(ac: &[u8], scale: f32) -> Vec<f32> {
+        ac.iter()
+            .map(|&bits| (bits as f32 / 7.5 - 1.0) * scale)
+            .collect()
+    }
+
+    /// Replaces the AC terms for a channel with the given values and returns
+    /// the new scale. The values are normalized the same way the encoder does.
+    fn set_ac_values(ac: &mut Vec<u8>, values: &[f32]) -> f32 {
+        let scale = values.iter().fold(0.0f32, |scale, f| f.abs().max(scale));
+        ac.clear();
+        ac.extend(values.iter().map(|&f| {
+            let f = if scale > 0.0 {
+                0.5 + 0.5 / scale * f
             } else {
-                hash.push(u);
+                f
+            };
+            quantize(15.0 * f, 15)
+        }));
+        scale
+    }
+
+    /// Renders the channels to a `w` by `h` RGBA image. RGB is not
+    /// premultiplied by A.
+    fn render(&self, w: usize, h: usize) -> Vec<u8> {
+        let mut rgba = Vec::new();
+        self.render_into(w, h, &mut rgba);
+        rgba
+    }
+
+    /// Like [`Channels::render`] but appends the pixels to `rgba`.
+    fn render_into(&self, w: usize, h: usize, rgba: &mut Vec<u8>) {
+        // Evaluate each pixel directly to avoid an intermediate buffer
+        let basis = Basis::new(self);
+        rgba.reserve(w * h * 4);
+        for y in 0..h {
+            for x in 0..w {
+                let tx = PI / w as f32 * (x as f32 + 0.5);
+                let ty = PI / h as f32 * (y as f32 + 0.5);
+                rgba.extend_from_slice(&lpqa_to_rgba_u8(basis.lpqa(tx, ty)));
             }
-            is_odd = !is_odd;
         }
     }
-    if has_alpha {
-        for f in a_ac {
-            let u = (15.0 * f).round() as u8;
-            if is_odd {
-                *hash.last_mut().unwrap() |= u << 4;
-            } else {
-                hash.push(u);
+
+    /// Renders a `w` by `h` image into a larger RGBA buffer with its top-left
+    /// corner at `(x, y)`, where each row of the buffer starts `stride` bytes
+    /// after the previous one.
+    fn render_to(&self, dst: &mut [u8], stride: usize, (x, y, w, h): (usize, usize, usize, usize)) {
+        let basis = Basis::new(self);
+        for row in 0..h {
+            let start = x * 4 + (y + row) * stride;
+            let ty = PI / h as f32 * (row as f32 + 0.5);
+            for (column, pixel) in dst[start..start + w * 4].chunks_exact_mut(4).enumerate() {
+                let tx = PI / w as f32 * (column as f32 + 0.5);
+                pixel.copy_from_slice(&lpqa_to_rgba_u8(basis.lpqa(tx, ty)));
             }
-            is_odd = !is_odd;
         }
     }
-    hash
 }
 
-fn read_byte(bytes: &mut &[u8]) -> Result<u8, ()> {
-    let mut byte = [0; 1];
-    bytes.read_exact(&mut byte).map_err(|_| ())?;
-    Ok(byte[0])
+/// The dequantized AC terms of a hash, ready for evaluating the image.
+struct Basis<'a> {
+    channels: &'a Channels,
+    l_ac: Vec<f32>,
+    p_ac: Vec<f32>,
+    q_ac: Vec<f32>,
+    a_ac: Vec<f32>,
 }
 
-/// Decodes a ThumbHash to an RGBA image.
-///
-/// RGB is not be premultiplied by A. Returns the width, height, and pixels of
-/// the rendered placeholder image. An error will be returned if the input is
-/// too short.
-pub fn thumb_hash_to_rgba(mut hash: &[u8]) -> Result<(usize, usize, Vec<u8>), ()> {
-    let ratio = thumb_hash_to_approximate_aspect_ratio(hash)?;
-
-    // Read the constants
-    let header24 = read_byte(&mut hash)? as u32
-        | ((read_byte(&mut hash)? as u32) << 8)
-        | ((read_byte(&mut hash)? as u32) << 16);
-    let header16 = read_byte(&mut hash)? as u16 | ((read_byte(&mut hash)? as u16) << 8);
-    let l_dc = (header24 & 63) as f32 / 63.0;
-    let p_dc = ((header24 >> 6) & 63) as f32 / 31.5 - 1.0;
-    let q_dc = ((header24 >> 12) & 63) as f32 / 31.5 - 1.0;
-    let l_scale = ((header24 >> 18) & 31) as f32 / 31.0;
-    let has_alpha = (header24 >> 23) != 0;
-    let p_scale = ((header16 >> 3) & 63) as f32 / 63.0;
-    let q_scale = ((header16 >> 9) & 63) as f32 / 63.0;
-    let is_landscape = (header16 >> 15) != 0;
-    let l_max = if has_alpha { 5 } else { 7 };
-    let lx = 3.max(if is_landscape { l_max } else { header16 & 7 }) as usize;
-    let ly = 3.max(if is_landscape { header16 & 7 } else { l_max }) as usize;
-    let (a_dc, a_scale) = if has_alpha {
-        let header8 = read_byte(&mut hash)?;
-        ((header8 & 15) as f32 / 15.0, (header8 >> 4) as f32 / 15.0)
-    } else {
-        (1.0, 1.0)
-    };
+impl<'a> Basis<'a> {
+    fn new(channels: &'a Channels) -> Basis<'a> {
+        // Boost saturation by 1.25x to compensate for quantization
+        Basis {
+            channels,
+            l_ac: Channels::ac_values(&channels.l_ac, channels.l_scale),
+            p_ac: Channels::ac_values(&channels.p_ac, channels.p_scale * 1.25),
+            q_ac: Channels::ac_values(&channels.q_ac, channels.q_scale * 1.25),
+            a_ac: Channels::ac_values(&channels.a_ac, channels.a_scale),
+        }
+    }
 
-    // Read the varying factors (boost saturation by 1.25x to compensate for quantization)
-    let mut prev_bits = None;
-    let mut decode_channel = |nx: usize, ny: usize, scale: f32| -> Result<Vec<f32>, ()> {
-        let mut ac = Vec::with_capacity(nx * ny);
-        for cy in 0..ny {
+    /// Evaluates LPQA at a point. The coordinates are angles that go from 0 at
+    /// the top-left edge of the image to π at the bottom-right edge.
+    fn lpqa(&self, tx: f32, ty: f32) -> (f32, f32, f32, f32) {
+        // Precompute the coefficients
+        let mut fx = [0.0; 16];
+        let mut fy = [0.0; 16];
+        for cx in 0..self.nx() {
+            fx[cx] = (tx * cx as f32).cos();
+        }
+        for cy in 0..self.ny() {
+            fy[cy] = (ty * cy as f32).cos();
+        }
+        self.lpqa_with_factors(&fx, &fy)
+    }
+
+    /// The number of horizontal basis functions used by any channel.
+    fn nx(&self) -> usize {
+        let c = self.channels;
+        c.l_nx.max(c.pq_n).max(c.a_n)
+    }
+
+    /// The number of vertical basis functions used by any channel.
+    fn ny(&self) -> usize {
+        let c = self.channels;
+        c.l_ny.max(c.pq_n).max(c.a_n)
+    }
+
+    /// Evaluates LPQA given the value of each horizontal and vertical basis
+    /// function, which must have at least [`Basis::nx`] and [`Basis::ny`]
+    /// elements respectively.
+    fn lpqa_with_factors(&self, fx: &[f32], fy: &[f32]) -> (f32, f32, f32, f32) {
+        let c = self.channels;
+        let mut l = c.l_dc;
+        let mut p = c.p_dc;
+        let mut q = c.q_dc;
+        let mut a = c.a_dc;
+
+        // Decode L
+        let mut j = 0;
+        for cy in 0..c.l_ny {
             let mut cx = if cy > 0 { 0 } else { 1 };
-            while cx * ny < nx * (ny - cy) {
-                let bits = if let Some(bits) = prev_bits {
-                    prev_bits = None;
-                    bits
-                } else {
-                    let bits = read_byte(&mut hash)?;
-                    prev_bits = Some(bits >> 4);
-                    bits & 15
-                };
-                ac.push((bits as f32 / 7.5 - 1.0) * scale);
+            let fy2 = fy[cy] * 2.0;
+            while cx * c.l_ny < c.l_nx * (c.l_ny - cy) {
+                l += self.l_ac[j] * fx[cx] * fy2;
+                j += 1;
                 cx += 1;
             }
         }
-        Ok(ac)
-    };
-    let l_ac = decode_channel(lx, ly, l_scale)?;
-    let p_ac = decode_channel(3, 3, p_scale * 1.25)?;
-    let q_ac = decode_channel(3, 3, q_scale * 1.25)?;
-    let a_ac = if has_alpha {
-        decode_channel(5, 5, a_scale)?
-    } else {
-        Vec::new()
-    };
 
-    // Decode using the DCT into RGB
-    let (w, h) = if ratio > 1.0 {
-        (32, (32.0 / ratio).round() as usize)
-    } else {
-        ((32.0 * ratio).round() as usize, 32)
-    };
-    let mut rgba = Vec::with_capacity(w * h * 4);
-    let mut fx = [0.0].repeat(7);
-    let mut fy = [0.0].repeat(7);
-    for y in 0..h {
-        for x in 0..w {
-            let mut l = l_dc;
-            let mut p = p_dc;
-            let mut q = q_dc;
-            let mut a = a_dc;
-
-            // Precompute the coefficients
-            for cx in 0..lx.max(if has_alpha { 5 } else { 3 }) {
-                fx[cx] = (PI / w as f32 * (x as f32 + 0.5) * cx as f32).cos();
+        // Decode P and Q
+        let mut j = 0;
+        for cy in 0..c.pq_n {
+            let mut cx = if cy > 0 { 0 } else { 1 };
+            let fy2 = fy[cy] * 2.0;
+            while cx < c.pq_n - cy {
+                let f = fx[cx] * fy2;
+                p += self.p_ac[j] * f;
+                q += self.q_ac[j] * f;
+                j += 1;
+                cx += 1;
             }
-            for cy in 0..ly.max(if has_alpha { 5 } else { 3 }) {
-                fy[cy] = (PI / h as f32 * (y as f32 + 0.5) * cy as f32).cos();
+        }
+
+        // Decode A
+        let mut j = 0;
+        for cy in 0..c.a_n {
+            let mut cx = if cy > 0 { 0 } else { 1 };
+            let fy2 = fy[cy] * 2.0;
+            while cx < c.a_n - cy {
+                a += self.a_ac[j] * fx[cx] * fy2;
+                j += 1;
+                cx += 1;
             }
+        }
 
-            // Decode L
-            let mut j = 0;
-            for cy in 0..ly {
-                let mut cx = if cy > 0 { 0 } else { 1 };
-                let fy2 = fy[cy] * 2.0;
-                while cx * ly < lx * (ly - cy) {
-                    l += l_ac[j] * fx[cx] * fy2;
-                    j += 1;
-                    cx += 1;
-                }
+        (l, p, q, a)
+    }
+
+    /// Evaluates LPQA at the center of every pixel of a `w` by `h` image, row
+    /// by row.
+    fn grid(&self, w: usize, h: usize) -> Vec<(f32, f32, f32, f32)> {
+        let mut lpqa = Vec::with_capacity(w * h);
+        for y in 0..h {
+            for x in 0..w {
+                lpqa.push(self.lpqa(
+                    PI / w as f32 * (x as f32 + 0.5),
+                    PI / h as f32 * (y as f32 + 0.5),
+                ));
             }
+        }
+        lpqa
+    }
+}
 
-            // Decode P and Q
-            let mut j = 0;
-            for cy in 0..3 {
-                let mut cx = if cy > 0 { 0 } else { 1 };
-                let fy2 = fy[cy] * 2.0;
-                while cx < 3 - cy {
-                    let f = fx[cx] * fy2;
-                    p += p_ac[j] * f;
-                    q += q_ac[j] * f;
-                    j += 1;
-                    cx += 1;
-                }
+/// A pixel that the encoder can read.
+trait Pixel {
+    /// Returns the RGB values premultiplied by alpha and the alpha value, all
+    /// from 0 to 1.
+    fn premultiplied(self) -> ([f32; 3], f32);
+}
+
+/// RGBA values from 0 to 255 where RGB is not premultiplied by A.
+impl Pixel for [u8; 4] {
+    fn premultiplied(self) -> ([f32; 3], f32) {
+        let alpha = self[3] as f32 / 255.0;
+        ([0, 1, 2].map(|i| alpha / 255.0 * self[i] as f32), alpha)
+    }
+}
+
+/// Opaque RGB values from 0 to 1, for sources that aren't stored as RGBA
+/// bytes (which would lose precision to rounding).
+impl Pixel for [f32; 3] {
+    fn premultiplied(self) -> ([f32; 3], f32) {
+        (self, 1.0)
+    }
+}
+
+/// Returns the average color of some pixels weighted by alpha, along with the
+/// sum of their alpha values.
+fn average_color(pixels: impl Iterator<Item = impl Pixel>) -> (f32, f32, f32, f32) {
+    let mut avg_r = 0.0;
+    let mut avg_g = 0.0;
+    let mut avg_b = 0.0;
+    let mut avg_a = 0.0;
+    for pixel in pixels {
+        let ([r, g, b], alpha) = pixel.premultiplied();
+        avg_r += r;
+        avg_g += g;
+        avg_b += b;
+        avg_a += alpha;
+    }
+    if avg_a > 0.0 {
+        avg_r /= avg_a;
+        avg_g /= avg_a;
+        avg_b /= avg_a;
+    }
+    (avg_r, avg_g, avg_b, avg_a)
+}
+
+/// Returns the number of luminance coefficients along each axis that the
+/// encoder uses for a `w` by `h` image, which is what encodes the aspect ratio.
+fn luminance_counts(w: usize, h: usize, l_limit: usize) -> (usize, usize) {
+    let lx = (((l_limit * w) as f32 / w.max(h) as f32).round() as usize).max(1);
+    let ly = (((l_limit * h) as f32 / w.max(h) as f32).round() as usize).max(1);
+    (lx, ly)
+}
+
+/// Encodes a channel using the DCT into DC (constant) and normalized AC
+/// (varying) terms. The AC terms are returned quantized to 4 bits.
+fn encode_channel(
+    w: usize,
+    h: usize,
+    channel: &[f32],
+    nx: usize,
+    ny: usize,
+) -> (f32, Vec<u8>, f32) {
+    let mut fx = [0.0].repeat(w);
+    normalize_channel(nx, ny, |cx, cy| {
+        let mut f = 0.0;
+        for x in 0..w {
+            fx[x] = (PI / w as f32 * cx as f32 * (x as f32 + 0.5)).cos();
+        }
+        for y in 0..h {
+            let fy = (PI / h as f32 * cy as f32 * (y as f32 + 0.5)).cos();
+            for x in 0..w {
+                f += channel[x + y * w] * fx[x] * fy;
             }
+        }
+        f / (w * h) as f32
+    })
+}
 
-            // Decode A
-            if has_alpha {
-                let mut j = 0;
-                for cy in 0..5 {
-                    let mut cx = if cy > 0 { 0 } else { 1 };
-                    let fy2 = fy[cy] * 2.0;
-                    while cx < 5 - cy {
-                        a += a_ac[j] * fx[cx] * fy2;
-                        j += 1;
-                        cx += 1;
-                    }
-                }
+/// Splits the DCT of a channel into DC (constant) and normalized AC (varying)
+/// terms for a triangle of `nx` by `ny` terms, where `dct(cx, cy)` returns the
+/// average of the channel times the basis function for `cx` and `cy`. The AC
+/// terms are returned quantized to 4 bits.
+fn normalize_channel(
+    nx: usize,
+    ny: usize,
+    mut dct: impl FnMut(usize, usize) -> f32,
+) -> (f32, Vec<u8>, f32) {
+    let mut dc = 0.0;
+    let mut ac = Vec::with_capacity(nx * ny / 2);
+    let mut scale = 0.0;
+    for cy in 0..ny {
+        let mut cx = 0;
+        while cx * ny < nx * (ny - cy) {
+            let f = dct(cx, cy);
+            if cx > 0 || cy > 0 {
+                ac.push(f);
+                scale = f.abs().max(scale);
+            } else {
+                dc = f;
             }
+            cx += 1;
+        }
+    }
+    if scale > 0.0 {
+        for ac in &mut ac {
+            *ac = 0.5 + 0.5 / scale * *ac;
+        }
+    }
+    let ac = ac.into_iter().map(|f| quantize(15.0 * f, 15)).collect();
+    (dc, ac, scale)
+}
 
-            // Convert to RGB
-            let b = l - 2.0 / 3.0 * p;
-            let r = (3.0 * l - b + q) / 2.0;
-            let g = r - q;
-            rgba.extend_from_slice(&[
-                (r.clamp(0.0, 1.0) * 255.0) as u8,
-                (g.clamp(0.0, 1.0) * 255.0) as u8,
-                (b.clamp(0.0, 1.0) * 255.0) as u8,
-                (a.clamp(0.0, 1.0) * 255.0) as u8,
-            ]);
+/// Reads the AC terms of every channel, which are packed as 4-bit values.
+fn read_ac(
+    hash: &mut &[u8],
+    (l_nx, l_ny): (usize, usize),
+    pq_n: usize,
+    a_n: usize,
+) -> Result<[Vec<u8>; 4], ()> {
+    let mut prev_bits = None;
+    let mut read_channel = |nx: usize, ny: usize| -> Result<Vec<u8>, ()> {
+        let mut ac = Vec::with_capacity(nx * ny);
+        for _ in 0..ac_count(nx, ny) {
+            let bits = if let Some(bits) = prev_bits {
+                prev_bits = None;
+                bits
+            } else {
+                let bits = read_byte(hash)?;
+                prev_bits = Some(bits >> 4);
+                bits & 15
+            };
+            ac.push(bits);
+        }
+        Ok(ac)
+    };
+    Ok([
+        read_channel(l_nx, l_ny)?,
+        read_channel(pq_n, pq_n)?,
+        read_channel(pq_n, pq_n)?,
+        read_channel(a_n, a_n)?,
+    ])
+}
+
+/// Returns the number of AC terms in a triangle of `nx` by `ny` coefficients.
+fn ac_count(nx: usize, ny: usize) -> usize {
+    ac_positions(nx, ny).len()
+}
+
+/// Returns the `(cx, cy)` position of every AC term in a triangle of `nx` by
+/// `ny` coefficients, in the order they're stored in the hash.
+fn ac_positions(nx: usize, ny: usize) -> Vec<(usize, usize)> {
+    let mut positions = Vec::with_capacity(nx * ny);
+    for cy in 0..ny {
+        let mut cx = if cy > 0 { 0 } else { 1 };
+        while cx * ny < nx * (ny - cy) {
+            positions.push((cx, cy));
+            cx += 1;
         }
     }
-    Ok((w, h, rgba))
+    positions
 }
 
-/// Extracts the average color from a ThumbHash.
-///
-/// Returns the RGBA values where each value ranges from 0 to 1. RGB is not be
-/// premultiplied by A. An error will be returned if the input is too short.
-pub fn thumb_hash_to_average_rgba(hash: &[u8]) -> Result<(f32, f32, f32, f32), ()> {
-    if hash.len() < 5 {
-        return Err(());
+/// Calls `f` with a zeroed buffer of `len` floats for the encoder's
+/// intermediate values. With the `scratch` feature, this reuses a buffer that
+/// belongs to the current thread instead of allocating a new one every time.
+#[cfg(feature = "scratch")]
+fn with_scratch<R>(len: usize, f: impl FnOnce(&mut [f32]) -> R) -> R {
+    thread_local! {
+        static SCRATCH: std::cell::RefCell<Vec<f32>> = const { std::cell::RefCell::new(Vec::new()) };
     }
-    let header = hash[0] as u32 | ((hash[1] as u32) << 8) | ((hash[2] as u32) << 16);
-    let l = (header & 63) as f32 / 63.0;
-    let p = ((header >> 6) & 63) as f32 / 31.5 - 1.0;
-    let q = ((header >> 12) & 63) as f32 / 31.5 - 1.0;
-    let has_alpha = (header >> 23) != 0;
-    let a = if has_alpha {
-        (hash[5] & 15) as f32 / 15.0
-    } else {
-        1.0
-    };
+    SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+        Ok(mut scratch) => {
+            scratch.clear();
+            scratch.resize(len, 0.0);
+            f(&mut scratch)
+        }
+        Err(_) => f(&mut vec![0.0; len]),
+    })
+}
+
+#[cfg(not(feature = "scratch"))]
+fn with_scratch<R>(len: usize, f: impl FnOnce(&mut [f32]) -> R) -> R {
+    f(&mut vec![0.0; len])
+}
+
+/// Rounds a value to the nearest integer in `0..=max`.
+fn quantize(x: f32, max: u8) -> u8 {
+    x.round().clamp(0.0, max as f32) as u8
+}
+
+fn rgb_to_lpq(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    ((r + g + b) / 3.0, (r + g) / 2.0 - b, r - g)
+}
+
+fn lpq_to_rgb(l: f32, p: f32, q: f32) -> (f32, f32, f32) {
     let b = l - 2.0 / 3.0 * p;
     let r = (3.0 * l - b + q) / 2.0;
     let g = r - q;
-    Ok((r.clamp(0.0, 1.0), g.clamp(0.0, 1.0), b.clamp(0.0, 1.0), a))
+    (r, g, b)
 }
 
-/// Extracts the approximate aspect ratio of the original image.
-///
-/// An error will be returned if the input is too short.
-pub fn thumb_hash_to_approximate_aspect_ratio(hash: &[u8]) -> Result<f32, ()> {
-    if hash.len() < 5 {
-        return Err(());
+fn lpqa_to_rgba_u8((l, p, q, a): (f32, f32, f32, f32)) -> [u8; 4] {
+    let (r, g, b) = lpq_to_rgb(l, p, q);
+    [
+        (r.clamp(0.0, 1.0) * 255.0) as u8,
+        (g.clamp(0.0, 1.0) * 255.0) as u8,
+        (b.clamp(0.0, 1.0) * 255.0) as u8,
+        (a.clamp(0.0, 1.0) * 255.0) as u8,
+    ]
+}
+
+/// Checks the arguments that [`rgba_to_thumb_hash`] would otherwise panic on,
+/// for the language bindings where a panic can't be caught and turned into an
+/// exception (it aborts a WebAssembly module, for example).
+#[cfg(any(feature = "node", feature = "python", feature = "wasm"))]
+fn check_rgba(w: usize, h: usize, rgba: &[u8]) -> Result<(), &'static str> {
+    if w > 100 || h > 100 {
+        return Err("The image must be at most 100x100");
+    }
+    if rgba.len() != w * h * 4 {
+        return Err("The image must have w*h*4 bytes");
+    }
+    Ok(())
+}
+
+fn read_byte(bytes: &mut &[u8]) -> Result<u8, ()> {
+    let mut byte = [0; 1];
+    bytes.read_exact(&mut byte).map_err(|_| ())?;
+    Ok(byte[0])
+}
+
+/// Somewhere the encoder can write the bytes of a hash, so it can write
+/// directly into buffers other than a `Vec`.
+trait HashBuf: AsRef<[u8]> + AsMut<[u8]> {
+    fn push(&mut self, byte: u8);
+}
+
+impl HashBuf for Vec<u8> {
+    fn push(&mut self, byte: u8) {
+        Vec::push(self, byte);
+    }
+}
+
+#[cfg(feature = "arrayvec")]
+impl<const N: usize> HashBuf for arrayvec::ArrayVec<u8, N> {
+    fn push(&mut self, byte: u8) {
+        arrayvec::ArrayVec::push(self, byte);
+    }
+}
+
+/// Images and hashes that the tests of every module share.
+#[cfg(test)]
+mod test_util {
+    /// Returns an opaque, mostly red `w` by `h` image whose red fades toward
+    /// the right and whose green grows toward the bottom.
+    pub(crate) fn sample(w: usize, h: usize) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(w * h * 4);
+        for y in 0..h {
+            for x in 0..w {
+                let (r, g) = (200 - x * 96 / w, 60 + y * 120 / h);
+                rgba.extend_from_slice(&[r as u8, g as u8, 90, 255]);
+            }
+        }
+        rgba
+    }
+
+    /// Returns [`sample`] with alpha that fades out toward the right.
+    pub(crate) fn sample_with_alpha(w: usize, h: usize) -> Vec<u8> {
+        let mut rgba = sample(w, h);
+        for (i, pixel) in rgba.chunks_exact_mut(4).enumerate() {
+            pixel[3] = 255 - (i % w * 255 / w) as u8;
+        }
+        rgba
+    }
+
+    /// Returns the hash of a solid image whose red channel is `r`.
+    pub(crate) fn solid_hash(r: u8) -> Vec<u8> {
+        let rgba: Vec<u8> = (0..8 * 8).flat_map(|_| [r, 100, 50, 255]).collect();
+        crate::rgba_to_thumb_hash(8, 8, &rgba)
+    }
+}
+
+#[cfg(all(test, any(feature = "node", feature = "python", feature = "wasm")))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_rgba_rejects_what_the_encoder_panics_on() {
+        assert_eq!(check_rgba(100, 1, &[0; 400]), Ok(()));
+        assert!(check_rgba(101, 1, &[0; 404]).is_err());
+        assert!(check_rgba(1, 101, &[0; 404]).is_err());
+        assert!(check_rgba(2, 2, &[0; 15]).is_err());
     }
-    let has_alpha = (hash[2] & 0x80) != 0;
-    let l_max = if has_alpha { 5 } else { 7 };
-    let l_min = hash[3] & 7;
-    let is_landscape = (hash[4] & 0x80) != 0;
-    let lx = if is_landscape { l_max } else { l_min };
-    let ly = if is_landscape { l_min } else { l_max };
-    Ok(lx as f32 / ly as f32)
 }
@@ -0,0 +1,492 @@
+use crate::base64;
+
+/// The XMP namespace of the property that [`embed_thumb_hash`] stores the hash
+/// in. Only available with the `metadata` feature.
+pub const XMP_NAMESPACE: &str = "https://github.com/evanw/thumbhash/xmp/1.0/";
+
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const PNG_XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// Stores a ThumbHash in the XMP metadata of a JPEG or PNG file, so the
+/// placeholder travels along with the image through CDNs and asset management
+/// systems. Only available with the `metadata` feature.
+///
+/// Returns a copy of the file with the hash stored as a base64 string in the
+/// `ThumbHash` property of the [`XMP_NAMESPACE`] namespace. Any XMP metadata
+/// the file already has is kept, and a hash that was stored before is
+/// replaced. The image data itself isn't touched. An error will be returned
+/// if the file isn't a JPEG or PNG file, if it's malformed, or if its XMP
+/// metadata can't be updated (e.g. if it's compressed).
+pub fn embed_thumb_hash(image: &[u8], hash: &[u8]) -> Result<Vec<u8>, ()> {
+    let value = base64::encode(hash);
+    if image.starts_with(&[0xFF, 0xD8]) {
+        embed_jpeg(image, &value)
+    } else if image.starts_with(PNG_SIGNATURE) {
+        embed_png(image, &value)
+    } else {
+        Err(())
+    }
+}
+
+/// Reads a ThumbHash from the XMP metadata of a JPEG, PNG, or WebP file, such
+/// as one stored by [`embed_thumb_hash`]. This only looks at the metadata, so
+/// it's cheap enough to do for every request (e.g. in a CDN edge worker).
+/// Only available with the `metadata` feature.
+///
+/// Returns `None` if the file doesn't have a stored hash. An error will be
+/// returned if the file isn't a JPEG, PNG, or WebP file, if it's malformed, or
+/// if its XMP metadata can't be read (e.g. if it's compressed).
+pub fn extract_thumb_hash(image: &[u8]) -> Result<Option<Vec<u8>>, ()> {
+    let xmp = if image.starts_with(&[0xFF, 0xD8]) {
+        jpeg_segments(image)?
+            .into_iter()
+            .filter(|segment| segment.marker == 0xE1)
+            .find_map(|segment| segment.data(image).strip_prefix(JPEG_XMP_HEADER))
+            .map(|xmp| std::str::from_utf8(xmp).map_err(|_| ()))
+    } else if image.starts_with(PNG_SIGNATURE) {
+        png_chunks(image)?
+            .into_iter()
+            .filter(|chunk| &chunk.kind == b"iTXt")
+            .find_map(|chunk| png_xmp(chunk.data(image)))
+    } else if image.starts_with(b"RIFF") && image.get(8..12) == Some(b"WEBP") {
+        webp_xmp(image)?.map(|xmp| std::str::from_utf8(xmp).map_err(|_| ()))
+    } else {
+        return Err(());
+    };
+    match xmp {
+        Some(xmp) => read_xmp(xmp?),
+        None => Ok(None),
+    }
+}
+
+fn embed_jpeg(image: &[u8], value: &str) -> Result<Vec<u8>, ()> {
+    // Look for an existing XMP segment, and otherwise insert one after the
+    // JFIF and EXIF segments since some readers expect those to come first
+    let mut insert_at = 2;
+    let mut existing = None;
+    for segment in jpeg_segments(image)? {
+        if segment.marker == 0xE1 && segment.data(image).starts_with(JPEG_XMP_HEADER) {
+            existing = Some(segment);
+            break;
+        }
+        if (segment.marker == 0xE0 || segment.marker == 0xE1) && insert_at == segment.start {
+            insert_at = segment.end;
+        }
+    }
+    let (start, end, xmp) = match existing {
+        Some(segment) => {
+            let xmp = &segment.data(image)[JPEG_XMP_HEADER.len()..];
+            let xmp = std::str::from_utf8(xmp).map_err(|_| ())?;
+            (segment.start, segment.end, update_xmp(xmp, value)?)
+        }
+        None => (insert_at, insert_at, new_xmp(value)),
+    };
+
+    // The length of a segment includes its own two bytes
+    let len = 2 + JPEG_XMP_HEADER.len() + xmp.len();
+    if len > 0xFFFF {
+        return Err(());
+    }
+    let mut result = Vec::with_capacity(image.len() + len + 2);
+    result.extend_from_slice(&image[..start]);
+    result.extend_from_slice(&[0xFF, 0xE1, (len >> 8) as u8, len as u8]);
+    result.extend_from_slice(JPEG_XMP_HEADER);
+    result.extend_from_slice(xmp.as_bytes());
+    result.extend_from_slice(&image[end..]);
+    Ok(result)
+}
+
+fn embed_png(image: &[u8], value: &str) -> Result<Vec<u8>, ()> {
+    // Look for an existing XMP chunk, and otherwise insert one after the
+    // header chunk, which must come first
+    let mut insert_at = None;
+    let mut existing = None;
+    for chunk in png_chunks(image)? {
+        if &chunk.kind == b"iTXt" && png_xmp(chunk.data(image)).is_some() {
+            existing = Some(chunk);
+            break;
+        }
+        if &chunk.kind == b"IHDR" {
+            insert_at = Some(chunk.end);
+        }
+    }
+    let (start, end, xmp) = match existing {
+        Some(chunk) => {
+            let xmp = png_xmp(chunk.data(image)).unwrap()?;
+            (chunk.start, chunk.end, update_xmp(xmp, value)?)
+        }
+        None => {
+            let at = insert_at.ok_or(())?;
+            (at, at, new_xmp(value))
+        }
+    };
+
+    // The text is uncompressed and has no language tag or translated keyword
+    let mut chunk = b"iTXt".to_vec();
+    chunk.extend_from_slice(PNG_XMP_KEYWORD);
+    chunk.extend_from_slice(&[0, 0, 0, 0, 0]);
+    chunk.extend_from_slice(xmp.as_bytes());
+    let len = u32::try_from(chunk.len() - 4).map_err(|_| ())?;
+    let mut result = Vec::with_capacity(image.len() + chunk.len() + 8);
+    result.extend_from_slice(&image[..start]);
+    result.extend_from_slice(&len.to_be_bytes());
+    result.extend_from_slice(&chunk);
+    result.extend_from_slice(&crc32(&chunk).to_be_bytes());
+    result.extend_from_slice(&image[end..]);
+    Ok(result)
+}
+
+/// A segment of a JPEG file before the image data. The range includes the
+/// marker and length.
+struct JpegSegment {
+    marker: u8,
+    start: usize,
+    data: usize,
+    end: usize,
+}
+
+impl JpegSegment {
+    fn data<'a>(&self, image: &'a [u8]) -> &'a [u8] {
+        &image[self.data..self.end]
+    }
+}
+
+/// Returns the segments of a JPEG file up to the start of the image data.
+fn jpeg_segments(image: &[u8]) -> Result<Vec<JpegSegment>, ()> {
+    let mut segments = Vec::new();
+    let mut i = 2;
+    loop {
+        if image.get(i) != Some(&0xFF) {
+            return Err(());
+        }
+
+        // Markers may be preceded by any number of fill bytes
+        let start = i;
+        while image.get(i + 1) == Some(&0xFF) {
+            i += 1;
+        }
+        let marker = *image.get(i + 1).ok_or(())?;
+        let len = image.get(i + 2..i + 4).ok_or(())?;
+        let end = i + 2 + u16::from_be_bytes([len[0], len[1]]) as usize;
+        if end > image.len() || end < i + 4 {
+            return Err(());
+        }
+
+        // The start of scan marker is followed by the image data
+        if marker == 0xDA {
+            return Ok(segments);
+        }
+        segments.push(JpegSegment {
+            marker,
+            start,
+            data: i + 4,
+            end,
+        });
+        i = end;
+    }
+}
+
+/// A chunk of a PNG file. The range includes the length, type, and CRC.
+struct PngChunk {
+    kind: [u8; 4],
+    start: usize,
+    end: usize,
+}
+
+impl PngChunk {
+    fn data<'a>(&self, image: &'a [u8]) -> &'a [u8] {
+        &image[self.start + 8..self.end - 4]
+    }
+}
+
+/// Returns the chunks of a PNG file.
+fn png_chunks(image: &[u8]) -> Result<Vec<PngChunk>, ()> {
+    let mut chunks = Vec::new();
+    let mut i = PNG_SIGNATURE.len();
+    while i < image.len() {
+        let header = image.get(i..i + 8).ok_or(())?;
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let end = i.checked_add(12 + len).ok_or(())?;
+        if end > image.len() {
+            return Err(());
+        }
+        let kind = [header[4], header[5], header[6], header[7]];
+        chunks.push(PngChunk {
+            kind,
+            start: i,
+            end,
+        });
+        if &kind == b"IEND" {
+            break;
+        }
+        i = end;
+    }
+    Ok(chunks)
+}
+
+/// Returns the XMP packet in the data of an `iTXt` chunk, or `None` if the
+/// chunk isn't for XMP. An error will be returned if it's compressed.
+fn png_xmp(data: &[u8]) -> Option<Result<&str, ()>> {
+    let rest = data.strip_prefix(PNG_XMP_KEYWORD)?.strip_prefix(&[0])?;
+    let (compressed, rest) = rest.split_first()?;
+    if *compressed != 0 {
+        return Some(Err(()));
+    }
+
+    // Skip the compression method, language tag, and translated keyword
+    let mut parts = rest.get(1..)?.splitn(3, |&b| b == 0);
+    let (_, _, text) = (parts.next()?, parts.next()?, parts.next()?);
+    Some(std::str::from_utf8(text).map_err(|_| ()))
+}
+
+/// Returns the data of the XMP chunk of a WebP file, if there is one.
+fn webp_xmp(image: &[u8]) -> Result<Option<&[u8]>, ()> {
+    let mut rest = image.get(12..).ok_or(())?;
+    while !rest.is_empty() {
+        let header = rest.get(..8).ok_or(())?;
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let data = rest.get(8..8 + len).ok_or(())?;
+        if &header[..4] == b"XMP " {
+            return Ok(Some(data));
+        }
+
+        // Chunks are padded to an even length
+        rest = &rest[(8 + len + (len & 1)).min(rest.len())..];
+    }
+    Ok(None)
+}
+
+/// Returns a new XMP packet with only the ThumbHash property.
+fn new_xmp(value: &str) -> String {
+    format!(
+        "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+        <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+        <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+        <rdf:Description rdf:about=\"\" xmlns:thumbhash=\"{XMP_NAMESPACE}\" thumbhash:ThumbHash=\"{value}\"/>\
+        </rdf:RDF>\
+        </x:xmpmeta>\
+        <?xpacket end=\"w\"?>"
+    )
+}
+
+/// Sets the ThumbHash property of an existing XMP packet, replacing the value
+/// from an earlier call if there is one. Every form of the property that
+/// [`read_xmp`] understands is removed first so that the new value is the one
+/// that's read back.
+fn update_xmp(xmp: &str, value: &str) -> Result<String, ()> {
+    let mut xmp = xmp.to_string();
+    for prefix in xmp_prefixes(&xmp) {
+        remove_attribute(&mut xmp, &format!("xmlns:{prefix}"))?;
+        remove_attribute(&mut xmp, &format!("{prefix}:ThumbHash"))?;
+        let name = format!("{prefix}:ThumbHash");
+        while let Some(start) = xmp.find(&format!("<{name}>")) {
+            let close = format!("</{name}>");
+            let len = xmp[start..].find(&close).ok_or(())?;
+            xmp.replace_range(start..start + len + close.len(), "");
+        }
+    }
+    remove_attribute(&mut xmp, "xmlns:thumbhash")?;
+    let at = xmp.find("<rdf:Description").ok_or(())? + "<rdf:Description".len();
+    xmp.insert_str(
+        at,
+        &format!(" xmlns:thumbhash=\"{XMP_NAMESPACE}\" thumbhash:ThumbHash=\"{value}\""),
+    );
+    Ok(xmp)
+}
+
+/// Removes every attribute called `name` (and any whitespace before it) from
+/// an XMP packet. An error will be returned if a value isn't terminated.
+fn remove_attribute(xmp: &mut String, name: &str) -> Result<(), ()> {
+    for quote in ['"', '\''] {
+        let pattern = format!("{name}={quote}");
+        let mut from = 0;
+        while let Some(found) = xmp[from..].find(&pattern) {
+            let start = from + found;
+            let before = xmp[..start].trim_end().len();
+            if before == start {
+                from = start + pattern.len();
+                continue;
+            }
+            let value = start + pattern.len();
+            let len = xmp[value..].find(quote).ok_or(())?;
+            xmp.replace_range(before..value + len + 1, "");
+            from = before;
+        }
+    }
+    Ok(())
+}
+
+/// Returns every prefix that is bound to [`XMP_NAMESPACE`] in an XMP packet.
+fn xmp_prefixes(xmp: &str) -> Vec<String> {
+    let mut prefixes = Vec::new();
+    for quote in ['"', '\''] {
+        for (end, _) in xmp.match_indices(&format!("={quote}{XMP_NAMESPACE}{quote}")) {
+            if let Some(start) = xmp[..end].rfind("xmlns:") {
+                let prefix = &xmp[start + "xmlns:".len()..end];
+                if !prefixes.iter().any(|p| p == prefix) {
+                    prefixes.push(prefix.to_string());
+                }
+            }
+        }
+    }
+    prefixes
+}
+
+/// Reads the ThumbHash property from an XMP packet. The property can be either
+/// an attribute or an element, and its namespace can use any prefix.
+fn read_xmp(xmp: &str) -> Result<Option<Vec<u8>>, ()> {
+    let value = xmp_prefixes(xmp).into_iter().find_map(|prefix| {
+        let name = format!("{prefix}:ThumbHash");
+        ["\"", "'"]
+            .into_iter()
+            .find_map(|quote| {
+                let start = xmp.find(&format!("{name}={quote}"))? + name.len() + 2;
+                let len = xmp[start..].find(quote)?;
+                Some(&xmp[start..start + len])
+            })
+            .or_else(|| {
+                let start = xmp.find(&format!("<{name}>"))? + name.len() + 2;
+                let len = xmp[start..].find(&format!("</{name}>"))?;
+                Some(&xmp[start..start + len])
+            })
+    });
+    match value {
+        Some(value) => base64::decode(value.trim()).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// The CRC-32 that PNG uses for each chunk.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &[u8] = &[0x1B, 0x08, 0x06, 0x0D, 0x80, 0x57, 0x78, 0x87];
+
+    fn packet(description: &str) -> String {
+        format!(
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\
+            <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+            {description}\
+            </rdf:RDF>\
+            </x:xmpmeta>"
+        )
+    }
+
+    fn jpeg() -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46]);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    fn png() -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        for (kind, data) in [(&b"IHDR"[..], &[0; 13][..]), (b"IEND", &[])] {
+            let chunk = [kind, data].concat();
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(&chunk);
+            png.extend_from_slice(&crc32(&chunk).to_be_bytes());
+        }
+        png
+    }
+
+    #[test]
+    fn embeds_in_jpeg() {
+        let image = jpeg();
+        let embedded = embed_thumb_hash(&image, HASH).unwrap();
+        let xmp = new_xmp("GwgGDYBXeIc=");
+        let len = 2 + JPEG_XMP_HEADER.len() + xmp.len();
+        let expected = [
+            &image[..8],
+            &[0xFF, 0xE1, (len >> 8) as u8, len as u8],
+            JPEG_XMP_HEADER,
+            xmp.as_bytes(),
+            &image[8..],
+        ]
+        .concat();
+        assert_eq!(embedded, expected);
+        assert_eq!(extract_thumb_hash(&embedded), Ok(Some(HASH.to_vec())));
+        assert_eq!(extract_thumb_hash(&image), Ok(None));
+
+        // Embedding again replaces the hash instead of adding another one
+        let again = embed_thumb_hash(&embedded, &HASH[..5]).unwrap();
+        assert_eq!(again.len(), embedded.len() - 4);
+        assert_eq!(extract_thumb_hash(&again), Ok(Some(HASH[..5].to_vec())));
+    }
+
+    #[test]
+    fn embeds_in_png() {
+        let image = png();
+        let embedded = embed_thumb_hash(&image, HASH).unwrap();
+        assert_eq!(&embedded[..33], &image[..33]);
+        assert_eq!(&embedded[33 + 4..33 + 8], b"iTXt");
+        assert_eq!(&embedded[embedded.len() - 12..], &image[33..]);
+        assert_eq!(extract_thumb_hash(&embedded), Ok(Some(HASH.to_vec())));
+        let again = embed_thumb_hash(&embedded, &HASH[..5]).unwrap();
+        assert_eq!(extract_thumb_hash(&again), Ok(Some(HASH[..5].to_vec())));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(embed_thumb_hash(b"GIF89a", HASH), Err(()));
+        assert_eq!(extract_thumb_hash(b"GIF89a"), Err(()));
+        assert_eq!(extract_thumb_hash(&jpeg()[..7]), Err(()));
+    }
+
+    #[test]
+    fn reads_any_prefix_and_form() {
+        let attribute = packet(&format!(
+            "<rdf:Description rdf:about=''\n  xmlns:th='{XMP_NAMESPACE}'\n  th:ThumbHash='GwgGDYBXeIc='/>"
+        ));
+        let element = packet(&format!(
+            "<rdf:Description rdf:about=\"\" xmlns:th=\"{XMP_NAMESPACE}\">\
+            <th:ThumbHash> GwgGDYBXeIc= </th:ThumbHash>\
+            </rdf:Description>"
+        ));
+        for xmp in [&attribute, &element] {
+            assert_eq!(read_xmp(xmp), Ok(Some(HASH.to_vec())));
+        }
+        assert_eq!(read_xmp(&packet("<rdf:Description/>")), Ok(None));
+    }
+
+    #[test]
+    fn update_replaces_every_form() {
+        let xmp = packet(&format!(
+            "<rdf:Description rdf:about=\"\"\n  xmlns:th=\"{XMP_NAMESPACE}\"\n  th:ThumbHash=\"AAAAAAA=\">\
+            <th:ThumbHash>AAAAAAA=</th:ThumbHash>\
+            </rdf:Description>\
+            <rdf:Description rdf:about=\"\" xmlns:old='{XMP_NAMESPACE}'>\
+            <old:ThumbHash>AAAAAAA=</old:ThumbHash>\
+            </rdf:Description>"
+        ));
+        let updated = update_xmp(&xmp, "GwgGDYBXeIc=").unwrap();
+        assert_eq!(
+            updated,
+            packet(&format!(
+                "<rdf:Description xmlns:thumbhash=\"{XMP_NAMESPACE}\" thumbhash:ThumbHash=\"GwgGDYBXeIc=\" rdf:about=\"\">\
+                </rdf:Description>\
+                <rdf:Description rdf:about=\"\">\
+                </rdf:Description>"
+            ))
+        );
+        assert_eq!(read_xmp(&updated), Ok(Some(HASH.to_vec())));
+        assert_eq!(update_xmp(&updated, "GwgGDYBXeIc="), Ok(updated.clone()));
+    }
+}
@@ -0,0 +1,71 @@
+use napi::bindgen_prelude::Buffer;
+use napi::{Error, Result};
+use napi_derive::napi;
+
+// A Node.js addon for using this crate from Node, which can be built with
+// "napi build". These match the API of the JavaScript implementation but take
+// and return a `Buffer`, so they can be used as a drop-in replacement for it.
+// Only available with the `node` feature.
+
+/// The result of decoding a ThumbHash to an RGBA image.
+#[napi(object)]
+pub struct DecodedImage {
+    /// The width of the image.
+    pub w: u32,
+    /// The height of the image.
+    pub h: u32,
+    /// The pixels in the image, row-by-row.
+    pub rgba: Buffer,
+}
+
+/// An RGBA color with values from 0 to 1.
+#[napi(object)]
+pub struct AverageRgba {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    pub a: f64,
+}
+
+/// Encodes an RGBA image to a ThumbHash. RGB should not be premultiplied by A.
+#[napi(js_name = "rgbaToThumbHash")]
+pub fn rgba_to_thumb_hash(w: u32, h: u32, rgba: Buffer) -> Result<Buffer> {
+    let (w, h) = (w as usize, h as usize);
+    crate::check_rgba(w, h, &rgba).map_err(Error::from_reason)?;
+    Ok(crate::rgba_to_thumb_hash(w, h, &rgba).into())
+}
+
+/// Decodes a ThumbHash to an RGBA image. RGB is not premultiplied by A.
+#[napi(js_name = "thumbHashToRGBA")]
+pub fn thumb_hash_to_rgba(hash: Buffer) -> Result<DecodedImage> {
+    let (w, h, rgba) = crate::thumb_hash_to_rgba(&hash).map_err(|_| invalid_hash())?;
+    Ok(DecodedImage {
+        w: w as u32,
+        h: h as u32,
+        rgba: rgba.into(),
+    })
+}
+
+/// Extracts the average color from a ThumbHash. RGB is not premultiplied by A.
+#[napi(js_name = "thumbHashToAverageRGBA")]
+pub fn thumb_hash_to_average_rgba(hash: Buffer) -> Result<AverageRgba> {
+    let (r, g, b, a) = crate::thumb_hash_to_average_rgba(&hash).map_err(|_| invalid_hash())?;
+    Ok(AverageRgba {
+        r: r as f64,
+        g: g as f64,
+        b: b as f64,
+        a: a as f64,
+    })
+}
+
+/// Extracts the approximate aspect ratio of the original image.
+#[napi(js_name = "thumbHashToApproximateAspectRatio")]
+pub fn thumb_hash_to_approximate_aspect_ratio(hash: Buffer) -> Result<f64> {
+    crate::thumb_hash_to_approximate_aspect_ratio(&hash)
+        .map(|ratio| ratio as f64)
+        .map_err(|_| invalid_hash())
+}
+
+fn invalid_hash() -> Error {
+    Error::from_reason("Invalid ThumbHash")
+}
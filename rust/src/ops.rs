@@ -0,0 +1,546 @@
+use crate::{rgb_to_lpq, Basis, Channels};
+use std::f32::consts::PI;
+
+/// Scales the luminance of a ThumbHash, returning a new ThumbHash.
+///
+/// A `factor` of 1 leaves the hash unchanged, values below 1 darken it, and
+/// values above 1 brighten it. An error will be returned if the input is too
+/// short.
+pub fn adjust_brightness(hash: &[u8], factor: f32) -> Result<Vec<u8>, ()> {
+    let factor = factor.max(0.0);
+    let mut channels = Channels::read(hash)?;
+    channels.l_dc *= factor;
+    channels.l_scale *= factor;
+    Ok(channels.write())
+}
+
+/// Scales the saturation of a ThumbHash, returning a new ThumbHash.
+///
+/// A `factor` of 0 makes the hash grayscale, 1 leaves it unchanged, and values
+/// above 1 make it more colorful. An error will be returned if the input is too
+/// short.
+pub fn adjust_saturation(hash: &[u8], factor: f32) -> Result<Vec<u8>, ()> {
+    let factor = factor.max(0.0);
+    let mut channels = Channels::read(hash)?;
+    channels.p_dc *= factor;
+    channels.q_dc *= factor;
+    channels.p_scale *= factor;
+    channels.q_scale *= factor;
+    Ok(channels.write())
+}
+
+/// Rotates the hue of a ThumbHash, returning a new ThumbHash.
+///
+/// The rotation happens in the chroma plane used by ThumbHash (yellow-blue
+/// and red-green), so luminance is preserved. The angle is in radians. An
+/// error will be returned if the input is too short.
+pub fn hue_rotate(hash: &[u8], radians: f32) -> Result<Vec<u8>, ()> {
+    let mut channels = Channels::read(hash)?;
+    let (sin, cos) = radians.sin_cos();
+    let rotate = |p: f32, q: f32| (p * cos - q * sin, p * sin + q * cos);
+    (channels.p_dc, channels.q_dc) = rotate(channels.p_dc, channels.q_dc);
+    let p_ac = Channels::ac_values(&channels.p_ac, channels.p_scale);
+    let q_ac = Channels::ac_values(&channels.q_ac, channels.q_scale);
+    let (p_ac, q_ac): (Vec<f32>, Vec<f32>) = p_ac
+        .into_iter()
+        .zip(q_ac)
+        .map(|(p, q)| rotate(p, q))
+        .unzip();
+    channels.p_scale = Channels::set_ac_values(&mut channels.p_ac, &p_ac);
+    channels.q_scale = Channels::set_ac_values(&mut channels.q_ac, &q_ac);
+    Ok(channels.write())
+}
+
+/// Tints a ThumbHash toward a color, returning a new ThumbHash.
+///
+/// The color is given as RGB values from 0 to 1. An `amount` of 0 leaves the
+/// hash unchanged and 1 replaces the chroma of the hash entirely with the
+/// chroma of the color, keeping only the luminance of the original. An error
+/// will be returned if the input is too short.
+pub fn tint(hash: &[u8], (r, g, b): (f32, f32, f32), amount: f32) -> Result<Vec<u8>, ()> {
+    let amount = amount.clamp(0.0, 1.0);
+    let mut channels = Channels::read(hash)?;
+    let (_, p, q) = rgb_to_lpq(r, g, b);
+    channels.p_dc += (p - channels.p_dc) * amount;
+    channels.q_dc += (q - channels.q_dc) * amount;
+    channels.p_scale *= 1.0 - amount;
+    channels.q_scale *= 1.0 - amount;
+    Ok(channels.write())
+}
+
+/// Derives a placeholder suitable for a dark theme from a ThumbHash, returning
+/// a new ThumbHash.
+///
+/// This dims the luminance and slightly desaturates the colors so the
+/// placeholder doesn't glare against a dark background. An error will be
+/// returned if the input is too short.
+pub fn to_dark_mode(hash: &[u8]) -> Result<Vec<u8>, ()> {
+    let mut channels = Channels::read(hash)?;
+    channels.l_dc *= 0.7;
+    channels.l_scale *= 0.7;
+    channels.p_dc *= 0.85;
+    channels.q_dc *= 0.85;
+    channels.p_scale *= 0.85;
+    channels.q_scale *= 0.85;
+    Ok(channels.write())
+}
+
+/// Interpolates between two ThumbHashes, returning a new ThumbHash.
+///
+/// The interpolation happens on the DCT coefficients, so decoding the result
+/// gives a smooth crossfade between the two placeholders. A `t` of 0 returns
+/// `hash_a` and 1 returns `hash_b`. If the hashes have different aspect ratios,
+/// the result uses the layout of whichever hash `t` is closer to, and it keeps
+/// the extensions (such as the exact aspect ratio and the checksum) of that
+/// hash too. An error will be returned if either input is too short.
+pub fn lerp(hash_a: &[u8], hash_b: &[u8], t: f32) -> Result<Vec<u8>, ()> {
+    let a = Channels::read(hash_a)?;
+    let b = Channels::read(hash_b)?;
+    let has_alpha = (a.has_alpha && t < 1.0) || (b.has_alpha && t > 0.0);
+    let closest = if t < 0.5 { &a } else { &b };
+    let (layout, a_n) = if closest.has_alpha == has_alpha {
+        ((closest.lx, closest.ly, closest.is_landscape), closest.a_n)
+    } else {
+        let (l_limit, _, a_n) = closest.format.sizes(has_alpha);
+        let layout = Channels::layout(closest.aspect_ratio(), l_limit);
+        (layout, a_n)
+    };
+    let sizes = (closest.pq_n, a_n);
+    let mut channels = Channels::from_terms(layout, sizes, closest.format, |channel, cx, cy| {
+        let a = a.term(channel, cx, cy);
+        a + (b.term(channel, cx, cy) - a) * t
+    });
+    channels.extensions = closest.extensions.clone();
+    Ok(channels.write())
+}
+
+/// Removes the alpha channel from a ThumbHash, returning a new ThumbHash that
+/// uses the more compact opaque layout.
+///
+/// This is for images that were flattened after they were hashed. Transparent
+/// areas turn into the colors the encoder composited them onto, which is the
+/// average color of the image. Opaque hashes have more room for luminance, so
+/// the luminance terms are laid out again for the opaque budget with the
+/// extra terms set to 0. An error will be returned if the input is too short.
+pub fn strip_alpha(hash: &[u8]) -> Result<Vec<u8>, ()> {
+    let channels = Channels::read(hash)?;
+    if !channels.has_alpha {
+        return Ok(channels.write());
+    }
+    let (l_limit, _, _) = channels.format.sizes(false);
+    let layout = Channels::layout(channels.aspect_ratio(), l_limit);
+    let sizes = (channels.pq_n, 0);
+    let mut opaque = Channels::from_terms(layout, sizes, channels.format, |channel, cx, cy| {
+        channels.term(channel, cx, cy)
+    });
+    opaque.extensions = channels.extensions.clone();
+    Ok(opaque.write())
+}
+
+/// Composites a ThumbHash over a background color, returning a new opaque
+/// ThumbHash. This is for adapting stored hashes to a page whose background
+/// is known.
+///
+/// The color is given as RGB values from 0 to 1. Compositing multiplies the
+/// color channels by the alpha channel, which is done directly on the
+/// coefficients of the hash instead of on decoded pixels. The result is laid
+/// out like [`strip_alpha`] does. An error will be returned if the input is too
+/// short.
+pub fn flatten_onto(hash: &[u8], (r, g, b): (f32, f32, f32)) -> Result<Vec<u8>, ()> {
+    let channels = Channels::read(hash)?;
+    if !channels.has_alpha {
+        return Ok(channels.write());
+    }
+
+    // Expand each channel into a grid of the values that multiply each pair of
+    // cosines when decoding, which includes the saturation boost for P and Q
+    let n = channels
+        .l_nx
+        .max(channels.l_ny)
+        .max(channels.pq_n)
+        .max(channels.a_n);
+    let background = rgb_to_lpq(r, g, b);
+    let background = [background.0, background.1, background.2];
+    let grid = |channel: usize| -> Vec<f32> {
+        let boost = if channel == 1 || channel == 2 {
+            1.25
+        } else {
+            1.0
+        };
+        let mut grid = vec![0.0; n * n];
+        for cy in 0..n {
+            for cx in 0..n {
+                grid[cx + cy * n] = match (cx, cy) {
+                    (0, 0) => channels.term(channel, 0, 0),
+                    _ => channels.term(channel, cx, cy) * 2.0 * boost,
+                };
+            }
+        }
+        grid
+    };
+    let alpha = grid(3);
+    let colors: Vec<Vec<f32>> = (0..3)
+        .map(|channel| {
+            let mut grid = grid(channel);
+            grid[0] -= background[channel];
+            grid
+        })
+        .collect();
+
+    // The result is "background + (color - background) * alpha". Multiplying
+    // cos(i*t) by cos(j*t) gives half of cos((i+j)*t) plus half of
+    // cos(|i-j|*t), so the product is also a sum of cosines.
+    let weight = |i: usize, j: usize, m: usize| {
+        (if i + j == m { 0.5 } else { 0.0 }) + if i.abs_diff(j) == m { 0.5 } else { 0.0 }
+    };
+    let term = |channel: usize, mx: usize, my: usize| -> f32 {
+        let color = &colors[channel];
+        let mut sum = 0.0;
+        for (i, &c) in color.iter().enumerate() {
+            for (j, &a) in alpha.iter().enumerate() {
+                let (wx, wy) = (weight(i % n, j % n, mx), weight(i / n, j / n, my));
+                sum += c * a * wx * wy;
+            }
+        }
+        let boost = if channel == 1 || channel == 2 {
+            1.25
+        } else {
+            1.0
+        };
+        match (mx, my) {
+            (0, 0) => background[channel] + sum,
+            _ => sum / (2.0 * boost),
+        }
+    };
+
+    let (l_limit, _, _) = channels.format.sizes(false);
+    let layout = Channels::layout(channels.aspect_ratio(), l_limit);
+    let sizes = (channels.pq_n, 0);
+    let mut opaque = Channels::from_terms(layout, sizes, channels.format, term);
+    opaque.extensions = channels.extensions.clone();
+    Ok(opaque.write())
+}
+
+/// Stretches a ThumbHash to a new aspect ratio (width divided by height),
+/// returning a new ThumbHash.
+///
+/// This is for layouts that show every image at the same ratio, so that the
+/// placeholder is already the shape of its slot and nothing jumps when the
+/// real image loads. The terms of a hash don't depend on the size of the
+/// image, so they're kept as-is and only the layout is changed to the one the
+/// encoder would pick for the new ratio. Terms that the new layout has no room
+/// for are dropped. The stored ratio is approximate, unless the hash stores
+/// the exact ratio (see [`thumb_hash_with_aspect_ratio`](crate::thumb_hash_with_aspect_ratio))
+/// in which case it's replaced. An error will be returned if the input is too
+/// short or the ratio isn't a positive finite number.
+pub fn retarget_aspect_ratio(hash: &[u8], ratio: f32) -> Result<Vec<u8>, ()> {
+    if !(ratio > 0.0 && ratio.is_finite()) {
+        return Err(());
+    }
+    let channels = Channels::read(hash)?;
+    let layout = Channels::layout(ratio, channels.term_counts().0);
+    let mut stretched = Channels::from_terms(
+        layout,
+        (channels.pq_n, channels.a_n),
+        channels.format,
+        |channel, cx, cy| channels.term(channel, cx, cy),
+    );
+    stretched.extensions = channels.extensions.clone();
+    if stretched.extensions.aspect_ratio.is_some() {
+        stretched.extensions.set_aspect_ratio(ratio);
+    }
+    Ok(stretched.write())
+}
+
+/// Crops the middle of a ThumbHash to a new aspect ratio (width divided by
+/// height), returning a new ThumbHash. This is for grids that crop images to
+/// fill square (or other fixed-ratio) cells, so the placeholder can be derived
+/// from the hash of the uncropped image.
+///
+/// The placeholder is decoded, cropped, and then encoded again, so the result
+/// is only as detailed as the part of the original that remains. If the hash
+/// stores the exact ratio (see [`thumb_hash_with_aspect_ratio`](crate::thumb_hash_with_aspect_ratio)),
+/// it's replaced. An error will be returned if the input is too short or the
+/// ratio isn't a positive finite number.
+pub fn crop_to_aspect(hash: &[u8], ratio: f32) -> Result<Vec<u8>, ()> {
+    if !(ratio > 0.0 && ratio.is_finite()) {
+        return Err(());
+    }
+    let channels = Channels::read(hash)?;
+    let (w, h) = channels.size(100);
+    let (crop_w, crop_h) = if ratio < w as f32 / h as f32 {
+        (((h as f32 * ratio).round() as usize).clamp(1, w), h)
+    } else {
+        (w, ((w as f32 / ratio).round() as usize).clamp(1, h))
+    };
+
+    // Evaluate the placeholder at each pixel of the crop, undoing the boost
+    // to saturation that decoding applies so it doesn't build up
+    let (x0, y0) = ((w - crop_w) / 2, (h - crop_h) / 2);
+    let basis = Basis::new(&channels);
+    let mut planes = [(); 4].map(|()| Vec::with_capacity(crop_w * crop_h));
+    for y in y0..y0 + crop_h {
+        let ty = PI / h as f32 * (y as f32 + 0.5);
+        for x in x0..x0 + crop_w {
+            let tx = PI / w as f32 * (x as f32 + 0.5);
+            let (l, p, q, a) = basis.lpqa(tx, ty);
+            for (plane, value) in planes.iter_mut().zip([l, p / 1.25, q / 1.25, a]) {
+                plane.push(value);
+            }
+        }
+    }
+
+    let [l, p, q, a] = &planes;
+    let mut cropped = Channels::from_lpqa(
+        crop_w,
+        crop_h,
+        [l, p, q, a],
+        channels.has_alpha,
+        channels.format,
+        channels.term_counts(),
+    );
+    cropped.extensions = channels.extensions.clone();
+    if cropped.extensions.aspect_ratio.is_some() {
+        cropped.extensions.set_aspect_ratio(ratio);
+    }
+    Ok(cropped.write())
+}
+
+/// Reads a ThumbHash and writes it again, returning the bytes that this crate
+/// would have produced for the same placeholder. This is for deduplicating
+/// hashes from other encoders, which can round differently or leave unused
+/// bits set.
+///
+/// The terms are quantized again from their decoded values using the same
+/// layout and format, so a hash from this crate usually comes back unchanged.
+/// Any extensions in the hash are kept. An error will be returned if the input
+/// is too short.
+pub fn canonicalize(hash: &[u8]) -> Result<Vec<u8>, ()> {
+    let channels = Channels::read(hash)?;
+    let layout = (channels.lx, channels.ly, channels.is_landscape);
+    let mut canonical = Channels::from_terms(
+        layout,
+        (channels.pq_n, channels.a_n),
+        channels.format,
+        |channel, cx, cy| channels.term(channel, cx, cy),
+    );
+    canonical.extensions = channels.extensions.clone();
+    Ok(canonical.write())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{sample, sample_with_alpha};
+    use crate::{
+        has_alpha, has_checksum, rgba_to_thumb_hash, thumb_hash_to_approximate_aspect_ratio,
+        thumb_hash_to_average_rgba, thumb_hash_to_exact_aspect_ratio, thumb_hash_to_rgba,
+        thumb_hash_with_aspect_ratio, thumb_hash_with_checksum,
+    };
+
+    fn luminance(hash: &[u8]) -> f32 {
+        Channels::read(hash).unwrap().l_dc
+    }
+
+    #[test]
+    fn adjust_brightness_scales_luminance() {
+        let hash = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        assert_eq!(adjust_brightness(&hash, 1.0), Ok(hash.clone()));
+        let darker = adjust_brightness(&hash, 0.5).unwrap();
+        let brighter = adjust_brightness(&hash, 1.5).unwrap();
+        assert!((luminance(&darker) - luminance(&hash) * 0.5).abs() < 0.02);
+        assert!(luminance(&brighter) > luminance(&hash));
+        assert_eq!(luminance(&adjust_brightness(&hash, -1.0).unwrap()), 0.0);
+        assert_eq!(adjust_brightness(&[], 1.0), Err(()));
+    }
+
+    #[test]
+    fn adjust_saturation_scales_chroma() {
+        let hash = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        assert_eq!(adjust_saturation(&hash, 1.0), Ok(hash.clone()));
+        let (r, g, b, _) =
+            thumb_hash_to_average_rgba(&adjust_saturation(&hash, 0.0).unwrap()).unwrap();
+        assert!((r - g).abs() < 0.02 && (g - b).abs() < 0.02);
+        let (r, g, _, _) = thumb_hash_to_average_rgba(&hash).unwrap();
+        let (r2, g2, _, _) =
+            thumb_hash_to_average_rgba(&adjust_saturation(&hash, 1.5).unwrap()).unwrap();
+        assert!(r2 - g2 > r - g);
+        assert_eq!(adjust_saturation(&[], 1.0), Err(()));
+    }
+
+    #[test]
+    fn hue_rotate_keeps_luminance() {
+        let hash = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        let c = Channels::read(&hash).unwrap();
+        let rotated = Channels::read(&hue_rotate(&hash, PI).unwrap()).unwrap();
+        assert_eq!(rotated.l_dc, c.l_dc);
+        assert_eq!(rotated.l_ac, c.l_ac);
+        assert!((rotated.p_dc + c.p_dc).abs() < 0.05 && (rotated.q_dc + c.q_dc).abs() < 0.05);
+        let full_turn = Channels::read(&hue_rotate(&hash, 2.0 * PI).unwrap()).unwrap();
+        assert!((full_turn.p_dc - c.p_dc).abs() < 0.05 && (full_turn.q_dc - c.q_dc).abs() < 0.05);
+        assert_eq!(hue_rotate(&[], 1.0), Err(()));
+    }
+
+    #[test]
+    fn tint_moves_toward_the_color() {
+        let hash = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        assert_eq!(tint(&hash, (0.0, 0.0, 1.0), 0.0), Ok(hash.clone()));
+        let tinted = Channels::read(&tint(&hash, (0.0, 0.0, 1.0), 1.0).unwrap()).unwrap();
+        let (_, p, q) = rgb_to_lpq(0.0, 0.0, 1.0);
+        assert!((tinted.p_dc - p).abs() < 0.05 && (tinted.q_dc - q).abs() < 0.05);
+        assert_eq!((tinted.p_scale, tinted.q_scale), (0.0, 0.0));
+        assert_eq!(tinted.l_dc, Channels::read(&hash).unwrap().l_dc);
+        assert_eq!(tint(&[], (0.0, 0.0, 1.0), 0.5), Err(()));
+    }
+
+    #[test]
+    fn to_dark_mode_dims_and_desaturates() {
+        let hash = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        let c = Channels::read(&hash).unwrap();
+        let dark = Channels::read(&to_dark_mode(&hash).unwrap()).unwrap();
+        assert!((dark.l_dc - c.l_dc * 0.7).abs() < 0.02);
+        assert!(dark.p_dc.abs() < c.p_dc.abs() && dark.q_dc.abs() < c.q_dc.abs());
+        assert_eq!((dark.lx, dark.ly), (c.lx, c.ly));
+        assert_eq!(to_dark_mode(&[]), Err(()));
+    }
+
+    #[test]
+    fn lerp_returns_the_ends() {
+        let a = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        let b = rgba_to_thumb_hash(
+            24,
+            32,
+            &sample(24, 32).iter().map(|c| 255 - c).collect::<Vec<_>>(),
+        );
+        assert_eq!(lerp(&a, &b, 0.0), Ok(a.clone()));
+        assert_eq!(lerp(&a, &b, 1.0), Ok(b.clone()));
+        let middle = Channels::read(&lerp(&a, &b, 0.5).unwrap()).unwrap();
+        let (ca, cb) = (Channels::read(&a).unwrap(), Channels::read(&b).unwrap());
+        assert!((middle.l_dc - (ca.l_dc + cb.l_dc) / 2.0).abs() < 0.02);
+        assert_eq!((middle.lx, middle.ly), (cb.lx, cb.ly));
+        assert_eq!(lerp(&a, &[], 0.5), Err(()));
+    }
+
+    #[test]
+    fn lerp_keeps_the_extensions_of_the_closer_hash() {
+        let a = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        let a =
+            thumb_hash_with_checksum(&thumb_hash_with_aspect_ratio(&a, 32, 24).unwrap()).unwrap();
+        let b = rgba_to_thumb_hash(24, 32, &sample(24, 32));
+        let near_a = lerp(&a, &b, 0.25).unwrap();
+        assert_eq!(has_checksum(&near_a), Ok(true));
+        assert_eq!(
+            thumb_hash_to_exact_aspect_ratio(&near_a),
+            thumb_hash_to_exact_aspect_ratio(&a)
+        );
+        let near_b = lerp(&a, &b, 0.75).unwrap();
+        assert_eq!(has_checksum(&near_b), Ok(false));
+        assert_eq!(thumb_hash_to_exact_aspect_ratio(&near_b), Ok(None));
+    }
+
+    #[test]
+    fn strip_alpha_keeps_the_average_color() {
+        let hash = rgba_to_thumb_hash(32, 24, &sample_with_alpha(32, 24));
+        let opaque = strip_alpha(&hash).unwrap();
+        assert_eq!(has_alpha(&opaque), Ok(false));
+        let (r, g, b, _) = thumb_hash_to_average_rgba(&hash).unwrap();
+        let (r2, g2, b2, a2) = thumb_hash_to_average_rgba(&opaque).unwrap();
+        assert!((r - r2).abs() < 0.02 && (g - g2).abs() < 0.02 && (b - b2).abs() < 0.02);
+        assert_eq!(a2, 1.0);
+        assert_eq!(strip_alpha(&opaque), Ok(opaque.clone()));
+        assert_eq!(strip_alpha(&[]), Err(()));
+    }
+
+    #[test]
+    fn flatten_onto_matches_compositing_the_decoded_image() {
+        let mut uniform = sample(32, 24);
+        for pixel in uniform.chunks_exact_mut(4) {
+            pixel[3] = 128;
+        }
+        let background = [20, 40, 230];
+        let color = background.map(|c| c as f32 / 255.0);
+
+        // With uniform alpha the only error is from quantizing the result, but
+        // otherwise the product has more terms than the opaque layout can hold
+        for (rgba, max_error, mean_error) in
+            [(uniform, 12, 3.0), (sample_with_alpha(32, 24), 32, 6.0)]
+        {
+            let hash = rgba_to_thumb_hash(32, 24, &rgba);
+            let (w, h, mut expected) = thumb_hash_to_rgba(&hash).unwrap();
+            for pixel in expected.chunks_exact_mut(4) {
+                let a = pixel[3] as f32 / 255.0;
+                for (c, bg) in pixel.iter_mut().zip(background) {
+                    *c = (bg as f32 + (*c as f32 - bg as f32) * a).round() as u8;
+                }
+                pixel[3] = 255;
+            }
+            let flattened = flatten_onto(&hash, (color[0], color[1], color[2])).unwrap();
+            assert_eq!(has_alpha(&flattened), Ok(false));
+
+            // The opaque layout can round the stored ratio differently
+            let rgba = Channels::read(&flattened).unwrap().render(w, h);
+            let diffs: Vec<u8> = rgba
+                .iter()
+                .zip(&expected)
+                .map(|(a, b)| a.abs_diff(*b))
+                .collect();
+            let mean = diffs.iter().map(|&d| d as f32).sum::<f32>() / diffs.len() as f32;
+            assert!(*diffs.iter().max().unwrap() <= max_error && mean <= mean_error);
+        }
+
+        let opaque = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        assert_eq!(flatten_onto(&opaque, (0.0, 0.0, 0.0)), Ok(opaque.clone()));
+        assert_eq!(flatten_onto(&[], (0.0, 0.0, 0.0)), Err(()));
+    }
+
+    #[test]
+    fn retarget_aspect_ratio_changes_the_layout() {
+        let hash = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        for (w, h) in [(40, 20), (20, 40), (30, 30)] {
+            let retargeted = retarget_aspect_ratio(&hash, w as f32 / h as f32).unwrap();
+            let encoded = rgba_to_thumb_hash(w, h, &sample(w, h));
+            assert_eq!(
+                thumb_hash_to_approximate_aspect_ratio(&retargeted),
+                thumb_hash_to_approximate_aspect_ratio(&encoded),
+            );
+        }
+        for ratio in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert_eq!(retarget_aspect_ratio(&hash, ratio), Err(()));
+        }
+        assert_eq!(retarget_aspect_ratio(&[], 1.0), Err(()));
+    }
+
+    #[test]
+    fn crop_to_aspect_changes_the_layout() {
+        let hash = rgba_to_thumb_hash(32, 24, &sample(32, 24));
+        for (w, h) in [(40, 20), (20, 40), (30, 30)] {
+            let cropped = crop_to_aspect(&hash, w as f32 / h as f32).unwrap();
+            let encoded = rgba_to_thumb_hash(w, h, &sample(w, h));
+            assert_eq!(
+                thumb_hash_to_approximate_aspect_ratio(&cropped),
+                thumb_hash_to_approximate_aspect_ratio(&encoded),
+            );
+        }
+        for ratio in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert_eq!(crop_to_aspect(&hash, ratio), Err(()));
+        }
+        assert_eq!(crop_to_aspect(&[], 1.0), Err(()));
+    }
+
+    #[test]
+    fn canonicalize_is_idempotent() {
+        for hash in [
+            rgba_to_thumb_hash(32, 24, &sample(32, 24)),
+            rgba_to_thumb_hash(24, 32, &sample_with_alpha(24, 32)),
+        ] {
+            assert_eq!(canonicalize(&hash), Ok(hash.clone()));
+            let mut scrambled = hash.clone();
+            for (i, byte) in scrambled[5..].iter_mut().enumerate() {
+                *byte = (i * 37) as u8;
+            }
+            let canonical = canonicalize(&scrambled).unwrap();
+            assert_eq!(canonicalize(&canonical), Ok(canonical.clone()));
+        }
+        assert_eq!(canonicalize(&[]), Err(()));
+    }
+}
@@ -0,0 +1,42 @@
+use crate::{lpq_to_rgb, Basis, Channels};
+use image::{ImageBuffer, Pixel, Primitive};
+use num_traits::NumCast;
+use std::f32::consts::PI;
+
+/// Decodes a ThumbHash to an image buffer from the `image` crate, with any of
+/// its pixel types (e.g. `Rgba<u8>`, `Rgb<u8>`, `LumaA<u8>`, or `Rgba<u16>`).
+/// Only available with the `image` feature.
+///
+/// Each pixel is converted as it's decoded, so there's no intermediate RGBA
+/// buffer. Alpha is dropped for pixel types without it and luma uses the same
+/// Rec. 709 weights as `image`. The size is the same as for
+/// [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba), and `Rgba<u8>` gives the
+/// same pixels. An error will be returned if the input is too short.
+pub fn thumb_hash_to_image<P: Pixel>(hash: &[u8]) -> Result<ImageBuffer<P, Vec<P::Subpixel>>, ()> {
+    let channels = Channels::read(hash)?;
+    let (w, h) = channels.size(32);
+    let basis = Basis::new(&channels);
+    let max = P::Subpixel::DEFAULT_MAX_VALUE;
+    let scale: f32 = NumCast::from(max).unwrap();
+    let convert = |x: f32| NumCast::from(x * scale).unwrap_or(max);
+    Ok(ImageBuffer::from_fn(w as u32, h as u32, |x, y| {
+        let tx = PI / w as f32 * (x as f32 + 0.5);
+        let ty = PI / h as f32 * (y as f32 + 0.5);
+        let (l, p, q, a) = basis.lpqa(tx, ty);
+        let (r, g, b) = lpq_to_rgb(l, p, q);
+        let (r, g, b, a) = (
+            r.clamp(0.0, 1.0),
+            g.clamp(0.0, 1.0),
+            b.clamp(0.0, 1.0),
+            a.clamp(0.0, 1.0),
+        );
+        let luma = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+        let values = match P::CHANNEL_COUNT {
+            1 => [luma, 0.0, 0.0, 0.0],
+            2 => [luma, a, 0.0, 0.0],
+            3 => [r, g, b, 0.0],
+            _ => [r, g, b, a],
+        };
+        *P::from_slice(&values.map(convert)[..P::CHANNEL_COUNT as usize])
+    }))
+}
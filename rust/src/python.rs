@@ -0,0 +1,66 @@
+use pyo3::buffer::PyBuffer;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+// A Python module for using this crate from Python, which can be built with
+// "maturin build". The functions accept `bytes` or anything else with the
+// buffer protocol, such as numpy arrays. Only available with the `python`
+// feature.
+
+/// Encodes an RGBA image to a ThumbHash. RGB should not be premultiplied by A.
+/// The pixels can be any buffer of `w*h*4` bytes, such as a numpy array with
+/// the shape `(h, w, 4)` and the type `uint8`.
+#[pyfunction]
+fn rgba_to_thumb_hash<'py>(
+    py: Python<'py>,
+    w: usize,
+    h: usize,
+    rgba: PyBuffer<u8>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let rgba = rgba.to_vec(py)?;
+    crate::check_rgba(w, h, &rgba).map_err(PyValueError::new_err)?;
+    let hash = py.detach(|| crate::rgba_to_thumb_hash(w, h, &rgba));
+    Ok(PyBytes::new(py, &hash))
+}
+
+/// Decodes a ThumbHash to an RGBA image. RGB is not premultiplied by A.
+/// Returns the width, height, and pixels of the rendered placeholder image.
+#[pyfunction]
+fn thumb_hash_to_rgba<'py>(
+    py: Python<'py>,
+    hash: &[u8],
+) -> PyResult<(usize, usize, Bound<'py, PyBytes>)> {
+    let (w, h, rgba) = crate::thumb_hash_to_rgba(hash).map_err(|_| invalid_hash())?;
+    Ok((w, h, PyBytes::new(py, &rgba)))
+}
+
+/// Extracts the average color from a ThumbHash as RGBA values from 0 to 1.
+/// RGB is not premultiplied by A.
+#[pyfunction]
+fn thumb_hash_to_average_rgba(hash: &[u8]) -> PyResult<(f32, f32, f32, f32)> {
+    crate::thumb_hash_to_average_rgba(hash).map_err(|_| invalid_hash())
+}
+
+/// Extracts the approximate aspect ratio (width / height) of the original
+/// image.
+#[pyfunction]
+fn thumb_hash_to_approximate_aspect_ratio(hash: &[u8]) -> PyResult<f32> {
+    crate::thumb_hash_to_approximate_aspect_ratio(hash).map_err(|_| invalid_hash())
+}
+
+fn invalid_hash() -> PyErr {
+    PyValueError::new_err("Invalid ThumbHash")
+}
+
+#[pymodule]
+fn thumbhash(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(rgba_to_thumb_hash, module)?)?;
+    module.add_function(wrap_pyfunction!(thumb_hash_to_rgba, module)?)?;
+    module.add_function(wrap_pyfunction!(thumb_hash_to_average_rgba, module)?)?;
+    module.add_function(wrap_pyfunction!(
+        thumb_hash_to_approximate_aspect_ratio,
+        module
+    )?)?;
+    Ok(())
+}
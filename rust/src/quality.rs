@@ -0,0 +1,178 @@
+use crate::Channels;
+
+/// How closely a ThumbHash matches the image it was made from, returned by
+/// [`thumb_hash_quality`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ThumbHashQuality {
+    /// The peak signal-to-noise ratio in decibels, which is infinite if the
+    /// images are identical. Placeholders of photos are typically around 20.
+    pub psnr: f32,
+
+    /// The structural similarity of the luminance, from -1 to 1 (identical).
+    pub ssim: f32,
+}
+
+/// The side of the square window that SSIM is computed over.
+const SSIM_WINDOW: usize = 7;
+
+/// Measures how well a ThumbHash represents the image it was made from, which
+/// is useful for flagging images whose placeholders are misleading (such as
+/// screenshots of text).
+///
+/// The original is downscaled to the size that [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba)
+/// decodes to, and both images are premultiplied by alpha before they are
+/// compared. The image takes the same form as it does for
+/// [`rgba_to_thumb_hash`](crate::rgba_to_thumb_hash). An error will be
+/// returned if the hash is too short.
+pub fn thumb_hash_quality(
+    w: usize,
+    h: usize,
+    rgba: &[u8],
+    hash: &[u8],
+) -> Result<ThumbHashQuality, ()> {
+    assert!(w > 0 && h > 0);
+    assert_eq!(rgba.len(), w * h * 4);
+    let channels = Channels::read(hash)?;
+    let (dw, dh) = channels.size(32);
+    let decoded = premultiply(&channels.render(dw, dh));
+    let original = downscale(w, h, rgba, dw, dh);
+
+    // Compare every color channel for PSNR
+    let mse = decoded
+        .iter()
+        .zip(&original)
+        .map(|(a, b)| (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum::<f32>())
+        .sum::<f32>()
+        / (dw * dh * 3) as f32;
+    let psnr = 10.0 * (255.0 * 255.0 / mse).log10();
+
+    // Compare the luminance for SSIM
+    let luma = |rgb: &[f32; 3]| 0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2];
+    let a: Vec<f32> = decoded.iter().map(luma).collect();
+    let b: Vec<f32> = original.iter().map(luma).collect();
+    Ok(ThumbHashQuality {
+        psnr,
+        ssim: ssim(dw, dh, &a, &b),
+    })
+}
+
+/// Returns the premultiplied RGB values of each pixel.
+fn premultiply(rgba: &[u8]) -> Vec<[f32; 3]> {
+    rgba.chunks_exact(4)
+        .map(|p| {
+            let a = p[3] as f32 / 255.0;
+            [p[0] as f32 * a, p[1] as f32 * a, p[2] as f32 * a]
+        })
+        .collect()
+}
+
+/// Downscales an image to `dw` by `dh` by averaging the premultiplied pixels
+/// whose centers fall within each destination pixel.
+fn downscale(w: usize, h: usize, rgba: &[u8], dw: usize, dh: usize) -> Vec<[f32; 3]> {
+    let pixels = premultiply(rgba);
+    let span = |i: usize, from: usize, to: usize| {
+        let start = i * from / to;
+        start..((i + 1) * from / to).max(start + 1)
+    };
+    let mut result = Vec::with_capacity(dw * dh);
+    for y in 0..dh {
+        for x in 0..dw {
+            let mut sum = [0.0; 3];
+            let mut count = 0.0;
+            for sy in span(y, h, dh) {
+                for sx in span(x, w, dw) {
+                    let pixel = pixels[sx + sy * w];
+                    for i in 0..3 {
+                        sum[i] += pixel[i];
+                    }
+                    count += 1.0;
+                }
+            }
+            result.push(sum.map(|s| s / count));
+        }
+    }
+    result
+}
+
+/// Returns the mean SSIM over every window of two `w` by `h` images. Windows
+/// are shrunk to fit images smaller than the usual window.
+fn ssim(w: usize, h: usize, a: &[f32], b: &[f32]) -> f32 {
+    const C1: f32 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f32 = (0.03 * 255.0) * (0.03 * 255.0);
+    let (ww, wh) = (SSIM_WINDOW.min(w), SSIM_WINDOW.min(h));
+    let n = (ww * wh) as f32;
+    let mut total = 0.0;
+    let mut windows = 0.0;
+    for y0 in 0..=h - wh {
+        for x0 in 0..=w - ww {
+            let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in y0..y0 + wh {
+                for x in x0..x0 + ww {
+                    let (va, vb) = (a[x + y * w], b[x + y * w]);
+                    sa += va;
+                    sb += vb;
+                    saa += va * va;
+                    sbb += vb * vb;
+                    sab += va * vb;
+                }
+            }
+            let (ma, mb) = (sa / n, sb / n);
+            let va = saa / n - ma * ma;
+            let vb = sbb / n - mb * mb;
+            let cov = sab / n - ma * mb;
+            total += (2.0 * ma * mb + C1) * (2.0 * cov + C2)
+                / ((ma * ma + mb * mb + C1) * (va + vb + C2));
+            windows += 1.0;
+        }
+    }
+    total / windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgba_to_thumb_hash;
+
+    fn image(w: usize, h: usize, pixel: impl Fn(usize, usize) -> [u8; 4]) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(w * h * 4);
+        for y in 0..h {
+            for x in 0..w {
+                rgba.extend_from_slice(&pixel(x, y));
+            }
+        }
+        rgba
+    }
+
+    fn quality(w: usize, h: usize, rgba: &[u8]) -> ThumbHashQuality {
+        thumb_hash_quality(w, h, rgba, &rgba_to_thumb_hash(w, h, rgba)).unwrap()
+    }
+
+    #[test]
+    fn flat_images_match_their_placeholder() {
+        let rgba = image(40, 30, |_, _| [100, 150, 200, 255]);
+        let ThumbHashQuality { psnr, ssim } = quality(40, 30, &rgba);
+        assert!(psnr > 35.0);
+        assert!((ssim - 1.0).abs() < 0.001);
+
+        let luma: Vec<f32> = (0..100).map(|i| i as f32).collect();
+        assert_eq!(super::ssim(10, 10, &luma, &luma), 1.0);
+    }
+
+    #[test]
+    fn fine_detail_scores_lower_than_gradients() {
+        let checkerboard = image(40, 30, |x, y| {
+            let c = if (x + y) % 2 == 0 { 0 } else { 255 };
+            [c, c, c, 255]
+        });
+        let gradient = image(40, 30, |x, y| [(x * 6) as u8, (y * 8) as u8, 128, 255]);
+        let (checkerboard, gradient) = (quality(40, 30, &checkerboard), quality(40, 30, &gradient));
+        assert!(checkerboard.psnr < gradient.psnr);
+        assert!(checkerboard.ssim < gradient.ssim);
+    }
+
+    #[test]
+    fn rejects_short_hashes() {
+        let rgba = image(4, 4, |_, _| [0, 0, 0, 255]);
+        assert_eq!(thumb_hash_quality(4, 4, &rgba, &[1, 2, 3]), Err(()));
+    }
+}
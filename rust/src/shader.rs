@@ -0,0 +1,188 @@
+use crate::{Basis, Channels};
+use std::fmt::Write;
+
+/// The language of the code from [`thumb_hash_to_shader`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderLanguage {
+    /// WebGPU Shading Language, for WebGPU and wgpu.
+    Wgsl,
+
+    /// OpenGL Shading Language, for OpenGL and WebGL.
+    Glsl,
+}
+
+/// Generates a shader function that renders a ThumbHash, so a placeholder can
+/// be drawn at any resolution entirely on the GPU without uploading a texture.
+///
+/// The function is called `name` and takes a `vec2` of texture coordinates
+/// that go from 0 at the top-left corner of the image to 1 at the bottom-right
+/// corner. It returns a `vec4` with the same colors as the pixels from
+/// [`thumb_hash_to_rgba`](crate::thumb_hash_to_rgba) as values from 0 to 1
+/// (i.e. RGB is gamma-encoded and isn't premultiplied by A). The coefficients
+/// of the hash are inlined as constants. An error will be returned if the input is
+/// too short.
+pub fn thumb_hash_to_shader(
+    hash: &[u8],
+    language: ShaderLanguage,
+    name: &str,
+) -> Result<String, ()> {
+    let channels = Channels::read(hash)?;
+    let basis = Basis::new(&channels);
+    let (nx, ny) = (basis.nx(), basis.ny());
+    let (vec2, vec4, float, var) = match language {
+        ShaderLanguage::Wgsl => ("vec2f", "vec4f", "let", "var"),
+        ShaderLanguage::Glsl => ("vec2", "vec4", "float", "vec4"),
+    };
+    let mut code = String::new();
+    match language {
+        ShaderLanguage::Wgsl => writeln!(code, "fn {name}(uv: {vec2}) -> {vec4} {{"),
+        ShaderLanguage::Glsl => writeln!(code, "{vec4} {name}({vec2} uv) {{"),
+    }
+    .unwrap();
+
+    // Evaluate each cosine once with the angle going from 0 to π
+    for cx in 1..nx {
+        let f = float_literal(std::f32::consts::PI * cx as f32);
+        writeln!(code, "    {float} x{cx} = cos({f} * uv.x);").unwrap();
+    }
+    for cy in 1..ny {
+        let f = float_literal(std::f32::consts::PI * cy as f32);
+        writeln!(code, "    {float} y{cy} = cos({f} * uv.y);").unwrap();
+    }
+
+    // The basis is linear in each factor, so evaluating it with one factor set
+    // to 1 and the rest set to 0 gives the weight of that pair of factors
+    let mut fx = vec![0.0; nx];
+    let mut fy = vec![0.0; ny];
+    let dc = basis.lpqa_with_factors(&fx, &fy);
+    writeln!(code, "    {var} lpqa = {};", vec4_literal(vec4, dc)).unwrap();
+    for cy in 0..ny {
+        for cx in 0..nx {
+            if cx == 0 && cy == 0 {
+                continue;
+            }
+            fx[cx] = 1.0;
+            fy[cy] = 1.0;
+            let (l, p, q, a) = basis.lpqa_with_factors(&fx, &fy);
+            fx[cx] = 0.0;
+            fy[cy] = 0.0;
+            let weight = (l - dc.0, p - dc.1, q - dc.2, a - dc.3);
+            if weight == (0.0, 0.0, 0.0, 0.0) {
+                continue;
+            }
+            let factors = match (cx, cy) {
+                (_, 0) => format!("x{cx}"),
+                (0, _) => format!("y{cy}"),
+                _ => format!("x{cx} * y{cy}"),
+            };
+            let weight = vec4_literal(vec4, weight);
+            writeln!(code, "    lpqa += {weight} * {factors};").unwrap();
+        }
+    }
+
+    // Convert from LPQA to RGBA
+    writeln!(code, "    {float} b = lpqa.x - 2.0 / 3.0 * lpqa.y;").unwrap();
+    writeln!(code, "    {float} r = (3.0 * lpqa.x - b + lpqa.z) / 2.0;").unwrap();
+    writeln!(code, "    {float} g = r - lpqa.z;").unwrap();
+    writeln!(
+        code,
+        "    return clamp({vec4}(r, g, b, lpqa.w), {vec4}(0.0), {vec4}(1.0));"
+    )
+    .unwrap();
+    code.push_str("}\n");
+    Ok(code)
+}
+
+/// Formats a number so it parses as a float in both WGSL and GLSL.
+fn float_literal(value: f32) -> String {
+    // The debug format always has a "." or an exponent, unlike display
+    format!("{value:?}")
+}
+
+fn vec4_literal(vec4: &str, (l, p, q, a): (f32, f32, f32, f32)) -> String {
+    let (l, p, q, a) = (
+        float_literal(l),
+        float_literal(p),
+        float_literal(q),
+        float_literal(a),
+    );
+    format!("{vec4}({l}, {p}, {q}, {a})")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{rgba_to_thumb_hash, thumb_hash_to_rgba};
+    use std::collections::HashMap;
+
+    /// Evaluates the LPQA value of a generated shader at `uv`, which only has
+    /// to understand the statements that [`thumb_hash_to_shader`] writes.
+    fn evaluate(code: &str, uv: (f32, f32)) -> [f32; 4] {
+        let literal = |text: &str| -> f32 {
+            assert!(text.contains('.') || text.contains('e'), "{text}");
+            text.parse().unwrap()
+        };
+        let vec4 = |text: &str| -> [f32; 4] {
+            let (_, args) = text.trim_end_matches([')', ';']).split_once('(').unwrap();
+            let args: Vec<f32> = args.split(", ").map(literal).collect();
+            args.try_into().unwrap()
+        };
+        let mut factors = HashMap::new();
+        let mut lpqa = [0.0; 4];
+        for line in code.lines().map(str::trim) {
+            if let Some((name, value)) = line.split_once(" = cos(") {
+                let name = name.rsplit(' ').next().unwrap();
+                let (f, axis) = value.trim_end_matches(");").split_once(" * uv.").unwrap();
+                let t = if axis == "x" { uv.0 } else { uv.1 };
+                factors.insert(name.to_string(), (literal(f) * t).cos());
+            } else if let Some((_, value)) = line.split_once(" lpqa = ") {
+                lpqa = vec4(value);
+            } else if let Some(value) = line.strip_prefix("lpqa += ") {
+                let (weight, names) = value.trim_end_matches(';').split_once(") * ").unwrap();
+                let f: f32 = names.split(" * ").map(|name| factors[name]).product();
+                for (c, w) in lpqa.iter_mut().zip(vec4(weight)) {
+                    *c += w * f;
+                }
+            }
+        }
+        lpqa
+    }
+
+    #[test]
+    fn shaders_match_the_decoded_pixels() {
+        let mut rgba = Vec::new();
+        for y in 0..24 {
+            for x in 0..32 {
+                rgba.extend_from_slice(&[(x * 7) as u8, (y * 9) as u8, 200, 255 - x as u8 * 6]);
+            }
+        }
+        let hash = rgba_to_thumb_hash(32, 24, &rgba);
+        let (w, h, pixels) = thumb_hash_to_rgba(&hash).unwrap();
+        for language in [ShaderLanguage::Wgsl, ShaderLanguage::Glsl] {
+            let code = thumb_hash_to_shader(&hash, language, "placeholder").unwrap();
+            for (x, y) in [
+                (0, 0),
+                (w - 1, 0),
+                (w / 2, h / 3),
+                (3, h - 1),
+                (w - 1, h - 1),
+            ] {
+                let uv = ((x as f32 + 0.5) / w as f32, (y as f32 + 0.5) / h as f32);
+                let [l, p, q, a] = evaluate(&code, uv);
+
+                // The conversion at the end of the shader
+                let b = l - 2.0 / 3.0 * p;
+                let r = (3.0 * l - b + q) / 2.0;
+                let g = r - q;
+                for (i, c) in [r, g, b, a].into_iter().enumerate() {
+                    let expected = pixels[(x + y * w) * 4 + i] as f32 / 255.0;
+                    assert!((c.clamp(0.0, 1.0) - expected).abs() <= 1.0 / 255.0);
+                }
+            }
+        }
+        assert_eq!(
+            thumb_hash_to_shader(&[], ShaderLanguage::Wgsl, "f"),
+            Err(())
+        );
+    }
+}
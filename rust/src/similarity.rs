@@ -0,0 +1,513 @@
+use crate::{Basis, Channels};
+
+/// Derives a 64-bit perceptual fingerprint from a ThumbHash.
+///
+/// Each bit is the sign of one of the low-frequency terms of the hash, so
+/// visually similar images tend to have fingerprints that differ in only a few
+/// bits. The number of differing bits (e.g. `(a ^ b).count_ones()`) makes a
+/// cheap pre-filter before a more precise comparison. Fingerprints are stable
+/// across versions of this crate, so they can be stored in a database index.
+/// An error will be returned if the input is too short.
+pub fn fingerprint(hash: &[u8]) -> Result<u64, ()> {
+    let channels = Channels::read(hash)?;
+    let mut bits = 0;
+    let mut push = |bit: bool| bits = (bits << 1) | bit as u64;
+
+    // The low-frequency terms, in order of increasing frequency
+    for (channel, max_sum) in [(0, 8), (1, 2), (2, 2), (3, 2)] {
+        for sum in 1..=max_sum {
+            for cy in 0..=sum {
+                push(channels.term(channel, sum - cy, cy) > 0.0);
+            }
+        }
+    }
+
+    // The overall color and shape
+    push(channels.l_dc > 0.5);
+    push(channels.p_dc > 0.0);
+    push(channels.q_dc > 0.0);
+    push(channels.has_alpha);
+    push(channels.is_landscape);
+    Ok(bits)
+}
+
+/// An index of ThumbHashes for finding visually similar images.
+///
+/// This is a BK-tree over the [`fingerprint`] of each hash, where the distance
+/// between two hashes is the number of bits that differ between their
+/// fingerprints. Each hash is stored along with a value of your choosing, such
+/// as a database ID.
+#[derive(Clone, Debug)]
+pub struct ThumbHashIndex<T> {
+    nodes: Vec<IndexNode<T>>,
+    len: usize,
+}
+
+#[derive(Clone, Debug)]
+struct IndexNode<T> {
+    fingerprint: u64,
+    values: Vec<T>,
+    children: Vec<(u32, usize)>,
+}
+
+impl<T> Default for ThumbHashIndex<T> {
+    fn default() -> Self {
+        ThumbHashIndex {
+            nodes: Vec::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> ThumbHashIndex<T> {
+    /// Creates an empty index.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of values in the index.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the index contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds a hash to the index. An error will be returned if the hash is too
+    /// short.
+    pub fn insert(&mut self, hash: &[u8], value: T) -> Result<(), ()> {
+        self.insert_fingerprint(fingerprint(hash)?, value);
+        Ok(())
+    }
+
+    /// Adds a previously computed [`fingerprint`] to the index.
+    pub fn insert_fingerprint(&mut self, fingerprint: u64, value: T) {
+        self.len += 1;
+        let new_node = |value| IndexNode {
+            fingerprint,
+            values: vec![value],
+            children: Vec::new(),
+        };
+        if self.nodes.is_empty() {
+            self.nodes.push(new_node(value));
+            return;
+        }
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            let distance = (node.fingerprint ^ fingerprint).count_ones();
+            if distance == 0 {
+                self.nodes[index].values.push(value);
+                return;
+            }
+            match node.children.iter().find(|&&(d, _)| d == distance) {
+                Some(&(_, child)) => index = child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes[index].children.push((distance, child));
+                    self.nodes.push(new_node(value));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Returns every value whose hash is within `distance` of the given hash
+    /// along with its distance, sorted from closest to farthest. An error will
+    /// be returned if the hash is too short.
+    pub fn query_within(&self, hash: &[u8], distance: u32) -> Result<Vec<(u32, &T)>, ()> {
+        Ok(self.query_fingerprint_within(fingerprint(hash)?, distance))
+    }
+
+    /// Like [`ThumbHashIndex::query_within`] but takes a previously computed
+    /// [`fingerprint`].
+    pub fn query_fingerprint_within(&self, fingerprint: u64, distance: u32) -> Vec<(u32, &T)> {
+        let mut results = Vec::new();
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let d = (node.fingerprint ^ fingerprint).count_ones();
+            if d <= distance {
+                results.extend(node.values.iter().map(|value| (d, value)));
+            }
+
+            // The triangle inequality rules out children outside this range
+            for &(child_distance, child) in &node.children {
+                if child_distance + distance >= d && child_distance <= d + distance {
+                    stack.push(child);
+                }
+            }
+        }
+        results.sort_by_key(|&(d, _)| d);
+        results
+    }
+}
+
+/// Measures how different two ThumbHashes look.
+///
+/// This compares the decoded placeholders at a grid of points and returns the
+/// root-mean-square difference of their LPQA values, so 0 means the hashes are
+/// visually identical. Hashes of the same image at different resolutions are
+/// typically well under 0.05 apart. An error will be returned if either input
+/// is too short.
+pub fn thumb_hash_distance(hash_a: &[u8], hash_b: &[u8]) -> Result<f32, ()> {
+    let a = features(&Channels::read(hash_a)?);
+    let b = features(&Channels::read(hash_b)?);
+    Ok(feature_distance(&a, &b))
+}
+
+/// Returns true if two ThumbHashes are the same except for rounding.
+///
+/// The hashes must have the same format and layout, and every stored value
+/// (each DC term, scale, and AC term) must be within one quantization step of
+/// the other hash. This is for conformance tests and migrations between
+/// encoders, where the last bit of a value can legitimately differ. Use
+/// [`thumb_hash_distance`] to compare how hashes look instead. An error will be
+/// returned if either input is too short.
+pub fn approx_eq(hash_a: &[u8], hash_b: &[u8]) -> Result<bool, ()> {
+    let a = Channels::read(hash_a)?;
+    let b = Channels::read(hash_b)?;
+    if a.format != b.format
+        || a.has_alpha != b.has_alpha
+        || (a.lx, a.ly, a.is_landscape) != (b.lx, b.ly, b.is_landscape)
+    {
+        return Ok(false);
+    }
+    let (dc_steps, scale_steps) = a.format.steps();
+    fn channel(c: &Channels, i: usize) -> (f32, f32, &[u8]) {
+        match i {
+            0 => (c.l_dc, c.l_scale, &c.l_ac),
+            1 => (c.p_dc, c.p_scale, &c.p_ac),
+            2 => (c.q_dc, c.q_scale, &c.q_ac),
+            _ => (c.a_dc, c.a_scale, &c.a_ac),
+        }
+    }
+    for i in 0..4 {
+        let (a_dc, a_scale, a_ac) = channel(&a, i);
+        let (b_dc, b_scale, b_ac) = channel(&b, i);
+        // Leave some slack for the rounding of decoded values to f32
+        let close = |x: f32, y: f32, step: f32| (x - y).abs() <= step * 1.01;
+        if !close(a_dc, b_dc, dc_steps[i])
+            || !a_ac.is_empty() && !close(a_scale, b_scale, scale_steps[i])
+            || a_ac.len() != b_ac.len()
+            || a_ac.iter().zip(b_ac).any(|(&x, &y)| x.abs_diff(y) > 1)
+        {
+            return Ok(false);
+        }
+    }
+    Ok(
+        match (a.extensions.aspect_ratio, b.extensions.aspect_ratio) {
+            (Some(x), Some(y)) => x.abs_diff(y) <= 1,
+            (x, y) => x == y,
+        },
+    )
+}
+
+/// Groups hashes that look nearly identical, such as re-uploads of the same
+/// photo at different resolutions.
+///
+/// Two hashes end up in the same group if their [`thumb_hash_distance`] is at
+/// most `threshold` (or if they are both close to a third hash). Each group is
+/// a list of indices into `hashes` in increasing order, and hashes without any
+/// duplicates are left out. This compares every pair of hashes, so it's meant
+/// for batches of up to a few thousand hashes. An error will be returned if
+/// any input is too short.
+pub fn find_duplicates<H: AsRef<[u8]>>(
+    hashes: &[H],
+    threshold: f32,
+) -> Result<Vec<Vec<usize>>, ()> {
+    let features = hashes
+        .iter()
+        .map(|hash| Ok(features(&Channels::read(hash.as_ref())?)))
+        .collect::<Result<Vec<_>, ()>>()?;
+
+    // Merge groups using union-find
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+    for i in 0..features.len() {
+        for j in i + 1..features.len() {
+            if feature_distance(&features[i], &features[j]) <= threshold {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    // Gather the groups, which are already ordered by their smallest index
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut group_of_root = vec![usize::MAX; hashes.len()];
+    for i in 0..hashes.len() {
+        let r = root(&mut parents, i);
+        if group_of_root[r] == usize::MAX {
+            group_of_root[r] = groups.len();
+            groups.push(Vec::new());
+        }
+        groups[group_of_root[r]].push(i);
+    }
+    groups.retain(|group| group.len() > 1);
+    Ok(groups)
+}
+
+/// Groups hashes into `k` clusters of visually similar images using k-means.
+///
+/// Returns the cluster (from 0 to `k - 1`) that each hash belongs to. Images
+/// in the same cluster tend to share the same dominant colors and overall
+/// layout. The result is deterministic. If `k` is more than the number of
+/// distinct hashes (for example, more than `hashes.len()`), each distinct hash
+/// gets its own cluster and the remaining clusters are left empty. An error
+/// will be returned if `k` is 0 or any input is too short.
+pub fn cluster_thumb_hashes<H: AsRef<[u8]>>(hashes: &[H], k: usize) -> Result<Vec<usize>, ()> {
+    if k == 0 {
+        return Err(());
+    }
+    let features = hashes
+        .iter()
+        .map(|hash| Ok(features(&Channels::read(hash.as_ref())?)))
+        .collect::<Result<Vec<_>, ()>>()?;
+    Ok(kmeans(&features, k).0)
+}
+
+/// Clusters points using k-means, returning the cluster of each point and the
+/// center of each cluster (of which there are fewer than `k` if there are
+/// fewer than `k` points). Initialization picks each next center as the point
+/// farthest from the existing centers, which keeps the result deterministic.
+pub(crate) fn kmeans(points: &[Vec<f32>], k: usize) -> (Vec<usize>, Vec<Vec<f32>>) {
+    let mut assignments = vec![0; points.len()];
+    if points.is_empty() {
+        return (assignments, Vec::new());
+    }
+    let mut centers = vec![points[0].clone()];
+    while centers.len() < k.clamp(1, points.len()) {
+        let farthest = points
+            .iter()
+            .map(|p| {
+                centers
+                    .iter()
+                    .map(|c| feature_distance(p, c))
+                    .fold(f32::INFINITY, f32::min)
+            })
+            .enumerate()
+            .fold(
+                (0, -1.0),
+                |best, (i, d)| if d > best.1 { (i, d) } else { best },
+            )
+            .0;
+        centers.push(points[farthest].clone());
+    }
+
+    for iteration in 0..100 {
+        // Assign each point to its closest center
+        let mut changed = false;
+        for (i, p) in points.iter().enumerate() {
+            let closest = centers
+                .iter()
+                .map(|c| feature_distance(p, c))
+                .enumerate()
+                .fold(
+                    (0, f32::INFINITY),
+                    |best, (j, d)| if d < best.1 { (j, d) } else { best },
+                )
+                .0;
+            if closest != assignments[i] {
+                assignments[i] = closest;
+                changed = true;
+            }
+        }
+        if !changed && iteration > 0 {
+            break;
+        }
+
+        // Move each center to the mean of its points
+        for (j, center) in centers.iter_mut().enumerate() {
+            let mut count = 0;
+            let mut sum = vec![0.0; center.len()];
+            for (p, _) in points.iter().zip(&assignments).filter(|&(_, &a)| a == j) {
+                for (s, x) in sum.iter_mut().zip(p) {
+                    *s += x;
+                }
+                count += 1;
+            }
+            if count > 0 {
+                *center = sum.into_iter().map(|s| s / count as f32).collect();
+            }
+        }
+    }
+    (assignments, centers)
+}
+
+/// The number of points along each axis that [`features`] samples.
+const FEATURE_GRID: usize = 8;
+
+/// Samples the LPQA values of a hash on a grid that's independent of the
+/// hash's layout, giving a vector that can be compared across hashes.
+fn features(channels: &Channels) -> Vec<f32> {
+    Basis::new(channels)
+        .grid(FEATURE_GRID, FEATURE_GRID)
+        .into_iter()
+        .flat_map(|(l, p, q, a)| [l, p, q, a])
+        .collect()
+}
+
+fn feature_distance(a: &[f32], b: &[f32]) -> f32 {
+    let sum: f32 = a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum();
+    (sum / a.len() as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgba_to_thumb_hash;
+
+    fn solid([r, g, b]: [u8; 3]) -> Vec<u8> {
+        let rgba: Vec<u8> = (0..16 * 16).flat_map(|_| [r, g, b, 255]).collect();
+        rgba_to_thumb_hash(16, 16, &rgba)
+    }
+
+    fn gradient_rgba(w: usize, h: usize) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(w * h * 4);
+        for y in 0..h {
+            for x in 0..w {
+                rgba.extend_from_slice(&[(x * 255 / w) as u8, 100, (y * 255 / h) as u8, 255]);
+            }
+        }
+        rgba
+    }
+
+    fn gradient(w: usize, h: usize) -> Vec<u8> {
+        rgba_to_thumb_hash(w, h, &gradient_rgba(w, h))
+    }
+
+    #[test]
+    fn cluster_thumb_hashes_groups_similar_colors() {
+        let hashes = [
+            solid([250, 10, 10]),
+            solid([10, 10, 250]),
+            solid([240, 20, 20]),
+            solid([20, 20, 240]),
+        ];
+        let clusters = cluster_thumb_hashes(&hashes, 2).unwrap();
+        assert_eq!(clusters[0], clusters[2]);
+        assert_eq!(clusters[1], clusters[3]);
+        assert_ne!(clusters[0], clusters[1]);
+    }
+
+    #[test]
+    fn cluster_thumb_hashes_rejects_zero_clusters() {
+        assert_eq!(cluster_thumb_hashes(&[solid([1, 2, 3])], 0), Err(()));
+        assert_eq!(cluster_thumb_hashes::<Vec<u8>>(&[], 0), Err(()));
+    }
+
+    #[test]
+    fn cluster_thumb_hashes_allows_more_clusters_than_hashes() {
+        let hashes = [solid([250, 10, 10]), solid([10, 10, 250])];
+        let clusters = cluster_thumb_hashes(&hashes, 5).unwrap();
+        assert_ne!(clusters[0], clusters[1]);
+        assert!(clusters.iter().all(|&c| c < 5));
+        assert_eq!(cluster_thumb_hashes::<Vec<u8>>(&[], 3), Ok(vec![]));
+    }
+
+    #[test]
+    fn fingerprint_is_stable() {
+        assert_eq!(fingerprint(&gradient(32, 24)), Ok(0x3a6cae000006bc05));
+        assert_eq!(fingerprint(&solid([250, 10, 10])), Ok(0xc));
+        assert_eq!(fingerprint(&[]), Err(()));
+
+        // The same image at a different size is closer than a different image
+        let bits =
+            |a: &[u8], b: &[u8]| (fingerprint(a).unwrap() ^ fingerprint(b).unwrap()).count_ones();
+        let (small, large) = (gradient(32, 24), gradient(64, 48));
+        assert!(bits(&small, &large) < bits(&small, &solid([250, 10, 10])));
+    }
+
+    #[test]
+    fn index_finds_values_within_a_distance() {
+        let mut index = ThumbHashIndex::new();
+        assert!(index.is_empty());
+        let fingerprints = [0b0000, 0b0001, 0b0011, 0b1111, 0b0001];
+        for (i, &fingerprint) in fingerprints.iter().enumerate() {
+            index.insert_fingerprint(fingerprint, i);
+        }
+        assert_eq!(index.len(), 5);
+        assert_eq!(
+            index.query_fingerprint_within(0b0000, 1),
+            [(0, &0), (1, &1), (1, &4)]
+        );
+        let mut within_2 = index.query_fingerprint_within(0b0111, 2);
+        within_2.sort();
+        assert_eq!(within_2, [(1, &2), (1, &3), (2, &1), (2, &4)]);
+        assert_eq!(index.query_fingerprint_within(u64::MAX, 3), []);
+
+        // Hashes are indexed by their fingerprint
+        let hash = gradient(32, 24);
+        index.insert(&hash, 5).unwrap();
+        assert_eq!(index.query_within(&hash, 0), Ok(vec![(0, &5)]));
+        assert_eq!(index.insert(&[], 6), Err(()));
+        assert_eq!(index.len(), 6);
+    }
+
+    #[test]
+    fn find_duplicates_groups_nearby_hashes() {
+        let hashes = [
+            gradient(32, 24),
+            solid([250, 10, 10]),
+            gradient(64, 48),
+            solid([10, 10, 250]),
+            solid([244, 16, 12]),
+        ];
+        assert_eq!(
+            find_duplicates(&hashes, 0.05),
+            Ok(vec![vec![0, 2], vec![1, 4]])
+        );
+        assert_eq!(find_duplicates(&hashes, 0.0), Ok(vec![]));
+        assert_eq!(
+            find_duplicates(&hashes, 10.0),
+            Ok(vec![vec![0, 1, 2, 3, 4]])
+        );
+        assert_eq!(find_duplicates(&[&hashes[0][..], &[]], 0.05), Err(()));
+        assert!(thumb_hash_distance(&hashes[0], &hashes[2]).unwrap() < 0.05);
+        assert_eq!(thumb_hash_distance(&hashes[0], &hashes[0]), Ok(0.0));
+    }
+
+    #[test]
+    fn approx_eq_allows_one_step_of_rounding() {
+        let hash = gradient(32, 24);
+        assert_eq!(approx_eq(&hash, &hash), Ok(true));
+
+        // Change the low nibble of an AC term that has room to move both ways
+        let i = (5..hash.len())
+            .find(|&i| (2..=13).contains(&(hash[i] & 15)))
+            .unwrap();
+        let changed = |delta: i8| {
+            let mut changed = hash.clone();
+            changed[i] = changed[i].wrapping_add_signed(delta);
+            changed
+        };
+        assert_eq!(approx_eq(&hash, &changed(1)), Ok(true));
+        assert_eq!(approx_eq(&hash, &changed(-1)), Ok(true));
+        assert_eq!(approx_eq(&hash, &changed(2)), Ok(false));
+        assert_eq!(approx_eq(&changed(-2), &hash), Ok(false));
+    }
+
+    #[test]
+    fn approx_eq_rejects_different_layouts() {
+        let hash = gradient(32, 24);
+        assert_eq!(approx_eq(&hash, &gradient(24, 32)), Ok(false));
+        let extended = crate::rgba_to_thumb_hash_extended(32, 24, &gradient_rgba(32, 24));
+        assert_ne!(extended.len(), hash.len());
+        assert_eq!(approx_eq(&hash, &extended), Ok(false));
+        assert_eq!(approx_eq(&hash, &solid([10, 200, 10])), Ok(false));
+        assert_eq!(approx_eq(&hash, &[]), Err(()));
+        assert_eq!(approx_eq(&[1, 2], &hash), Err(()));
+    }
+}
@@ -0,0 +1,44 @@
+use crate::ThumbHash;
+use sqlx::encode::IsNull;
+use sqlx::error::BoxDynError;
+use sqlx::{Database, Decode, Encode, Type};
+
+// These store a ThumbHash in a binary column (e.g. "bytea" or "BLOB") the same
+// way as a `Vec<u8>`, and check that the bytes can be decoded when reading
+// them back. Only available with the `sqlx` feature.
+
+impl<DB: Database> Type<DB> for ThumbHash
+where
+    Vec<u8>: Type<DB>,
+{
+    fn type_info() -> DB::TypeInfo {
+        <Vec<u8> as Type<DB>>::type_info()
+    }
+
+    fn compatible(ty: &DB::TypeInfo) -> bool {
+        <Vec<u8> as Type<DB>>::compatible(ty)
+    }
+}
+
+impl<'q, DB: Database> Encode<'q, DB> for ThumbHash
+where
+    Vec<u8>: Encode<'q, DB>,
+{
+    fn encode_by_ref(&self, buf: &mut DB::ArgumentBuffer) -> Result<IsNull, BoxDynError> {
+        self.bytes.encode_by_ref(buf)
+    }
+
+    fn size_hint(&self) -> usize {
+        self.bytes.size_hint()
+    }
+}
+
+impl<'r, DB: Database> Decode<'r, DB> for ThumbHash
+where
+    Vec<u8>: Decode<'r, DB>,
+{
+    fn decode(value: DB::ValueRef<'r>) -> Result<Self, BoxDynError> {
+        let bytes = <Vec<u8> as Decode<DB>>::decode(value)?;
+        ThumbHash::try_from(bytes).map_err(|()| "invalid ThumbHash".into())
+    }
+}
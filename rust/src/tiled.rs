@@ -0,0 +1,208 @@
+use crate::{rgba_region_to_thumb_hash, Channels};
+
+/// A grid of ThumbHashes for an image that's too wide or tall for one.
+///
+/// A single hash can only represent a few features along each axis, so the
+/// hash of a 10:1 panorama is mostly one smear of color. This splits the image
+/// into tiles that are each roughly square, stores one hash per tile, and
+/// stitches the decoded tiles back into one placeholder.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TiledThumbHash {
+    columns: usize,
+    rows: usize,
+    tiles: Vec<Vec<u8>>,
+}
+
+impl TiledThumbHash {
+    /// Encodes an RGBA image to a grid of ThumbHashes, picking the number of
+    /// tiles from the aspect ratio so each tile is roughly square.
+    ///
+    /// * `w`: The width of the input image. Must be at least 1px, and each tile
+    ///   must be ≤100px wide.
+    /// * `h`: The height of the input image. Must be at least 1px, and each tile
+    ///   must be ≤100px tall.
+    /// * `rgba`: The pixels in the input image, row-by-row. Must have `w*h*4` elements.
+    pub fn from_rgba(w: usize, h: usize, rgba: &[u8]) -> TiledThumbHash {
+        let columns = (w as f32 / h.max(1) as f32).round().clamp(1.0, 255.0) as usize;
+        let rows = (h as f32 / w.max(1) as f32).round().clamp(1.0, 255.0) as usize;
+        TiledThumbHash::from_rgba_with_tiles(w, h, rgba, columns, rows)
+    }
+
+    /// Like [`TiledThumbHash::from_rgba`] but with the given number of tiles
+    /// across and down, which must each be from 1 to 255 and at most the width
+    /// and height of the image so that no tile is empty.
+    pub fn from_rgba_with_tiles(
+        w: usize,
+        h: usize,
+        rgba: &[u8],
+        columns: usize,
+        rows: usize,
+    ) -> TiledThumbHash {
+        assert!((1..=255).contains(&columns) && (1..=255).contains(&rows));
+        assert!(columns <= w && rows <= h);
+        let mut tiles = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            let (y0, y1) = (row * h / rows, (row + 1) * h / rows);
+            for column in 0..columns {
+                let (x0, x1) = (column * w / columns, (column + 1) * w / columns);
+                let tile = rgba_region_to_thumb_hash(w, h, rgba, (x0, y0, x1 - x0, y1 - y0));
+                tiles.push(tile.unwrap());
+            }
+        }
+        TiledThumbHash {
+            columns,
+            rows,
+            tiles,
+        }
+    }
+
+    /// Returns the number of tiles across.
+    pub fn columns(&self) -> usize {
+        self.columns
+    }
+
+    /// Returns the number of tiles down.
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    /// Returns the hash of the tile in the given column and row.
+    pub fn tile(&self, column: usize, row: usize) -> &[u8] {
+        assert!(column < self.columns && row < self.rows);
+        &self.tiles[column + row * self.columns]
+    }
+
+    /// Serializes the grid to bytes. This is the number of columns and rows
+    /// followed by each tile's hash (row-by-row) prefixed with its length.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.columns as u8, self.rows as u8];
+        for tile in &self.tiles {
+            bytes.push(tile.len() as u8);
+            bytes.extend_from_slice(tile);
+        }
+        bytes
+    }
+
+    /// Parses bytes from [`TiledThumbHash::to_bytes`]. An error will be
+    /// returned if the input is too short or any tile can't be decoded.
+    pub fn from_bytes(bytes: &[u8]) -> Result<TiledThumbHash, ()> {
+        let (&columns, &rows) = (bytes.first().ok_or(())?, bytes.get(1).ok_or(())?);
+        let (columns, rows) = (columns as usize, rows as usize);
+        if columns == 0 || rows == 0 {
+            return Err(());
+        }
+        let mut rest = &bytes[2..];
+        let mut tiles = Vec::with_capacity(columns * rows);
+        for _ in 0..columns * rows {
+            let (&len, tail) = rest.split_first().ok_or(())?;
+            let tile = tail.get(..len as usize).ok_or(())?;
+            Channels::read(tile)?;
+            tiles.push(tile.to_vec());
+            rest = &tail[len as usize..];
+        }
+        if !rest.is_empty() {
+            return Err(());
+        }
+        Ok(TiledThumbHash {
+            columns,
+            rows,
+            tiles,
+        })
+    }
+
+    /// Decodes the grid to one RGBA image by decoding every tile and placing
+    /// them next to each other. RGB is not premultiplied by A. Returns the
+    /// width, height, and pixels of the rendered placeholder image.
+    ///
+    /// Each tile is decoded at its own size. The tiles have to line up, so
+    /// each column is as wide as its widest tile and each row is as tall as its
+    /// tallest tile, and a tile that's narrower or shorter than that (which is
+    /// at most a few pixels for grids from [`TiledThumbHash::from_rgba`]) is
+    /// stretched to fill its cell.
+    pub fn to_rgba(&self) -> (usize, usize, Vec<u8>) {
+        let channels: Vec<Channels> = self
+            .tiles
+            .iter()
+            .map(|tile| Channels::read(tile).unwrap())
+            .collect();
+        let sizes: Vec<(usize, usize)> = channels.iter().map(|c| c.size(32)).collect();
+        let widths: Vec<usize> = (0..self.columns)
+            .map(|column| {
+                let column = sizes.iter().skip(column).step_by(self.columns);
+                column.map(|&(w, _)| w).max().unwrap()
+            })
+            .collect();
+        let heights: Vec<usize> = sizes
+            .chunks_exact(self.columns)
+            .map(|row| row.iter().map(|&(_, h)| h).max().unwrap())
+            .collect();
+        let (w, h) = (widths.iter().sum::<usize>(), heights.iter().sum::<usize>());
+        let mut rgba = vec![0; w * h * 4];
+        let mut y = 0;
+        for (row, &tile_h) in heights.iter().enumerate() {
+            let mut x = 0;
+            for (column, &tile_w) in widths.iter().enumerate() {
+                let channels = &channels[column + row * self.columns];
+                channels.render_to(&mut rgba, w * 4, (x, y, tile_w, tile_h));
+                x += tile_w;
+            }
+            y += tile_h;
+        }
+        (w, h, rgba)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::sample;
+    use crate::{rgba_to_thumb_hash, thumb_hash_to_rgba};
+
+    #[test]
+    fn from_rgba_picks_square_tiles() {
+        let grid = TiledThumbHash::from_rgba(300, 100, &sample(300, 100));
+        assert_eq!((grid.columns(), grid.rows()), (3, 1));
+        let grid = TiledThumbHash::from_rgba(50, 100, &sample(50, 100));
+        assert_eq!((grid.columns(), grid.rows()), (1, 2));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let grid = TiledThumbHash::from_rgba_with_tiles(200, 100, &sample(200, 100), 2, 2);
+        assert_eq!(
+            TiledThumbHash::from_bytes(&grid.to_bytes()),
+            Ok(grid.clone())
+        );
+        let bytes = grid.to_bytes();
+        assert_eq!(
+            TiledThumbHash::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(())
+        );
+        assert_eq!(
+            TiledThumbHash::from_bytes(&[bytes, vec![0]].concat()),
+            Err(())
+        );
+        assert_eq!(TiledThumbHash::from_bytes(&[0, 1]), Err(()));
+    }
+
+    #[test]
+    fn to_rgba_decodes_each_tile_at_its_own_size() {
+        let square = rgba_to_thumb_hash(40, 40, &sample(40, 40));
+        let portrait = rgba_to_thumb_hash(20, 40, &sample(20, 40));
+        let mut bytes = vec![2, 1];
+        for tile in [&square, &portrait] {
+            bytes.push(tile.len() as u8);
+            bytes.extend_from_slice(tile);
+        }
+        let grid = TiledThumbHash::from_bytes(&bytes).unwrap();
+        let (square_w, square_h, _) = thumb_hash_to_rgba(&square).unwrap();
+        let (portrait_w, portrait_h, expected) = thumb_hash_to_rgba(&portrait).unwrap();
+        assert_eq!(square_h, portrait_h);
+        let (w, h, rgba) = grid.to_rgba();
+        assert_eq!((w, h), (square_w + portrait_w, square_h));
+        for y in 0..h {
+            let row = &rgba[(y * w + square_w) * 4..(y * w + w) * 4];
+            assert_eq!(row, &expected[y * portrait_w * 4..(y + 1) * portrait_w * 4]);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{lerp, Channels};
+use std::time::Duration;
+
+/// A sequence of ThumbHashes for the frames of a video at different times.
+///
+/// This is useful for showing a preview while scrubbing through a video
+/// without loading the video itself. Asking for a time between two stored
+/// hashes blends them with [`lerp`], so the preview changes smoothly.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HashTimeline {
+    frames: Vec<(Duration, Vec<u8>)>,
+}
+
+impl HashTimeline {
+    /// Creates an empty timeline.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of hashes in the timeline.
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns true if the timeline contains no hashes.
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Returns the stored hashes and their times, in order of time.
+    pub fn frames(&self) -> impl Iterator<Item = (Duration, &[u8])> {
+        self.frames
+            .iter()
+            .map(|(time, hash)| (*time, hash.as_slice()))
+    }
+
+    /// Stores the hash of the frame at the given time, replacing any hash that
+    /// was already stored for that time. An error will be returned if the hash
+    /// can't be decoded.
+    pub fn insert(&mut self, time: Duration, hash: &[u8]) -> Result<(), ()> {
+        Channels::read(hash)?;
+        match self.frames.binary_search_by_key(&time, |&(t, _)| t) {
+            Ok(i) => self.frames[i].1 = hash.to_vec(),
+            Err(i) => self.frames.insert(i, (time, hash.to_vec())),
+        }
+        Ok(())
+    }
+
+    /// Returns the hash at the given time, blending the stored hashes on
+    /// either side of it. Times before the first hash or after the last one
+    /// return that hash. Returns `None` if the timeline is empty.
+    pub fn at(&self, time: Duration) -> Option<Vec<u8>> {
+        let i = match self.frames.binary_search_by_key(&time, |&(t, _)| t) {
+            Ok(i) => return Some(self.frames[i].1.clone()),
+            Err(i) => i,
+        };
+        if i == 0 || i == self.frames.len() {
+            return self
+                .frames
+                .get(i.saturating_sub(1))
+                .map(|(_, hash)| hash.clone());
+        }
+        let (before, hash_a) = &self.frames[i - 1];
+        let (after, hash_b) = &self.frames[i];
+        let t = (time - *before).as_secs_f32() / (*after - *before).as_secs_f32();
+        Some(lerp(hash_a, hash_b, t).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::solid_hash;
+
+    #[test]
+    fn at_blends_between_frames() {
+        let mut timeline = HashTimeline::new();
+        assert_eq!(timeline.at(Duration::ZERO), None);
+        let (a, b) = (solid_hash(0), solid_hash(240));
+        timeline.insert(Duration::from_secs(2), &b).unwrap();
+        timeline.insert(Duration::from_secs(1), &a).unwrap();
+        assert_eq!(timeline.len(), 2);
+        let times: Vec<Duration> = timeline.frames().map(|(time, _)| time).collect();
+        assert_eq!(times, [Duration::from_secs(1), Duration::from_secs(2)]);
+
+        assert_eq!(timeline.at(Duration::ZERO), Some(a.clone()));
+        assert_eq!(timeline.at(Duration::from_secs(1)), Some(a.clone()));
+        assert_eq!(timeline.at(Duration::from_secs(3)), Some(b.clone()));
+        assert_eq!(
+            timeline.at(Duration::from_millis(1250)),
+            Some(lerp(&a, &b, 0.25).unwrap())
+        );
+
+        // Inserting at an existing time replaces the hash
+        timeline.insert(Duration::from_secs(2), &a).unwrap();
+        assert_eq!(timeline.len(), 2);
+        assert_eq!(timeline.at(Duration::from_secs(2)), Some(a));
+        assert_eq!(timeline.insert(Duration::ZERO, &[]), Err(()));
+        assert_eq!(timeline.len(), 2);
+    }
+}
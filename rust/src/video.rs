@@ -0,0 +1,110 @@
+use crate::rgba_to_thumb_hash_with_stride;
+use ffmpeg::format::Pixel;
+use ffmpeg::frame::Video;
+use ffmpeg::software::scaling;
+use ffmpeg_next as ffmpeg;
+use std::path::Path;
+use std::time::Duration;
+
+/// Encodes the frame of a video at the given time to a ThumbHash, which is
+/// useful as a placeholder for the poster of a video. Only available with the
+/// `ffmpeg` feature.
+///
+/// This decodes the first frame at or after `timestamp`, or the last frame if
+/// the video is shorter than that. The frame is scaled down to fit within
+/// 100x100 before it's encoded. An error will be returned if the file can't be
+/// opened, has no video stream, or can't be decoded.
+pub fn thumb_hash_from_video<P: AsRef<Path>>(path: P, timestamp: Duration) -> Result<Vec<u8>, ()> {
+    ffmpeg::init().map_err(|_| ())?;
+    let mut input = ffmpeg::format::input(&path).map_err(|_| ())?;
+    let (index, time_base, parameters) = {
+        let stream = input.streams().best(ffmpeg::media::Type::Video).ok_or(())?;
+        (stream.index(), stream.time_base(), stream.parameters())
+    };
+    let context = ffmpeg::codec::context::Context::from_parameters(parameters).map_err(|_| ())?;
+    let mut decoder = context.decoder().video().map_err(|_| ())?;
+
+    // Seek to the closest keyframe before the timestamp, then decode from there
+    let seconds = timestamp.as_secs_f64();
+    let position = (seconds * ffmpeg::ffi::AV_TIME_BASE as f64) as i64;
+    if position > 0 && input.seek(position, ..position).is_err() {
+        input.seek(0, ..0).map_err(|_| ())?;
+    }
+    let target = (seconds * time_base.denominator() as f64 / time_base.numerator() as f64) as i64;
+    let mut frame = None;
+    let mut reached = false;
+    for (stream, packet) in input.packets() {
+        if stream.index() != index {
+            continue;
+        }
+        decoder.send_packet(&packet).map_err(|_| ())?;
+        reached = receive_frames(&mut decoder, target, &mut frame);
+        if reached {
+            break;
+        }
+    }
+    if !reached {
+        decoder.send_eof().map_err(|_| ())?;
+        receive_frames(&mut decoder, target, &mut frame);
+    }
+    let frame = frame.ok_or(())?;
+
+    // Scale the frame down to the size the encoder accepts
+    let (w, h) = (frame.width(), frame.height());
+    let scale = 100.0 / w.max(h) as f64;
+    let (sw, sh) = (
+        ((w as f64 * scale).round() as u32).clamp(1, 100),
+        ((h as f64 * scale).round() as u32).clamp(1, 100),
+    );
+    let mut scaler = scaling::Context::get(
+        frame.format(),
+        w,
+        h,
+        Pixel::RGBA,
+        sw,
+        sh,
+        scaling::Flags::AREA,
+    )
+    .map_err(|_| ())?;
+    let mut rgba = Video::empty();
+    scaler.run(&frame, &mut rgba).map_err(|_| ())?;
+    Ok(rgba_to_thumb_hash_with_stride(
+        sw as usize,
+        sh as usize,
+        rgba.data(0),
+        rgba.stride(0),
+    ))
+}
+
+/// Receives every frame the decoder has ready, keeping the latest one in
+/// `frame`. Returns true once a frame at or after `target` has been received.
+fn receive_frames(
+    decoder: &mut ffmpeg::decoder::Video,
+    target: i64,
+    frame: &mut Option<Video>,
+) -> bool {
+    let mut next = Video::empty();
+    while decoder.receive_frame(&mut next).is_ok() {
+        let reached = next.pts().is_none_or(|pts| pts >= target);
+        *frame = Some(std::mem::replace(&mut next, Video::empty()));
+        if reached {
+            return true;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_files_without_video() {
+        let timestamp = Duration::from_secs(1);
+        assert_eq!(
+            thumb_hash_from_video("does-not-exist.mp4", timestamp),
+            Err(())
+        );
+        assert_eq!(thumb_hash_from_video("Cargo.toml", timestamp), Err(()));
+    }
+}
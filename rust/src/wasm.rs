@@ -0,0 +1,56 @@
+use js_sys::{Object, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+// These match the API of the JavaScript implementation so this can be used as
+// a drop-in replacement for it. Only available with the `wasm` feature.
+
+/// Encodes an RGBA image to a ThumbHash. RGB should not be premultiplied by A.
+#[wasm_bindgen(js_name = rgbaToThumbHash)]
+pub fn rgba_to_thumb_hash(w: usize, h: usize, rgba: &[u8]) -> Result<Vec<u8>, JsError> {
+    crate::check_rgba(w, h, rgba).map_err(JsError::new)?;
+    Ok(crate::rgba_to_thumb_hash(w, h, rgba))
+}
+
+/// Decodes a ThumbHash to an RGBA image. RGB is not premultiplied by A.
+/// Returns an object with the width `w`, height `h`, and pixels `rgba`.
+#[wasm_bindgen(js_name = thumbHashToRGBA)]
+pub fn thumb_hash_to_rgba(hash: &[u8]) -> Result<Object, JsError> {
+    let (w, h, rgba) = crate::thumb_hash_to_rgba(hash).map_err(|_| invalid_hash())?;
+    object(&[
+        ("w", w.into()),
+        ("h", h.into()),
+        ("rgba", Uint8Array::from(rgba.as_slice()).into()),
+    ])
+}
+
+/// Extracts the average color from a ThumbHash. RGB is not premultiplied by
+/// A. Returns an object with `r`, `g`, `b`, and `a` values from 0 to 1.
+#[wasm_bindgen(js_name = thumbHashToAverageRGBA)]
+pub fn thumb_hash_to_average_rgba(hash: &[u8]) -> Result<Object, JsError> {
+    let (r, g, b, a) = crate::thumb_hash_to_average_rgba(hash).map_err(|_| invalid_hash())?;
+    object(&[
+        ("r", r.into()),
+        ("g", g.into()),
+        ("b", b.into()),
+        ("a", a.into()),
+    ])
+}
+
+/// Extracts the approximate aspect ratio of the original image.
+#[wasm_bindgen(js_name = thumbHashToApproximateAspectRatio)]
+pub fn thumb_hash_to_approximate_aspect_ratio(hash: &[u8]) -> Result<f32, JsError> {
+    crate::thumb_hash_to_approximate_aspect_ratio(hash).map_err(|_| invalid_hash())
+}
+
+fn invalid_hash() -> JsError {
+    JsError::new("Invalid ThumbHash")
+}
+
+fn object(properties: &[(&str, JsValue)]) -> Result<Object, JsError> {
+    let object = Object::new();
+    for (key, value) in properties {
+        Reflect::set(&object, &(*key).into(), value)
+            .map_err(|_| JsError::new("Failed to set a property"))?;
+    }
+    Ok(object)
+}
@@ -0,0 +1,134 @@
+use crate::{Channels, Format};
+
+/// How the Y, U, and V values of an image map to RGB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YuvColorSpace {
+    /// BT.601 with limited range (Y from 16 to 235), which is what
+    /// standard-definition video uses.
+    Bt601,
+
+    /// BT.709 with limited range (Y from 16 to 235), which is what HD video
+    /// uses.
+    Bt709,
+
+    /// BT.601 with full range (Y from 0 to 255), which is what JPEG and many
+    /// cameras use.
+    Jpeg,
+}
+
+impl YuvColorSpace {
+    /// Converts a pixel to RGB values from 0 to 1.
+    fn to_rgb(self, y: u8, u: u8, v: u8) -> [f32; 3] {
+        let (y, u, v) = (y as f32, u as f32 - 128.0, v as f32 - 128.0);
+        let (r, g, b) = match self {
+            YuvColorSpace::Bt601 => {
+                let y = 1.164 * (y - 16.0);
+                (y + 1.596 * v, y - 0.392 * u - 0.813 * v, y + 2.017 * u)
+            }
+            YuvColorSpace::Bt709 => {
+                let y = 1.164 * (y - 16.0);
+                (y + 1.793 * v, y - 0.213 * u - 0.533 * v, y + 2.112 * u)
+            }
+            YuvColorSpace::Jpeg => (y + 1.402 * v, y - 0.344 * u - 0.714 * v, y + 1.772 * u),
+        };
+        [r, g, b].map(|c| (c / 255.0).clamp(0.0, 1.0))
+    }
+}
+
+/// Encodes an image in planar YUV 4:2:0 format (also known as I420) to a
+/// ThumbHash, which is what most video decoders and cameras output. Each pixel
+/// is converted as it's read, without making an RGBA copy or rounding the
+/// colors to bytes.
+///
+/// * `w`: The width of the input image. Must be ≤100px.
+/// * `h`: The height of the input image. Must be ≤100px.
+/// * `y`: The luma plane, row-by-row. Must have `w*h` elements.
+/// * `u`: The blue chroma plane at half the width and height (rounded up).
+/// * `v`: The red chroma plane at half the width and height (rounded up).
+pub fn yuv420_to_thumb_hash(
+    w: usize,
+    h: usize,
+    y: &[u8],
+    u: &[u8],
+    v: &[u8],
+    color_space: YuvColorSpace,
+) -> Vec<u8> {
+    assert!(w <= 100 && h <= 100);
+    let cw = w.div_ceil(2);
+    assert_eq!(y.len(), w * h);
+    assert_eq!(u.len(), cw * h.div_ceil(2));
+    assert_eq!(v.len(), cw * h.div_ceil(2));
+    let pixels = || {
+        (0..h).flat_map(move |py| {
+            (0..w).map(move |px| {
+                let c = px / 2 + py / 2 * cw;
+                color_space.to_rgb(y[px + py * w], u[c], v[c])
+            })
+        })
+    };
+    Channels::from_pixels(w, h, pixels, Format::Standard).write()
+}
+
+/// Like [`yuv420_to_thumb_hash`] but for NV12, where the chroma values are
+/// interleaved in a single plane.
+///
+/// * `uv`: Alternating blue and red chroma values at half the width and height
+///   (rounded up).
+pub fn nv12_to_thumb_hash(
+    w: usize,
+    h: usize,
+    y: &[u8],
+    uv: &[u8],
+    color_space: YuvColorSpace,
+) -> Vec<u8> {
+    assert!(w <= 100 && h <= 100);
+    let cw = w.div_ceil(2);
+    assert_eq!(y.len(), w * h);
+    assert_eq!(uv.len(), cw * h.div_ceil(2) * 2);
+    let pixels = || {
+        (0..h).flat_map(move |py| {
+            (0..w).map(move |px| {
+                let c = (px / 2 + py / 2 * cw) * 2;
+                color_space.to_rgb(y[px + py * w], uv[c], uv[c + 1])
+            })
+        })
+    };
+    Channels::from_pixels(w, h, pixels, Format::Standard).write()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgba_to_thumb_hash;
+
+    #[test]
+    fn gray_matches_rgba() {
+        let (w, h) = (9usize, 7usize);
+        let y: Vec<u8> = (0..w * h).map(|i| (i * 4) as u8).collect();
+        let chroma = vec![128; w.div_ceil(2) * h.div_ceil(2)];
+        let rgba: Vec<u8> = y.iter().flat_map(|&y| [y, y, y, 255]).collect();
+        let hash = yuv420_to_thumb_hash(w, h, &y, &chroma, &chroma, YuvColorSpace::Jpeg);
+        assert_eq!(hash, rgba_to_thumb_hash(w, h, &rgba));
+    }
+
+    #[test]
+    fn nv12_matches_yuv420() {
+        let (w, h) = (9usize, 7usize);
+        let y: Vec<u8> = (0..w * h).map(|i| (i * 4) as u8).collect();
+        let u: Vec<u8> = (0..w.div_ceil(2) * h.div_ceil(2))
+            .map(|i| (i * 9) as u8)
+            .collect();
+        let v: Vec<u8> = u.iter().map(|&u| 255 - u).collect();
+        let uv: Vec<u8> = u.iter().zip(&v).flat_map(|(&u, &v)| [u, v]).collect();
+        for color_space in [
+            YuvColorSpace::Bt601,
+            YuvColorSpace::Bt709,
+            YuvColorSpace::Jpeg,
+        ] {
+            assert_eq!(
+                nv12_to_thumb_hash(w, h, &y, &uv, color_space),
+                yuv420_to_thumb_hash(w, h, &y, &u, &v, color_space),
+            );
+        }
+    }
+}